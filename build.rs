@@ -1,8 +1,12 @@
 use std::env;
 use std::fs;
 use std::path::Path;
+use std::process::Command;
 
 fn main() {
+    // 记录构建信息，供 bitcomm::buildinfo 通过 env! 读取
+    emit_build_info();
+
     // 获取当前工作目录
     let current_dir = env::current_dir().unwrap();
 
@@ -43,3 +47,54 @@ fn copy_dir(source: &Path, target: &Path) -> std::io::Result<()> {
     }
     Ok(())
 }
+
+/// 通过 cargo:rustc-env 输出构建信息
+fn emit_build_info() {
+    // rustc 版本
+    let rustc = rustc_version::version_meta()
+        .map(|meta| meta.short_version_string)
+        .unwrap_or_else(|_| "unknown".to_string());
+    println!("cargo:rustc-env=BITCOMM_RUSTC_VERSION={}", rustc);
+
+    // git 提交及工作区是否有未提交的修改
+    let commit = git(&["rev-parse", "--short=12", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"]).is_some_and(|status| !status.is_empty());
+    println!("cargo:rustc-env=BITCOMM_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=BITCOMM_GIT_DIRTY={}", dirty);
+
+    // 构建时间，设置了 SOURCE_DATE_EPOCH 时使用该值以便可重现构建
+    let timestamp = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .and_then(|epoch| chrono::DateTime::from_timestamp(epoch, 0))
+        .unwrap_or_else(chrono::Utc::now);
+    println!("cargo:rustc-env=BITCOMM_BUILD_TIMESTAMP={}", timestamp.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+
+    // 目标平台与编译模式
+    println!("cargo:rustc-env=BITCOMM_TARGET={}", env::var("TARGET").unwrap_or_default());
+    println!("cargo:rustc-env=BITCOMM_PROFILE={}", env::var("PROFILE").unwrap_or_default());
+
+    // 启用的 cargo features，cargo 以 CARGO_FEATURE_<NAME> 环境变量传入
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(|name| name.to_lowercase().replace('_', "-")))
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=BITCOMM_FEATURES={}", features.join(","));
+
+    // 提交或源码变化时重新生成；admin 目录变化时重新复制
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=admin");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
+
+/// 执行 git 命令，失败时返回 None (例如从源码包构建)
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
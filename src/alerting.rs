@@ -0,0 +1,624 @@
+// 告警通知
+//
+// 看门狗、服务状态、运行时卡顿等通过 fire / resolve 报告告警，按 key 去重：
+// 同一告警持续期间每 repeat_interval_secs 最多通知一次 (级别升高时立即通知)，
+// 条件消除时 resolve 发送一次恢复通知；所有通知合计每分钟不超过 max_per_minute 条。
+// 通知发送到 [[alert.sinks]] 中的每个通道：webhook (JSON + HMAC-SHA256 签名)、SMTP 邮件、本地脚本。
+// fire / resolve 可在任意线程 (包括 panic 中的线程) 调用，只把事件放入队列，由后台任务发送。
+
+use crate::config::{ AlertSection, AlertSink, ScriptSink, SmtpSink, SmtpTls, WebhookSink };
+use crate::{ metrics, supervisor };
+use ::metrics::counter;
+use hmac::{ Hmac, Mac };
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{ AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor };
+use serde::{ Deserialize, Serialize };
+use sha2::Sha256;
+use std::collections::{ BTreeMap, VecDeque };
+use std::error::Error;
+use std::process::Stdio;
+use std::sync::OnceLock;
+use std::time::{ Duration, Instant };
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{ self, UnboundedReceiver, UnboundedSender };
+use tracing::{ debug, info, warn };
+
+/// webhook 签名头，值为 sha256=<hex>，对请求体计算
+pub const SIGNATURE_HEADER: &str = "X-Bitcomm-Signature";
+
+// 速率限制的统计窗口
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// 告警级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertSeverity {
+    Warning,
+    Critical,
+}
+
+/// 告警状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+/// 一条告警通知，webhook 的请求体和脚本的 stdin 都是它的 JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    /// 去重键，例如 service-stuck:imserver
+    pub key: String,
+    pub status: AlertStatus,
+    pub severity: AlertSeverity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    pub summary: String,
+    pub detail: String,
+    pub host: String,
+    pub pid: u32,
+    /// 首次触发的 RFC 3339 时间
+    pub started_at: String,
+    /// 本次通知的 RFC 3339 时间
+    pub timestamp: String,
+}
+
+impl Alert {
+    /// 邮件标题
+    pub fn subject(&self) -> String {
+        let status = match self.status {
+            AlertStatus::Firing => "FIRING",
+            AlertStatus::Resolved => "RESOLVED",
+        };
+        format!("[bitcomm {}] {} {:?}: {}", self.host, status, self.severity, self.summary)
+    }
+
+    /// 邮件正文
+    pub fn text(&self) -> String {
+        format!(
+            "{}\n\nkey: {}\nservice: {}\nhost: {} (pid {})\nstarted: {}\nnotified: {}\n",
+            self.detail,
+            self.key,
+            self.service.as_deref().unwrap_or("-"),
+            self.host,
+            self.pid,
+            self.started_at,
+            self.timestamp
+        )
+    }
+}
+
+enum AlertEvent {
+    Fire {
+        key: String,
+        severity: AlertSeverity,
+        service: Option<String>,
+        summary: String,
+        detail: String,
+    },
+    Resolve {
+        key: String,
+        detail: String,
+    },
+}
+
+static QUEUE: OnceLock<UnboundedSender<AlertEvent>> = OnceLock::new();
+
+/// 启动后台发送任务，需在 tokio 运行时中调用；未启用或没有通道时 fire / resolve 不做任何事
+pub fn init(config: &AlertSection) {
+    if !config.enable || config.sinks.is_empty() {
+        return;
+    }
+    let (tx, rx) = mpsc::unbounded_channel();
+    if QUEUE.set(tx).is_err() {
+        return;
+    }
+    let dispatcher = Dispatcher {
+        config: config.clone(),
+        client: reqwest::Client::new(),
+        active: BTreeMap::new(),
+        sent: VecDeque::new(),
+    };
+    supervisor::spawn_named("alert/dispatch", dispatcher.run(rx));
+    info!(sinks = config.sinks.len(), "alerting enabled");
+}
+
+/// 报告告警，同一 key 持续触发时按 repeat_interval_secs 去重
+pub fn fire(
+    key: impl Into<String>,
+    severity: AlertSeverity,
+    service: Option<&str>,
+    summary: impl Into<String>,
+    detail: impl Into<String>
+) {
+    if let Some(queue) = QUEUE.get() {
+        let _ = queue.send(AlertEvent::Fire {
+            key: key.into(),
+            severity,
+            service: service.map(str::to_string),
+            summary: summary.into(),
+            detail: detail.into(),
+        });
+    }
+}
+
+/// 告警条件已消除；key 未在触发中时不做任何事
+pub fn resolve(key: impl Into<String>, detail: impl Into<String>) {
+    if let Some(queue) = QUEUE.get() {
+        let _ = queue.send(AlertEvent::Resolve { key: key.into(), detail: detail.into() });
+    }
+}
+
+struct ActiveAlert {
+    severity: AlertSeverity,
+    service: Option<String>,
+    summary: String,
+    started_at: String,
+    // 最近一次成功放行通知的时间，被速率限制丢弃时为 None
+    last_notified: Option<Instant>,
+}
+
+struct Dispatcher {
+    config: AlertSection,
+    client: reqwest::Client,
+    active: BTreeMap<String, ActiveAlert>,
+    // 最近一个窗口内发出的通知时间
+    sent: VecDeque<Instant>,
+}
+
+impl Dispatcher {
+    async fn run(mut self, mut rx: UnboundedReceiver<AlertEvent>) {
+        while let Some(event) = rx.recv().await {
+            match event {
+                AlertEvent::Fire { key, severity, service, summary, detail } => {
+                    self.on_fire(key, severity, service, summary, detail).await
+                }
+                AlertEvent::Resolve { key, detail } => self.on_resolve(key, detail).await,
+            }
+        }
+    }
+
+    async fn on_fire(&mut self, key: String, severity: AlertSeverity, service: Option<String>, summary: String, detail: String) {
+        let repeat = Duration::from_secs(self.config.repeat_interval_secs);
+        let entry = self.active.entry(key.clone()).or_insert_with(|| ActiveAlert {
+            severity,
+            service: None,
+            summary: String::new(),
+            started_at: chrono::Local::now().to_rfc3339(),
+            last_notified: None,
+        });
+        let escalated = severity > entry.severity;
+        entry.severity = severity;
+        entry.service = service;
+        entry.summary = summary;
+        if !escalated && entry.last_notified.is_some_and(|at| at.elapsed() < repeat) {
+            debug!(key = %key, "duplicate alert suppressed");
+            return;
+        }
+
+        let alert = new_alert(&key, AlertStatus::Firing, entry, detail);
+        let notified = self.notify(&alert).await;
+        if let Some(entry) = self.active.get_mut(&key) {
+            entry.last_notified = notified.then(Instant::now);
+        }
+    }
+
+    async fn on_resolve(&mut self, key: String, detail: String) {
+        let Some(entry) = self.active.remove(&key) else {
+            return;
+        };
+        // 触发通知没有发出去 (被速率限制) 时也不发恢复通知
+        if entry.last_notified.is_none() {
+            return;
+        }
+        let alert = new_alert(&key, AlertStatus::Resolved, &entry, detail);
+        self.notify(&alert).await;
+    }
+
+    // 受速率限制，返回通知是否放行
+    async fn notify(&mut self, alert: &Alert) -> bool {
+        let status = if alert.status == AlertStatus::Firing { "firing" } else { "resolved" };
+        let now = Instant::now();
+        while self.sent.front().is_some_and(|at| now.duration_since(*at) >= RATE_WINDOW) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.config.max_per_minute {
+            counter!(metrics::ALERT_NOTIFICATIONS, "status" => status, "result" => "rate_limited").increment(1);
+            warn!(key = %alert.key, "alert rate limit reached, notification dropped");
+            return false;
+        }
+        self.sent.push_back(now);
+        counter!(metrics::ALERT_NOTIFICATIONS, "status" => status, "result" => "sent").increment(1);
+        info!(key = %alert.key, status, "sending alert: {}", alert.summary);
+
+        for (sink, result) in deliver_all(&self.config, &self.client, alert).await {
+            if let Err(e) = result {
+                counter!(metrics::ALERT_SINK_ERRORS, "sink" => sink_kind(&sink)).increment(1);
+                // 不能再走告警，只记日志
+                warn!(key = %alert.key, sink = %sink.describe(), "failed to deliver alert: {}", e);
+            }
+        }
+        true
+    }
+}
+
+fn new_alert(key: &str, status: AlertStatus, entry: &ActiveAlert, detail: String) -> Alert {
+    Alert {
+        key: key.to_string(),
+        status,
+        severity: entry.severity,
+        service: entry.service.clone(),
+        summary: entry.summary.clone(),
+        detail,
+        host: hostname(),
+        pid: std::process::id(),
+        started_at: entry.started_at.clone(),
+        timestamp: chrono::Local::now().to_rfc3339(),
+    }
+}
+
+/// 向 [alert] 中的每个通道发送一条测试告警，返回各通道的结果
+pub async fn send_test(config: &AlertSection) -> Vec<(AlertSink, Result<(), Box<dyn Error + Send + Sync>>)> {
+    let now = chrono::Local::now().to_rfc3339();
+    let alert = Alert {
+        key: "test".to_string(),
+        status: AlertStatus::Firing,
+        severity: AlertSeverity::Warning,
+        service: None,
+        summary: "test alert".to_string(),
+        detail: "This is a test alert sent by `bitcomm alert test`.".to_string(),
+        host: hostname(),
+        pid: std::process::id(),
+        started_at: now.clone(),
+        timestamp: now,
+    };
+    deliver_all(config, &reqwest::Client::new(), &alert).await
+}
+
+async fn deliver_all(
+    config: &AlertSection,
+    client: &reqwest::Client,
+    alert: &Alert
+) -> Vec<(AlertSink, Result<(), Box<dyn Error + Send + Sync>>)> {
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let mut results = Vec::with_capacity(config.sinks.len());
+    for sink in &config.sinks {
+        let result = match tokio::time::timeout(timeout, deliver(sink, client, alert, timeout)).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("timed out after {:?}", timeout).into()),
+        };
+        results.push((sink.clone(), result));
+    }
+    results
+}
+
+async fn deliver(
+    sink: &AlertSink,
+    client: &reqwest::Client,
+    alert: &Alert,
+    timeout: Duration
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match sink {
+        AlertSink::Webhook(sink) => send_webhook(sink, client, alert).await,
+        AlertSink::Smtp(sink) => send_email(sink, alert, timeout).await,
+        AlertSink::Script(sink) => run_script(sink, alert).await,
+    }
+}
+
+async fn send_webhook(sink: &WebhookSink, client: &reqwest::Client, alert: &Alert) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let body = serde_json::to_vec(alert)?;
+    let mut request = client.post(&sink.url).header(reqwest::header::CONTENT_TYPE, "application/json");
+    if !sink.secret.is_empty() {
+        request = request.header(SIGNATURE_HEADER, format!("sha256={}", sign(&sink.secret, &body)));
+    }
+    request.body(body).send().await?.error_for_status()?;
+    Ok(())
+}
+
+/// 计算 webhook 请求体的 HMAC-SHA256 签名 (hex)，接收方用同一密钥校验
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+async fn send_email(sink: &SmtpSink, alert: &Alert, timeout: Duration) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let builder = match sink.tls {
+        SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&sink.host),
+        SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&sink.host)?,
+        SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&sink.host)?,
+    };
+    let mut builder = builder.port(sink.port).timeout(Some(timeout));
+    if !sink.username.is_empty() {
+        builder = builder.credentials(Credentials::new(sink.username.clone(), sink.password.clone()));
+    }
+
+    let mut message = Message::builder().from(sink.from.parse()?).subject(alert.subject());
+    for to in &sink.to {
+        message = message.to(to.parse()?);
+    }
+    let message = message.header(ContentType::TEXT_PLAIN).body(alert.text())?;
+    builder.build().send(message).await?;
+    Ok(())
+}
+
+async fn run_script(sink: &ScriptSink, alert: &Alert) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut child = tokio::process::Command
+        ::new(&sink.path)
+        .env("BITCOMM_ALERT_KEY", &alert.key)
+        .env("BITCOMM_ALERT_STATUS", serde_json::to_value(alert.status)?.as_str().unwrap_or_default())
+        .env("BITCOMM_ALERT_SEVERITY", serde_json::to_value(alert.severity)?.as_str().unwrap_or_default())
+        .env("BITCOMM_ALERT_SERVICE", alert.service.as_deref().unwrap_or_default())
+        .env("BITCOMM_ALERT_SUMMARY", &alert.summary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // 脚本可能不读 stdin，写入失败不影响结果
+        let _ = stdin.write_all(&serde_json::to_vec(alert)?).await;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(format!("{} {}", output.status, String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(())
+}
+
+fn sink_kind(sink: &AlertSink) -> &'static str {
+    match sink {
+        AlertSink::Webhook(_) => "webhook",
+        AlertSink::Smtp(_) => "smtp",
+        AlertSink::Script(_) => "script",
+    }
+}
+
+/// 本机主机名，取不到时为 unknown
+pub fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    // SAFETY: gethostname 最多写入 buffer.len() 字节
+    if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+        return "unknown".to_string();
+    }
+    let len = buffer.iter().position(|byte| *byte == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Bytes;
+    use axum::extract::State;
+    use axum::http::HeaderMap;
+    use axum::routing::post;
+    use axum::Router;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{ Path, PathBuf };
+    use tokio::io::{ AsyncBufReadExt, BufReader };
+    use tokio::net::TcpListener;
+
+    fn sample_alert() -> Alert {
+        Alert {
+            key: "service-stuck:imserver".to_string(),
+            status: AlertStatus::Firing,
+            severity: AlertSeverity::Critical,
+            service: Some("imserver".to_string()),
+            summary: "imserver stuck".to_string(),
+            detail: "no progress for 120s".to_string(),
+            host: "test-host".to_string(),
+            pid: 42,
+            started_at: "2026-01-01T00:00:00+00:00".to_string(),
+            timestamp: "2026-01-01T00:01:00+00:00".to_string(),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bitcomm-alert-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_script(dir: &Path, body: &str) -> PathBuf {
+        let path = dir.join("notify.sh");
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn webhook_body_is_signed_with_the_secret() {
+        let (tx, mut rx) = mpsc::unbounded_channel::<(HeaderMap, Bytes)>();
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(|State(tx): State<UnboundedSender<(HeaderMap, Bytes)>>, headers: HeaderMap, body: Bytes| async move {
+                    let _ = tx.send((headers, body));
+                })
+            )
+            .with_state(tx);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        let alert = sample_alert();
+        send_webhook(&WebhookSink { url: url.clone(), secret: "s3cret".to_string() }, &client, &alert).await.unwrap();
+        let (headers, body) = rx.recv().await.unwrap();
+        let signature = headers.get(SIGNATURE_HEADER).unwrap().to_str().unwrap();
+        assert_eq!(signature, format!("sha256={}", sign("s3cret", &body)));
+        assert_ne!(signature, format!("sha256={}", sign("other", &body)));
+        let received: Alert = serde_json::from_slice(&body).unwrap();
+        assert_eq!(received.key, alert.key);
+        assert_eq!(received.status, AlertStatus::Firing);
+
+        // 未配置密钥时不签名
+        send_webhook(&WebhookSink { url, secret: String::new() }, &client, &alert).await.unwrap();
+        let (headers, _) = rx.recv().await.unwrap();
+        assert!(headers.get(SIGNATURE_HEADER).is_none());
+    }
+
+    #[test]
+    fn signature_is_hex_hmac_sha256() {
+        // RFC 4231 测试用例 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    // 只支持一封邮件的 SMTP 服务器，返回收到的 MAIL / RCPT 命令和邮件内容
+    async fn smtp_stand_in(listener: TcpListener) -> (Vec<String>, String) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        write.write_all(b"220 stand-in ESMTP\r\n").await.unwrap();
+        let mut envelope = Vec::new();
+        let mut data = String::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let command = line.to_ascii_uppercase();
+            let reply: &[u8] = if command.starts_with("EHLO") || command.starts_with("HELO") {
+                b"250 stand-in\r\n"
+            } else if command.starts_with("MAIL") || command.starts_with("RCPT") {
+                envelope.push(line);
+                b"250 ok\r\n"
+            } else if command == "DATA" {
+                write.write_all(b"354 go ahead\r\n").await.unwrap();
+                while let Some(line) = lines.next_line().await.unwrap() {
+                    if line == "." {
+                        break;
+                    }
+                    data.push_str(&line);
+                    data.push('\n');
+                }
+                b"250 queued\r\n"
+            } else if command == "QUIT" {
+                write.write_all(b"221 bye\r\n").await.unwrap();
+                break;
+            } else {
+                b"250 ok\r\n"
+            };
+            write.write_all(reply).await.unwrap();
+        }
+        (envelope, data)
+    }
+
+    #[tokio::test]
+    async fn email_is_delivered_to_every_recipient() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(smtp_stand_in(listener));
+
+        let sink = SmtpSink {
+            host: "127.0.0.1".to_string(),
+            port,
+            tls: SmtpTls::None,
+            username: String::new(),
+            password: String::new(),
+            from: "bitcomm@example.com".to_string(),
+            to: vec!["ops@example.com".to_string(), "oncall@example.com".to_string()],
+        };
+        let alert = sample_alert();
+        send_email(&sink, &alert, Duration::from_secs(5)).await.unwrap();
+
+        let (envelope, data) = tokio::time::timeout(Duration::from_secs(5), server).await.unwrap().unwrap();
+        assert!(envelope[0].contains("<bitcomm@example.com>"), "{:?}", envelope);
+        assert!(envelope.iter().any(|line| line.contains("<ops@example.com>")));
+        assert!(envelope.iter().any(|line| line.contains("<oncall@example.com>")));
+        assert!(data.contains(&format!("Subject: {}", alert.subject())), "{}", data);
+        assert!(data.contains("no progress for 120s"), "{}", data);
+    }
+
+    #[tokio::test]
+    async fn script_receives_alert_on_stdin_and_environment() {
+        let dir = temp_dir("script");
+        let out = dir.join("out");
+        let script = write_script(
+            &dir,
+            &format!("echo \"$BITCOMM_ALERT_KEY $BITCOMM_ALERT_STATUS $BITCOMM_ALERT_SEVERITY $BITCOMM_ALERT_SERVICE\" > {0}\ncat >> {0}", out.display())
+        );
+        let alert = sample_alert();
+        run_script(&ScriptSink { path: script }, &alert).await.unwrap();
+
+        let output = std::fs::read_to_string(&out).unwrap();
+        let (env, stdin) = output.split_once('\n').unwrap();
+        assert_eq!(env, "service-stuck:imserver firing critical imserver");
+        let received: Alert = serde_json::from_str(stdin).unwrap();
+        assert_eq!(received.detail, alert.detail);
+
+        // 非零退出码和 stderr 作为错误返回
+        let failing = write_script(&dir, "echo boom >&2\nexit 3");
+        let error = run_script(&ScriptSink { path: failing }, &alert).await.unwrap_err().to_string();
+        assert!(error.contains('3') && error.contains("boom"), "{}", error);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // 没有通道的 Dispatcher，sent 的长度即放行的通知数
+    fn dispatcher(repeat_interval_secs: u64, max_per_minute: usize) -> Dispatcher {
+        Dispatcher {
+            config: AlertSection { repeat_interval_secs, max_per_minute, ..AlertSection::default() },
+            client: reqwest::Client::new(),
+            active: BTreeMap::new(),
+            sent: VecDeque::new(),
+        }
+    }
+
+    async fn fire(dispatcher: &mut Dispatcher, key: &str, severity: AlertSeverity) {
+        dispatcher.on_fire(key.to_string(), severity, None, format!("{} summary", key), String::new()).await;
+    }
+
+    fn ago(duration: Duration) -> Instant {
+        Instant::now().checked_sub(duration).unwrap()
+    }
+
+    #[tokio::test]
+    async fn duplicate_alerts_are_suppressed_until_repeat_or_escalation() {
+        let mut dispatcher = dispatcher(60, 100);
+        fire(&mut dispatcher, "a", AlertSeverity::Warning).await;
+        fire(&mut dispatcher, "a", AlertSeverity::Warning).await;
+        assert_eq!(dispatcher.sent.len(), 1);
+
+        // 级别升高立即通知，降回去不通知
+        fire(&mut dispatcher, "a", AlertSeverity::Critical).await;
+        fire(&mut dispatcher, "a", AlertSeverity::Warning).await;
+        assert_eq!(dispatcher.sent.len(), 2);
+
+        // 超过重复间隔后再次通知
+        dispatcher.active.get_mut("a").unwrap().last_notified = Some(ago(Duration::from_secs(61)));
+        fire(&mut dispatcher, "a", AlertSeverity::Warning).await;
+        assert_eq!(dispatcher.sent.len(), 3);
+
+        // 恢复通知只发一次，之后的 resolve 不做任何事
+        dispatcher.on_resolve("a".to_string(), String::new()).await;
+        dispatcher.on_resolve("a".to_string(), String::new()).await;
+        dispatcher.on_resolve("unknown".to_string(), String::new()).await;
+        assert_eq!(dispatcher.sent.len(), 4);
+        assert!(dispatcher.active.is_empty());
+    }
+
+    #[tokio::test]
+    async fn notifications_are_rate_limited_per_minute() {
+        let mut dispatcher = dispatcher(60, 2);
+        fire(&mut dispatcher, "a", AlertSeverity::Warning).await;
+        fire(&mut dispatcher, "b", AlertSeverity::Warning).await;
+        fire(&mut dispatcher, "c", AlertSeverity::Warning).await;
+        assert_eq!(dispatcher.sent.len(), 2);
+        assert!(dispatcher.active["c"].last_notified.is_none());
+
+        // 触发通知被丢弃的告警不发恢复通知
+        dispatcher.on_resolve("c".to_string(), String::new()).await;
+        assert_eq!(dispatcher.sent.len(), 2);
+
+        // 窗口滑过后恢复放行，之前被丢弃的告警再次触发时立即通知
+        for at in dispatcher.sent.iter_mut() {
+            *at = ago(RATE_WINDOW);
+        }
+        fire(&mut dispatcher, "c", AlertSeverity::Warning).await;
+        dispatcher.on_resolve("a".to_string(), String::new()).await;
+        assert_eq!(dispatcher.sent.len(), 2);
+        assert!(dispatcher.active["c"].last_notified.is_some());
+        assert!(!dispatcher.active.contains_key("a"));
+    }
+}
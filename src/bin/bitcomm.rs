@@ -2,38 +2,50 @@
 // SPDX-License-Identifier: Apache-2.0
 
 // 导入相关模块和库
+use bitcomm::cli::{ AlertCommand, BitcommCli, BitcommCommand, ConfigCommand, ProfileCommand };
+use bitcomm::config::{ self, BitcommConfig };
+use bitcomm::control::{ self, ControlRequest };
+use bitcomm::journal::{ EventKind, JournalEvent, JournalQuery };
+use bitcomm::{ alerting, buildinfo, crashreport, devcert, health, imbridge, imtcp, imws, journal, logsink, metrics, opsserver, slogbridge, stall, supervise, supervisor, systemd, telemetry, tls, top, watchdog };
 use btcmnetwork::{ imserver, mqserver, wdserver };
 use btcmweb::webserver;
 use colored::Colorize;
 use std::{error::Error, process};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use std::sync::mpsc::channel;
 use ctrlc;
-use btcmtools::BitcommOpt;
+use structopt::StructOpt;
 // use slog::info;
 use tokio::signal;
-use tracing::info;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing::{ info, info_span, Instrument };
 
 
 /// 主函数，程序入口
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    // 解析命令行参数
+    let opt = BitcommCli::from_args();
 
-    init_tracing();
-    print_logo();
-    // let version: &'static str = env!("CARGO_PKG_VERSION");
-    // info!("bitcomm version = {}", env!("CARGO_PKG_VERSION"));
+    switch_command(opt).await
+}
 
-    return start_server().await;
-    // 解析命令行参数
-    // let opt = BitcommOpt::from_args();
-    //
-    // switch_command(opt).await
+/// 是否输出启动 logo：stdout 是终端、未指定 --no-banner 且 stdout 不是 JSON 日志
+fn show_banner(no_banner: bool, config: &BitcommConfig) -> bool {
+    !no_banner && std::io::stdout().is_terminal() && !logsink::stdout_is_json(&config.log.sinks)
 }
 
 fn print_logo() {
-    // 在程序中硬编码 Rust 编译器的版本号
-    let rustc_version = "rustc 1.76.0 (07dca489a 2024-02-04)";//env!("RUSTC_VERSION");
+    // 遵循 NO_COLOR 约定
+    if !logsink::stdout_color_enabled() {
+        colored::control::set_override(false);
+    }
+
+    // 构建信息由 build.rs 在编译时写入
+    let info = buildinfo::build_info();
+    let version = format!("{} ({})", info.version, info.commit());
     println!("");
     println!("{}","                              ( * )".blue());
     println!("{}","                            /   |   \\ ".blue()); 
@@ -47,9 +59,9 @@ fn print_logo() {
     println!("{}","        |   /   |   \\   |   /   |   \\   |   /   |   \\   | ".blue());
     println!("{}","      ( * ) ----|---- ( * ) ----|---- ( * ) ----|---- ( * )  ".blue());
     println!("{}{}{}","            \\   |   /   |   \\   |   /   |   \\   |   /".blue(),"         Welcome to Bitcomm! PID = ".red(),process::id().to_string().yellow());
-    println!("{}{}{}","              ( * ) ----|---- ( * ) ----|---- ( * )".blue(),"           bitcomm version ".red(), env!("CARGO_PKG_VERSION").yellow());
+    println!("{}{}{}","              ( * ) ----|---- ( * ) ----|---- ( * )".blue(),"           bitcomm version ".red(), version.yellow());
     println!("{}{}","            /   |   \\   |   /   |   \\   |   /   |   \\".blue(),"         Http2/3,Quic,Redis,...".red());
-    println!("{}{}{}","      ( * ) ----|---- ( * ) ----|---- ( * ) ----|---- ( * )".blue(),"   Rustc version: ".red(),rustc_version.yellow());//      rust version ",rustc_version::version_meta().unwrap().semver_string());
+    println!("{}{}{}","      ( * ) ----|---- ( * ) ----|---- ( * ) ----|---- ( * )".blue(),"   Rustc version: ".red(),info.rustc.yellow());
     println!("{}","        |   \\   |   /   |   \\   |   /   |   \\   |   /   |".blue());
     println!("{}","        |---- ( * ) ----|---- ( * ) ----|---- ( * ) ----|".blue());
     println!("{}","        |   /   |   \\   |   /   |   \\   |   /   |   \\   | ".blue());
@@ -64,14 +76,6 @@ fn print_logo() {
     println!("{}","                   decentralized communication".green());
     println!("");
 }
-fn init_tracing() {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| "info,jwt_authorizer=debug,tower_http=debug".into()),
-        ))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-}
 /// 初始化 Citric 系统，设置 Ctrl-C 信号处理
 fn _init_citric_system() {
     let (tx, rx) = channel();
@@ -88,15 +92,135 @@ fn _init_citric_system() {
 }
 
 /// 异步函数，根据命令行参数执行相应操作
-#[allow(dead_code)]
-async fn switch_command(cmdopt: BitcommOpt) -> Result<(), Box<dyn Error>> {
-    match cmdopt {
-        BitcommOpt::StartServer => {
-            return start_server().await;
+async fn switch_command(cmdopt: BitcommCli) -> Result<(), Box<dyn Error>> {
+    // 缺省子命令时启动服务器
+    match cmdopt.command.unwrap_or(BitcommCommand::Start) {
+        BitcommCommand::Start => {
+            let config = BitcommConfig::load(&cmdopt.config)?;
+            telemetry::init_tracing(&config)?;
+            journal::init(&config.journal)?;
+            crashreport::install(&config);
+            if show_banner(cmdopt.no_banner, &config) {
+                print_logo();
+            }
+            return start_server(config).await;
         }
-        BitcommOpt::StopServer => {
+        BitcommCommand::Stop => {
+            telemetry::init_tracing(&BitcommConfig::default())?;
             stop_server();
         }
+        BitcommCommand::Supervise => {
+            let config = BitcommConfig::load(&cmdopt.config)?;
+            // 父进程只输出到 stdout，文件日志由子进程写入
+            telemetry::init_tracing(&BitcommConfig::default())?;
+            supervise::run(&cmdopt.config, cmdopt.no_banner, &config).await?;
+        }
+        BitcommCommand::Config(ConfigCommand::Schema { output }) => {
+            write_config_schema(output)?;
+        }
+        BitcommCommand::Version { json } => {
+            print_version(json)?;
+        }
+        BitcommCommand::LogLevel { filter, ttl } => {
+            let config = BitcommConfig::load(&cmdopt.config)?;
+            let request = match filter {
+                Some(filter) => ControlRequest::SetLogLevel { filter, ttl_secs: ttl },
+                None => ControlRequest::GetLogLevel,
+            };
+            let response = control::send_request(&config.control.path, &request).await?;
+            println!("{}", serde_json::to_string_pretty(&response)?);
+        }
+        BitcommCommand::Top => {
+            let config = BitcommConfig::load(&cmdopt.config)?;
+            top::run_top(&config.control.path).await?;
+        }
+        BitcommCommand::Profile(command) => {
+            let config = BitcommConfig::load(&cmdopt.config)?;
+            let request = match command {
+                ProfileCommand::Cpu { seconds, format, output } => ControlRequest::CpuProfile { seconds, format, output },
+                ProfileCommand::Heap => ControlRequest::HeapStats,
+            };
+            let response = control::send_request(&config.control.path, &request).await?;
+            println!("{}", serde_json::to_string_pretty(&response)?);
+        }
+        BitcommCommand::Journal { limit, service } => {
+            let config = BitcommConfig::load(&cmdopt.config)?;
+            let request = ControlRequest::Journal(JournalQuery { limit, service, kind: None });
+            let response = control::send_request(&config.control.path, &request).await?;
+            if !response.ok {
+                return Err(response.error.unwrap_or_default().into());
+            }
+            let events: Vec<JournalEvent> = serde_json::from_value(response.data["events"].clone())?;
+            for event in events {
+                println!(
+                    "{} {:<17} {:<10} {}",
+                    event.timestamp,
+                    serde_json::to_value(event.kind)?.as_str().unwrap_or_default(),
+                    event.service.unwrap_or_else(|| "-".to_string()),
+                    event.detail
+                );
+            }
+        }
+        BitcommCommand::Alert(AlertCommand::Test) => {
+            let config = BitcommConfig::load(&cmdopt.config)?;
+            if config.alert.sinks.is_empty() {
+                return Err("no [[alert.sinks]] configured".into());
+            }
+            let mut failed = 0;
+            for (sink, result) in alerting::send_test(&config.alert).await {
+                match result {
+                    Ok(()) => println!("{} {}", "ok    ".green(), sink.describe()),
+                    Err(e) => {
+                        failed += 1;
+                        println!("{} {}: {}", "failed".red(), sink.describe(), e);
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(format!("{} alert sink(s) failed", failed).into());
+            }
+        }
+        BitcommCommand::GenCert { dir, sans, profile, days, new_ca } => {
+            // 第一次搭建开发环境时可能还没有配置文件
+            let config = if cmdopt.config.exists() { BitcommConfig::load(&cmdopt.config)? } else { BitcommConfig::default() };
+            let options = devcert::GenCertOptions { dir, sans, profile, template: cmdopt.config.clone(), days, new_ca };
+            let output = devcert::generate(&options, &config)?;
+            println!("{:<10} {}{}", "ca", output.ca_path.display(), if output.ca_reused { " (existing)" } else { "" });
+            for cert in &output.certs {
+                println!("{:<10} {} {}", cert.server, cert.cert_path.display(), cert.names.join(","));
+            }
+            println!(
+                "{:<10} {}{} [imserver] cert_path / key_path updated",
+                "profile",
+                options.profile.display(),
+                if output.profile_created { " (created)" } else { "" }
+            );
+            println!("clients should trust {}; start with: bitcomm -c {} start", output.ca_path.display(), options.profile.display());
+        }
+    }
+    Ok(())
+}
+
+/// 输出版本与构建信息
+fn print_version(json: bool) -> Result<(), Box<dyn Error>> {
+    let info = buildinfo::build_info();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else {
+        println!("bitcomm {} ({} {})", info.version, info.commit(), info.build_timestamp);
+        println!("{}", info.rustc);
+        println!("target: {} ({})", info.target, info.profile);
+        println!("features: {}", if info.features.is_empty() { "none".to_string() } else { info.features.join(",") });
+    }
+    Ok(())
+}
+
+/// 输出配置文件的 JSON Schema 到文件或 stdout
+fn write_config_schema(output: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let schema = config::config_schema_json()?;
+    match output {
+        Some(path) => std::fs::write(path, schema + "\n")?,
+        None => println!("{}", schema),
     }
     Ok(())
 }
@@ -113,41 +237,169 @@ fn stop_server() {
 }
 
 /// 启动服务器，包括获取 MQ Server、IM Server、Web Server 和 WD Server 异步任务的句柄
-async fn start_server() -> Result<(), Box<dyn Error>> {
-    // 写入 PID
-    btcmtools::pid::save_pid();
-    
-    // let version = rustc_version::version_meta().unwrap();
-    
-    // info!("Rustc version: {}", rustc_version::version_meta().unwrap().short_version_string);
+async fn start_server(config: BitcommConfig) -> Result<(), Box<dyn Error>> {
+    // 写入 PID；受监管时 PID 文件记录的是父进程
+    if !supervise::is_supervised() {
+        btcmtools::pid::save_pid();
+    }
+
     // 输出日志
     info!("start server...");
 
+    // 无论是否输出 logo，都记录一条结构化的启动信息
+    log_startup_record(&config);
+    journal::record(EventKind::Started, None, format!("bitcomm {} pid {}", buildinfo::build_info().version, process::id()));
+
+    // 列出上次运行留下的、尚未上报的崩溃报告
+    crashreport::report_pending(&config);
+
+    // 安装指标注册表并开始采集 tokio 运行时统计
+    metrics::init_metrics()?;
+    metrics::spawn_runtime_collector(Duration::from_secs(config.opsserver.runtime_interval));
+
+    // 告警通知
+    alerting::init(&config.alert);
+
+    // 加载 IM 服务器证书并监视文件变化
+    tls::init(&config.imserver)?;
+    imws::init(&config.webserver.websocket)?;
+
+    // TCP+TLS 备用传输和 WebSocket 网关的连接转接到本机 QUIC 端点
+    if config.imserver.tcp_fallback.enable || config.webserver.websocket.enable {
+        let bridge = Arc::new(imbridge::Bridge::new(&config.imserver)?);
+        if config.imserver.tcp_fallback.enable {
+            imtcp::set_handler(imbridge::tcp_handler(bridge.clone()));
+        }
+        if config.webserver.websocket.enable {
+            imws::set_handler(imbridge::ws_handler(bridge));
+        }
+    }
+
+    // 检测阻塞工作线程的任务
+    stall::spawn_stall_detector(config.stall.clone())?;
+
+    // SIGUSR2 切换 debug 日志
+    telemetry::spawn_debug_toggle()?;
+
+    // SIGINT / SIGTERM 记入事件日志
+    spawn_signal_recorder()?;
+
+    // 登记 socket 激活传入的 socket，在 systemd 下发送就绪、状态和看门狗通知
+    systemd::init(&config)?;
+
     // 获取 MQ Server 异步任务句柄
     let mqserver_handle = get_mqserver_handle();
 
     // 获取 IM Server 异步任务句柄
     let imserver_handle = get_imserver_handle();
 
+    // 获取 IM TCP+TLS 备用传输异步任务句柄
+    let imtcpserver_handle = get_imtcpserver_handle(config.imserver.clone());
+
     // 获取 Web Server 异步任务句柄
     let webserver_handle = get_webserver_handle();
 
+    // 获取 IM WebSocket 网关异步任务句柄
+    let imwsserver_handle = get_imwsserver_handle(config.webserver.websocket.clone());
+
     // 获取 WD Server 异步任务句柄
-    let wdserver_handle = get_wdserver_handle();
+    let wdserver_handle = get_wdserver_handle(Arc::new(config.clone()));
 
-    // 等待四个服务执行完毕
-    tokio::try_join!(mqserver_handle, imserver_handle, webserver_handle, wdserver_handle)?;
+    // 获取 Ops Server 异步任务句柄
+    let opsserver_handle = get_opsserver_handle(config.opsserver.clone());
 
+    // 获取控制 socket 异步任务句柄
+    let ctlserver_handle = get_ctlserver_handle(config.control.clone());
+
+    // 获取健康检查异步任务句柄
+    let hcserver_handle = get_hcserver_handle(Arc::new(config.clone()));
+
+    // 等待所有服务执行完毕
+    tokio::try_join!(
+        mqserver_handle,
+        imserver_handle,
+        imtcpserver_handle,
+        webserver_handle,
+        imwsserver_handle,
+        wdserver_handle,
+        opsserver_handle,
+        ctlserver_handle,
+        hcserver_handle
+    )?;
+
+    // 导出剩余的链路追踪数据
+    telemetry::shutdown_tracing();
+
+    Ok(())
+}
+
+/// 把收到的 SIGINT / SIGTERM 记入事件日志并通知 systemd，各服务任务各自监听信号并退出
+fn spawn_signal_recorder() -> Result<(), Box<dyn Error>> {
+    let mut sig_int = signal::unix::signal(signal::unix::SignalKind::interrupt())?;
+    let mut sig_term = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+    supervisor::spawn_named("journal/signals", async move {
+        loop {
+            let name = tokio::select! {
+                _ = sig_int.recv() => "SIGINT",
+                _ = sig_term.recv() => "SIGTERM",
+            };
+            journal::record(EventKind::SignalReceived, None, name);
+            systemd::notify_stopping();
+        }
+    });
     Ok(())
 }
 
+/// 输出一行结构化的启动记录：版本、PID、启用的服务及监听地址
+fn log_startup_record(config: &BitcommConfig) {
+    let info = buildinfo::build_info();
+
+    let mut services = vec!["mqserver", "imserver", "webserver", "wdserver"];
+    let mut listeners = vec![
+        format!("imserver=udp://{}:{}", config.imserver.ip, config.imserver.port),
+        format!("webserver=tcp://{}:{}", config.webserver.ip, config.webserver.port),
+    ];
+    if config.imserver.tcp_fallback.enable {
+        services.push("imtcpserver");
+        listeners.push(format!("imtcpserver=tcp://{}", imtcp::listen_addr(&config.imserver)));
+    }
+    if config.webserver.websocket.enable {
+        let websocket = &config.webserver.websocket;
+        services.push("imwsserver");
+        listeners.push(format!("imwsserver=ws://{}:{}{}", websocket.ip, websocket.port, websocket.path));
+    }
+    if config.opsserver.enable {
+        services.push("opsserver");
+        listeners.push(format!("opsserver=tcp://{}:{}", config.opsserver.ip, config.opsserver.port));
+    }
+    if config.health.enable {
+        services.push("hcserver");
+        listeners.push(format!("hcserver=tcp://{}:{}", config.health.ip, config.health.port));
+    }
+    if config.control.enable {
+        services.push("ctlserver");
+        listeners.push(format!("ctlserver=unix://{}", config.control.path.display()));
+    }
+
+    // 与依赖库相同，经 slog 桥接进入 tracing
+    slog::info!(
+        slogbridge::LOGGER,
+        "bitcomm started";
+        "version" => info.version,
+        "commit" => info.commit(),
+        "pid" => process::id(),
+        "services" => services.join(","),
+        "listeners" => listeners.join(",")
+    );
+}
+
 /// 获取 Watch Dog Server 异步任务句柄
-fn get_wdserver_handle() -> tokio::task::JoinHandle<()> {
+fn get_wdserver_handle(config: Arc<BitcommConfig>) -> tokio::task::JoinHandle<()> {
     let wdserver_handle = {
         let mut sig_int = signal::unix::signal(signal::unix::SignalKind::interrupt()).unwrap();
         let mut sig_term = signal::unix::signal(signal::unix::SignalKind::terminate()).unwrap();
 
-        tokio::spawn(async move {
+        supervisor::spawn_named("wdserver", async move {
             tokio::select! {
                 _ = async {
                     // 等待中断信号
@@ -161,29 +413,137 @@ fn get_wdserver_handle() -> tokio::task::JoinHandle<()> {
                 } => {
                     // println!("Received SIGTERM, shutting down...");
                 }
-                _ = async {
+                _ = supervisor::run_restartable("wdserver", || async {
                     info!("Watch Dog Server starting...");
-                    // 启动 Watch Dog 异步任务
-                    wdserver::start_watch_dog_server().await.expect("wdserver error!");
+                    let _up = supervisor::ServiceGuard::new("wdserver");
+                    // 启动 Watch Dog 异步任务，同时巡检各服务
+                    tokio::select! {
+                        result = wdserver::start_watch_dog_server() => result.expect("wdserver error!"),
+                        _ = watchdog::run_watchdog(config.clone()) => {}
+                    }
                     // 
-                } => {
+                }) => {
                     // println!("Received connection, shutting down...");
                 }
             }
 
             info!("Received SIGINT/SIGTERM, Watch Dog server shutting down...");
-        })
+        }.instrument(info_span!("service", service = "wdserver")))
     };
     wdserver_handle
 }
 
+/// 获取 Ops Server 异步任务句柄
+fn get_opsserver_handle(ops_config: config::OpsServerSection) -> tokio::task::JoinHandle<()> {
+    let opsserver_handle = {
+        let mut sig_int = signal::unix::signal(signal::unix::SignalKind::interrupt()).unwrap();
+        let mut sig_term = signal::unix::signal(signal::unix::SignalKind::terminate()).unwrap();
+
+        supervisor::spawn_named("opsserver", async move {
+            if !ops_config.enable {
+                return;
+            }
+            tokio::select! {
+                _ = async {
+                    // 等待中断信号
+                    sig_int.recv().await;
+                } => {}
+                _ = async {
+                    // 等待终止信号
+                    sig_term.recv().await;
+                } => {}
+                _ = supervisor::run_restartable("opsserver", || async {
+                    info!("Ops Server starting...");
+                    let _up = supervisor::ServiceGuard::new("opsserver");
+                    // 启动运维 HTTP 异步任务
+                    opsserver::start_ops_server(ops_config.clone()).await.expect("opsserver error!");
+                }) => {}
+            }
+
+            info!("Received SIGINT/SIGTERM, Ops Server shutting down...");
+        }.instrument(info_span!("service", service = "opsserver")))
+    };
+    opsserver_handle
+}
+
+/// 获取健康检查异步任务句柄
+fn get_hcserver_handle(config: Arc<BitcommConfig>) -> tokio::task::JoinHandle<()> {
+    let hcserver_handle = {
+        let mut sig_int = signal::unix::signal(signal::unix::SignalKind::interrupt()).unwrap();
+        let mut sig_term = signal::unix::signal(signal::unix::SignalKind::terminate()).unwrap();
+
+        supervisor::spawn_named("hcserver", async move {
+            if !config.health.enable {
+                return;
+            }
+            tokio::select! {
+                _ = async {
+                    // 等待中断信号
+                    sig_int.recv().await;
+                } => {}
+                _ = async {
+                    // 等待终止信号
+                    sig_term.recv().await;
+                } => {}
+                _ = supervisor::run_restartable("hcserver", || async {
+                    info!("Health Server starting...");
+                    let _up = supervisor::ServiceGuard::new("hcserver");
+                    // 启动健康检查异步任务
+                    health::start_health_server(config.clone()).await.expect("hcserver error!");
+                }) => {}
+            }
+
+            info!("Received SIGINT/SIGTERM, Health Server shutting down...");
+        }.instrument(info_span!("service", service = "hcserver")))
+    };
+    hcserver_handle
+}
+
+/// 获取控制 socket 异步任务句柄
+fn get_ctlserver_handle(control_config: config::ControlSection) -> tokio::task::JoinHandle<()> {
+    let ctlserver_handle = {
+        let mut sig_int = signal::unix::signal(signal::unix::SignalKind::interrupt()).unwrap();
+        let mut sig_term = signal::unix::signal(signal::unix::SignalKind::terminate()).unwrap();
+
+        supervisor::spawn_named("ctlserver", async move {
+            if !control_config.enable {
+                return;
+            }
+            let socket_path = control_config.path.clone();
+            tokio::select! {
+                _ = async {
+                    // 等待中断信号
+                    sig_int.recv().await;
+                } => {}
+                _ = async {
+                    // 等待终止信号
+                    sig_term.recv().await;
+                } => {}
+                _ = supervisor::run_restartable("ctlserver", || async {
+                    info!("Control Server starting...");
+                    let _up = supervisor::ServiceGuard::new("ctlserver");
+                    // 启动控制 socket 异步任务
+                    control::start_control_server(control_config.clone()).await.expect("ctlserver error!");
+                }) => {}
+            }
+
+            // 删除 socket 文件；socket 激活时文件由 systemd 管理
+            if !systemd::uses_activated_socket("ctlserver") {
+                let _ = std::fs::remove_file(socket_path);
+            }
+            info!("Received SIGINT/SIGTERM, Control Server shutting down...");
+        }.instrument(info_span!("service", service = "ctlserver")))
+    };
+    ctlserver_handle
+}
+
 /// 获取 Web Admin Server 异步任务句柄
 fn get_webserver_handle() -> tokio::task::JoinHandle<()> {
     let webserver_handle = {
         let mut sig_int = signal::unix::signal(signal::unix::SignalKind::interrupt()).unwrap();
         let mut sig_term = signal::unix::signal(signal::unix::SignalKind::terminate()).unwrap();
 
-        tokio::spawn(async move {
+        supervisor::spawn_named("webserver", async move {
             tokio::select! {
                 _ = async {
                     // 等待中断信号
@@ -197,29 +557,63 @@ fn get_webserver_handle() -> tokio::task::JoinHandle<()> {
                 } => {
                     // println!("Received SIGTERM, shutting down...");
                 }
-                _ = async {
+                _ = supervisor::run_restartable("webserver", || async {
                     info!("Web Admin Server starting...");
+                    let _up = supervisor::ServiceGuard::new("webserver");
                     // 启动 Web Admin 异步任务
                     webserver::star_webserver().await;
                     // 
-                } => {
+                }) => {
                     // println!("Received connection, shutting down...");
                 }
             }
 
             info!( "Received SIGINT/SIGTERM, Web Admin Server shutting down...");
-        })
+        }.instrument(info_span!("service", service = "webserver")))
     };
     webserver_handle
 }
 
+/// 获取 IM WebSocket 网关异步任务句柄
+fn get_imwsserver_handle(ws_config: config::WebSocketSection) -> tokio::task::JoinHandle<()> {
+    let imwsserver_handle = {
+        let mut sig_int = signal::unix::signal(signal::unix::SignalKind::interrupt()).unwrap();
+        let mut sig_term = signal::unix::signal(signal::unix::SignalKind::terminate()).unwrap();
+
+        supervisor::spawn_named("imwsserver", async move {
+            if !ws_config.enable {
+                return;
+            }
+            tokio::select! {
+                _ = async {
+                    // 等待中断信号
+                    sig_int.recv().await;
+                } => {}
+                _ = async {
+                    // 等待终止信号
+                    sig_term.recv().await;
+                } => {}
+                _ = supervisor::run_restartable("imwsserver", || async {
+                    info!("IM WebSocket gateway starting...");
+                    let _up = supervisor::ServiceGuard::new("imwsserver");
+                    // 启动 WebSocket 网关异步任务
+                    imws::start_ws_gateway().await.expect("imwsserver error!");
+                }) => {}
+            }
+
+            info!("Received SIGINT/SIGTERM, IM WebSocket gateway shutting down...");
+        }.instrument(info_span!("service", service = "imwsserver")))
+    };
+    imwsserver_handle
+}
+
 /// 获取 Instant Message Server 异步任务句柄
 fn get_imserver_handle() -> tokio::task::JoinHandle<()> {
     let imserver_handle = {
         let mut sig_int = signal::unix::signal(signal::unix::SignalKind::interrupt()).unwrap();
         let mut sig_term = signal::unix::signal(signal::unix::SignalKind::terminate()).unwrap();
 
-        tokio::spawn(async move {
+        supervisor::spawn_named("imserver", async move {
             tokio::select! {
                 _ = async {
                     // 等待中断信号
@@ -233,29 +627,63 @@ fn get_imserver_handle() -> tokio::task::JoinHandle<()> {
                 } => {
                     // println!("Received SIGTERM, shutting down...");
                 }
-                _ = async {
+                _ = supervisor::run_restartable("imserver", || async {
                     info!("Instant Message Server starting...");
+                    let _up = supervisor::ServiceGuard::new("imserver");
                     // 启动 Instant Message 异步任务
                     imserver::start_instant_message_server().await.expect("imserver error!");
                     // 
-                } => {
+                }) => {
                     // println!("Received connection, shutting down...");
                 }
             }
 
             info!("Received SIGINT/SIGTERM, Instant Message Server shutting down...");
-        })
+        }.instrument(info_span!("service", service = "imserver")))
     };
     imserver_handle
 }
 
+/// 获取 IM TCP+TLS 备用传输异步任务句柄
+fn get_imtcpserver_handle(im_config: config::ImServerSection) -> tokio::task::JoinHandle<()> {
+    let imtcpserver_handle = {
+        let mut sig_int = signal::unix::signal(signal::unix::SignalKind::interrupt()).unwrap();
+        let mut sig_term = signal::unix::signal(signal::unix::SignalKind::terminate()).unwrap();
+
+        supervisor::spawn_named("imtcpserver", async move {
+            if !im_config.tcp_fallback.enable {
+                return;
+            }
+            tokio::select! {
+                _ = async {
+                    // 等待中断信号
+                    sig_int.recv().await;
+                } => {}
+                _ = async {
+                    // 等待终止信号
+                    sig_term.recv().await;
+                } => {}
+                _ = supervisor::run_restartable("imtcpserver", || async {
+                    info!("IM TCP+TLS fallback starting...");
+                    let _up = supervisor::ServiceGuard::new("imtcpserver");
+                    // 启动 TCP+TLS 监听异步任务
+                    imtcp::start_tcp_fallback(im_config.clone()).await.expect("imtcpserver error!");
+                }) => {}
+            }
+
+            info!("Received SIGINT/SIGTERM, IM TCP+TLS fallback shutting down...");
+        }.instrument(info_span!("service", service = "imtcpserver")))
+    };
+    imtcpserver_handle
+}
+
 /// 获取 Message Queue Server 异步任务句柄
 fn get_mqserver_handle() -> tokio::task::JoinHandle<()> {
     let mqserver_handle = {
         let mut sig_int = signal::unix::signal(signal::unix::SignalKind::interrupt()).unwrap();
         let mut sig_term = signal::unix::signal(signal::unix::SignalKind::terminate()).unwrap();
 
-        tokio::spawn(async move {
+        supervisor::spawn_named("mqserver", async move {
             tokio::select! {
                 _ = async {
                     // 等待中断信号
@@ -269,18 +697,19 @@ fn get_mqserver_handle() -> tokio::task::JoinHandle<()> {
                 } => {
                     // println!("Received SIGTERM, shutting down...");
                 }
-                _ = async {
-                    // 启动 Message Queue 异步任务
+                _ = supervisor::run_restartable("mqserver", || async {
+                    // 启动 Message Queue 异步任务，心跳由 ServiceGuard 的独立任务上报
                     info!("Message Queue Server starting...");
+                    let _up = supervisor::ServiceGuard::new("mqserver");
                     mqserver::start_message_event_queue_server().await.expect("mqserver error!");
                     // 
-                } => {
+                }) => {
                     info!("Received connection, shutting down...");
                 }
             }
 
             info!( "Received SIGINT/SIGTERM, Message Queue Server shutting down...");
-        })
+        }.instrument(info_span!("service", service = "mqserver")))
     };
     mqserver_handle
 }
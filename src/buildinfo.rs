@@ -0,0 +1,48 @@
+// 构建信息，由 build.rs 在编译时写入
+
+use serde::Serialize;
+
+/// 构建信息
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    /// bitcomm 版本
+    pub version: &'static str,
+    /// rustc 版本
+    pub rustc: &'static str,
+    /// git 提交
+    pub git_commit: &'static str,
+    /// 构建时工作区是否有未提交的修改
+    pub git_dirty: bool,
+    /// 构建时间 (RFC 3339)
+    pub build_timestamp: &'static str,
+    /// 目标平台
+    pub target: &'static str,
+    /// 编译模式 (debug / release)
+    pub profile: &'static str,
+    /// 启用的 cargo features
+    pub features: Vec<&'static str>,
+}
+
+/// 当前二进制的构建信息
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        rustc: env!("BITCOMM_RUSTC_VERSION"),
+        git_commit: env!("BITCOMM_GIT_COMMIT"),
+        git_dirty: env!("BITCOMM_GIT_DIRTY") == "true",
+        build_timestamp: env!("BITCOMM_BUILD_TIMESTAMP"),
+        target: env!("BITCOMM_TARGET"),
+        profile: env!("BITCOMM_PROFILE"),
+        features: env!("BITCOMM_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .collect(),
+    }
+}
+
+impl BuildInfo {
+    /// git 提交，工作区有修改时带 -dirty 后缀
+    pub fn commit(&self) -> String {
+        if self.git_dirty { format!("{}-dirty", self.git_commit) } else { self.git_commit.to_string() }
+    }
+}
@@ -0,0 +1,119 @@
+// bitcomm 命令行参数定义
+
+use crate::profiling::ProfileFormat;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// bitcomm 命令行
+#[derive(StructOpt, Debug)]
+#[structopt(name = "bitcomm", about = "bitcomm server, decentralized communication")]
+pub struct BitcommCli {
+    /// 配置文件路径
+    #[structopt(short, long, parse(from_os_str), default_value = "server.toml")]
+    pub config: PathBuf,
+
+    /// 不输出启动 logo (stdout 不是终端或 stdout 输出 JSON 日志时自动不输出)
+    #[structopt(long)]
+    pub no_banner: bool,
+
+    /// 子命令，缺省时启动服务器
+    #[structopt(subcommand)]
+    pub command: Option<BitcommCommand>,
+}
+
+// bitcomm 子命令
+#[derive(StructOpt, Debug)]
+pub enum BitcommCommand {
+    /// 启动服务器
+    Start,
+    /// 停止正在运行的服务器
+    Stop,
+    /// 以父进程方式运行服务器，服务器异常退出时自动重启
+    Supervise,
+    /// 配置文件工具
+    Config(ConfigCommand),
+    /// 输出版本与构建信息
+    Version {
+        /// 以 JSON 格式输出
+        #[structopt(long)]
+        json: bool,
+    },
+    /// 通过控制 socket 查询或修改运行中实例的日志过滤规则
+    LogLevel {
+        /// 新的过滤规则 (EnvFilter 语法)，缺省时只查询
+        filter: Option<String>,
+        /// 指定秒数后自动回退到原规则
+        #[structopt(long)]
+        ttl: Option<u64>,
+    },
+    /// 通过控制 socket 实时查看运行中实例的状态
+    Top,
+    /// 通过控制 socket 查询运行中实例的事件日志
+    Journal {
+        /// 最多显示最近的条数
+        #[structopt(short = "n", long)]
+        limit: Option<usize>,
+        /// 只显示该服务的事件
+        #[structopt(long)]
+        service: Option<String>,
+    },
+    /// 通过控制 socket 对运行中实例做性能剖析
+    Profile(ProfileCommand),
+    /// 告警通道工具
+    Alert(AlertCommand),
+    /// 生成开发用的自签名 CA 和 IM / Web 服务器证书，并让开发配置使用这些证书
+    GenCert {
+        /// 证书输出目录
+        #[structopt(short, long, parse(from_os_str), default_value = "certs")]
+        dir: PathBuf,
+        /// 额外写入证书的主机名或 IP，可重复指定
+        #[structopt(long = "san")]
+        sans: Vec<String>,
+        /// 要更新的开发配置，不存在时以 -c 指定的配置为模板创建
+        #[structopt(long, parse(from_os_str), default_value = "server.dev.toml")]
+        profile: PathBuf,
+        /// 服务器证书的有效天数
+        #[structopt(long, default_value = "825")]
+        days: u32,
+        /// 重新生成 CA，缺省时沿用目录中已有的 CA
+        #[structopt(long)]
+        new_ca: bool,
+    },
+}
+
+// `bitcomm config` 子命令
+#[derive(StructOpt, Debug)]
+pub enum ConfigCommand {
+    /// 输出配置文件的 JSON Schema，供编辑器补全和离线校验使用
+    Schema {
+        /// 写入到指定文件，缺省时输出到 stdout
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+}
+
+// `bitcomm profile` 子命令
+#[derive(StructOpt, Debug)]
+pub enum ProfileCommand {
+    /// 采样 CPU 并写入文件
+    Cpu {
+        /// 采样时长，单位秒
+        #[structopt(short, long, default_value = "30")]
+        seconds: u64,
+        /// 输出格式：pprof 或 flamegraph
+        #[structopt(short, long, default_value = "pprof")]
+        format: ProfileFormat,
+        /// 输出文件名，写入服务端 [control] profile_dir 目录，缺省按时间生成
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// 输出堆统计
+    Heap,
+}
+
+// `bitcomm alert` 子命令
+#[derive(StructOpt, Debug)]
+pub enum AlertCommand {
+    /// 向配置文件中的每个告警通道发送一条测试告警
+    Test,
+}
@@ -0,0 +1,1171 @@
+// bitcomm 配置文件 (server.toml) 的类型化定义
+//
+// 所有配置段都带有默认值，文件中缺省的键会按默认值补齐；
+// `bitcomm config schema` 直接由这里的结构体生成 JSON Schema，
+// 文档注释即为 Schema 中的 description。
+// 加载时 validate 检查 Schema 中声明的取值范围，越界的值 (例如间隔为 0) 直接报错，而不是在服务启动后 panic 或空转。
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{ RootSchema, Schema };
+use schemars::JsonSchema;
+use serde::de::{ self, Deserializer };
+use serde::{ Deserialize, Serialize };
+use sha2::{ Digest, Sha256 };
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::net::{ IpAddr, Ipv4Addr };
+use std::path::{ Path, PathBuf };
+
+/// bitcomm 服务器配置 (server.toml)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+#[schemars(title = "bitcomm server configuration")]
+pub struct BitcommConfig {
+    /// 公共依赖服务 (Redis、NATS) 的连接地址
+    pub bitcomm: BitcommSection,
+    /// Instant Message Server (QUIC) 监听配置
+    pub imserver: ImServerSection,
+    /// Web Admin Server (HTTP) 监听配置
+    pub webserver: WebServerSection,
+    /// Watch Dog Server 配置
+    pub wdserver: WdServerSection,
+    /// 内部运维监听 (/metrics 等)，不应暴露到公网
+    pub opsserver: OpsServerSection,
+    /// OpenTelemetry 链路追踪导出 (OTLP)
+    pub otlp: OtlpSection,
+    /// 日志输出配置
+    pub log: LogSection,
+    /// 本地控制 socket
+    pub control: ControlSection,
+    /// 健康检查监听 (/healthz、/readyz)
+    pub health: HealthSection,
+    /// panic 崩溃报告
+    pub crash: CrashSection,
+    /// 进程事件日志
+    pub journal: JournalSection,
+    /// tokio 运行时卡顿检测
+    pub stall: StallSection,
+    /// tokio-console 监听 (仅在以 console feature 构建时生效)
+    pub console: ConsoleSection,
+    /// 看门狗、服务状态等告警的通知通道
+    pub alert: AlertSection,
+    /// `bitcomm supervise` 父进程的重启策略
+    pub supervise: SuperviseSection,
+    /// systemd 通知 (sd_notify) 与看门狗
+    pub systemd: SystemdSection,
+}
+
+/// [bitcomm] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct BitcommSection {
+    /// Redis 连接地址，例如 redis://localhost:6379
+    #[schemars(url, regex(pattern = r"^rediss?://"))]
+    pub redis: String,
+    /// NATS 连接地址，例如 nats://localhost:4222
+    #[schemars(url, regex(pattern = r"^(nats|tls)://"))]
+    pub nats: String,
+    /// 数据库连接地址，例如 postgres://user@localhost:5432/bitcomm；为空时不做就绪检查
+    pub database: String,
+}
+
+impl Default for BitcommSection {
+    fn default() -> Self {
+        BitcommSection {
+            redis: "redis://localhost:6753".to_string(),
+            nats: "nats://10.20.30.1".to_string(),
+            database: String::new(),
+        }
+    }
+}
+
+/// [imserver] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ImServerSection {
+    /// 监听地址
+    pub ip: IpAddr,
+    /// 监听的 UDP 端口 (1-65535)
+    #[serde(deserialize_with = "de_from_str_or_num")]
+    #[schemars(schema_with = "port_schema")]
+    pub port: u16,
+    /// IM 服务器证书 (PEM，可包含中间证书)；与 key_path 同时设置时生效。
+    /// 目前用于 TCP+TLS 备用传输，QUIC 端点的证书仍由 btcmnetwork 加载
+    pub cert_path: Option<PathBuf>,
+    /// 证书私钥 (PEM，PKCS#8、PKCS#1 或 SEC1)
+    pub key_path: Option<PathBuf>,
+    /// 可选的 CA 证书链 (PEM)，追加在服务器证书之后发送给客户端
+    pub ca_path: Option<PathBuf>,
+    /// 检查证书文件是否变化的间隔，单位秒；变化后新握手使用新证书，已建立的连接不受影响
+    #[schemars(range(min = 1))]
+    pub cert_reload_secs: u64,
+    /// 证书剩余有效期少于该天数时告警
+    pub cert_expiry_warn_days: u64,
+    /// 客户端证书认证 (mTLS)
+    pub client_auth: ClientAuthSection,
+    /// UDP 不可用时客户端使用的 TCP+TLS 备用传输
+    pub tcp_fallback: TcpFallbackSection,
+    /// TCP+TLS 备用传输和 WebSocket 网关转接到本机 QUIC 端点的方式
+    pub bridge: ImBridgeSection,
+}
+
+impl Default for ImServerSection {
+    fn default() -> Self {
+        ImServerSection {
+            ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 1130,
+            cert_path: None,
+            key_path: None,
+            ca_path: None,
+            cert_reload_secs: 30,
+            cert_expiry_warn_days: 14,
+            client_auth: ClientAuthSection::default(),
+            tcp_fallback: TcpFallbackSection::default(),
+            bridge: ImBridgeSection::default(),
+        }
+    }
+}
+
+/// [imserver.tcp_fallback] 配置段，需同时配置 [imserver] 服务器证书
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct TcpFallbackSection {
+    /// 是否启用
+    pub enable: bool,
+    /// 监听的 TCP 端口，不设置时与 [imserver] port 相同
+    #[schemars(range(min = 1))]
+    pub port: Option<u16>,
+    /// TLS 握手超时，单位秒
+    #[schemars(range(min = 1))]
+    pub handshake_timeout_secs: u64,
+    /// 同时保持的最大连接数，超出后新连接被直接关闭
+    #[schemars(range(min = 1))]
+    pub max_connections: usize,
+}
+
+impl Default for TcpFallbackSection {
+    fn default() -> Self {
+        TcpFallbackSection {
+            enable: false,
+            port: None,
+            handshake_timeout_secs: 10,
+            max_connections: 10000,
+        }
+    }
+}
+
+/// [imserver.bridge] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ImBridgeSection {
+    /// 连接 QUIC 端点时使用的 ALPN，须与 btcmnetwork 的设置一致；为空时不发送
+    pub alpn: Vec<String>,
+    /// 校验 QUIC 端点证书时使用的主机名
+    pub server_name: String,
+    /// 签发 QUIC 端点证书的 CA (PEM)；不设置时只接受 [imserver] cert_path 中的证书
+    pub ca_path: Option<PathBuf>,
+    /// 连接 QUIC 端点的超时，单位秒
+    #[schemars(range(min = 1))]
+    pub connect_timeout_secs: u64,
+    /// 与 imserver 共享的 HMAC-SHA256 密钥，设置后每条转接的流以签名的身份前导行开头 (见 imbridge)；
+    /// 为空时不发送，客户端证书映射出的用户 ID 不会传给 imserver
+    pub identity_secret: String,
+}
+
+impl Default for ImBridgeSection {
+    fn default() -> Self {
+        ImBridgeSection {
+            alpn: Vec::new(),
+            server_name: "localhost".to_string(),
+            ca_path: None,
+            connect_timeout_secs: 5,
+            identity_secret: String::new(),
+        }
+    }
+}
+
+/// [imserver.client_auth] 配置段，需同时配置 [imserver] 服务器证书；
+/// 目前作用于 TCP+TLS 备用传输，映射出的用户 ID 经 [imserver.bridge] identity_secret 签名后传给 imserver；
+/// QUIC 端点的客户端认证仍由 btcmnetwork 决定
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ClientAuthSection {
+    /// 是否要求客户端证书
+    pub mode: ClientAuthMode,
+    /// 信任的客户端 CA 证书 (PEM)，mode 不为 none 时必须设置；修改后需重启生效
+    pub ca_path: Option<PathBuf>,
+    /// 证书吊销列表 (PEM)，文件变化后按 [imserver] cert_reload_secs 重新加载
+    pub crl_path: Option<PathBuf>,
+    /// 设置了 crl_path 但其中没有客户端证书签发者的吊销列表时，是否视为未吊销；
+    /// 默认拒绝，只有部分 CA 提供吊销列表时才需要开启
+    pub allow_unknown_revocation: bool,
+    /// 从客户端证书的哪个字段取得 bitcomm 用户 ID
+    pub user_id: ClientIdSource,
+    /// 只使用以该前缀开头的值，并去掉前缀，例如 bitcomm://user/
+    pub user_id_prefix: String,
+}
+
+impl Default for ClientAuthSection {
+    fn default() -> Self {
+        ClientAuthSection {
+            mode: ClientAuthMode::None,
+            ca_path: None,
+            crl_path: None,
+            allow_unknown_revocation: false,
+            user_id: ClientIdSource::CommonName,
+            user_id_prefix: String::new(),
+        }
+    }
+}
+
+/// 客户端证书认证方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientAuthMode {
+    /// 不请求客户端证书，只用 JWT 认证
+    None,
+    /// 请求客户端证书；没有证书的客户端仍可用 JWT 认证，提供的证书必须有效
+    Optional,
+    /// 必须提供有效的客户端证书
+    Required,
+}
+
+/// 用户 ID 在客户端证书中的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientIdSource {
+    /// 主题的 CN
+    CommonName,
+    /// SAN 中的 URI
+    SanUri,
+    /// SAN 中的 DNS 名称
+    SanDns,
+    /// SAN 中的邮箱地址
+    SanEmail,
+}
+
+/// [webserver] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct WebServerSection {
+    /// 监听地址
+    pub ip: IpAddr,
+    /// 监听的 TCP 端口 (1-65535)
+    #[serde(deserialize_with = "de_from_str_or_num")]
+    #[schemars(schema_with = "port_schema")]
+    pub port: u16,
+    /// 浏览器 IM 客户端使用的 WebSocket 网关
+    pub websocket: WebSocketSection,
+}
+
+impl Default for WebServerSection {
+    fn default() -> Self {
+        WebServerSection { ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED), port: 1220, websocket: WebSocketSection::default() }
+    }
+}
+
+/// [webserver.websocket] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct WebSocketSection {
+    /// 是否启用
+    pub enable: bool,
+    /// 监听地址
+    pub ip: IpAddr,
+    /// 监听的 TCP 端口 (1-65535)，btcmweb 的 Web 服务器支持合并网关路由之前网关单独监听
+    #[serde(deserialize_with = "de_from_str_or_num")]
+    #[schemars(schema_with = "port_schema")]
+    pub port: u16,
+    /// WebSocket 路径
+    pub path: String,
+    /// 允许的 Origin，例如 https://chat.example.com；启用时必须设置，不在列表中或不带 Origin 的请求被拒绝
+    pub allowed_origins: Vec<String>,
+    /// 同时保持的最大连接数
+    #[schemars(range(min = 1))]
+    pub max_connections: usize,
+}
+
+impl Default for WebSocketSection {
+    fn default() -> Self {
+        WebSocketSection {
+            enable: false,
+            ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 1131,
+            path: "/im/ws".to_string(),
+            allowed_origins: Vec::new(),
+            max_connections: 10000,
+        }
+    }
+}
+
+/// [wdserver] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct WdServerSection {
+    /// 巡检间隔，单位秒 (1-86400)
+    #[serde(deserialize_with = "de_from_str_or_num")]
+    #[schemars(schema_with = "interval_schema")]
+    pub time: u64,
+    /// 服务心跳超过该秒数未更新即判定为卡住 (1-86400)
+    #[serde(deserialize_with = "de_from_str_or_num")]
+    #[schemars(schema_with = "interval_schema")]
+    pub heartbeat_timeout: u64,
+    /// 服务有进行中的工作、进度计数超过该秒数没有增长即判定为卡住，0 表示不检查进度
+    pub progress_timeout: u64,
+    /// 判定卡住后的动作
+    pub action: WatchdogAction,
+    /// 按服务名覆盖上面的设置，例如 [wdserver.services.imserver]
+    pub services: BTreeMap<String, WatchdogRule>,
+    /// drain-and-restart 动作开始排空后等待多少秒再重启进程
+    pub drain_grace_secs: u64,
+    /// 每个巡检周期执行的资源检查
+    pub checks: Vec<ResourceCheck>,
+}
+
+impl Default for WdServerSection {
+    fn default() -> Self {
+        WdServerSection {
+            time: 300,
+            heartbeat_timeout: 30,
+            progress_timeout: 120,
+            action: WatchdogAction::Log,
+            services: BTreeMap::new(),
+            drain_grace_secs: 30,
+            checks: vec![
+                ResourceCheck::new(ResourceKind::Fds, 80.0, 95.0),
+                ResourceCheck::new(ResourceKind::Disk, 1024.0, 256.0),
+            ],
+        }
+    }
+}
+
+/// [wdserver.services.<name>] 单个服务的看门狗设置，缺省的键沿用 [wdserver] 中的值
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct WatchdogRule {
+    /// 心跳超时，单位秒
+    pub heartbeat_timeout: Option<u64>,
+    /// 进度超时，单位秒，0 表示不检查进度
+    pub progress_timeout: Option<u64>,
+    /// 判定卡住后的动作
+    pub action: Option<WatchdogAction>,
+}
+
+/// 看门狗判定服务卡住后的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatchdogAction {
+    /// 只输出告警日志
+    Log,
+    /// 原地重启该服务
+    RestartService,
+    /// 重新执行当前进程
+    RestartProcess,
+}
+
+/// [[wdserver.checks]] 单项资源检查
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ResourceCheck {
+    /// 检查项，阈值的单位随检查项而定
+    pub kind: ResourceKind,
+    /// disk 检查的目录，例如日志目录或数据目录
+    #[serde(default = "default_check_path")]
+    pub path: PathBuf,
+    /// 告警阈值
+    pub warn: f64,
+    /// 严重阈值
+    pub critical: f64,
+    /// 达到告警阈值时的动作
+    #[serde(default = "default_warn_action")]
+    pub on_warn: ResourceAction,
+    /// 达到严重阈值时的动作
+    #[serde(default = "default_critical_action")]
+    pub on_critical: ResourceAction,
+}
+
+impl ResourceCheck {
+    fn new(kind: ResourceKind, warn: f64, critical: f64) -> ResourceCheck {
+        ResourceCheck {
+            kind,
+            path: default_check_path(),
+            warn,
+            critical,
+            on_warn: default_warn_action(),
+            on_critical: default_critical_action(),
+        }
+    }
+
+    /// 检查项名称，disk 检查带上目录，例如 disk:/var/log
+    pub fn name(&self) -> String {
+        match self.kind {
+            ResourceKind::Disk => format!("disk:{}", self.path.display()),
+            kind => kind.as_str().to_string(),
+        }
+    }
+}
+
+fn default_check_path() -> PathBuf {
+    PathBuf::from(".")
+}
+
+fn default_warn_action() -> ResourceAction {
+    ResourceAction::Log
+}
+
+fn default_critical_action() -> ResourceAction {
+    ResourceAction::Alert
+}
+
+/// 资源检查项
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResourceKind {
+    /// 进程常驻内存，单位 MB
+    Rss,
+    /// 打开的文件描述符数占 RLIMIT_NOFILE 的百分比
+    Fds,
+    /// path 所在文件系统的剩余空间，单位 MB，低于阈值时告警
+    Disk,
+    /// Redis PING 往返时间，单位毫秒，不可达时视为严重
+    Redis,
+    /// NATS 连接往返时间，单位毫秒，不可达时视为严重
+    Nats,
+    /// tokio 全局队列中等待调度的任务数
+    QueueDepth,
+}
+
+impl ResourceKind {
+    /// 配置文件中的名称
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResourceKind::Rss => "rss",
+            ResourceKind::Fds => "fds",
+            ResourceKind::Disk => "disk",
+            ResourceKind::Redis => "redis",
+            ResourceKind::Nats => "nats",
+            ResourceKind::QueueDepth => "queue-depth",
+        }
+    }
+
+    /// 数值越小越糟糕的检查项 (剩余空间)
+    pub fn lower_is_worse(self) -> bool {
+        self == ResourceKind::Disk
+    }
+}
+
+/// 资源检查越过阈值时的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResourceAction {
+    /// 只输出日志
+    Log,
+    /// 输出 ERROR 日志、记入事件日志并发送到 [alert] 通道
+    Alert,
+    /// 就绪检查失败、TCP+TLS 备用传输和 WebSocket 网关拒绝新连接 (QUIC 新连接不受影响) 并发送告警，回到阈值以下后恢复
+    Shed,
+    /// 发送告警并开始排空，等待 drain_grace_secs 秒后重启进程
+    DrainAndRestart,
+}
+
+/// [opsserver] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct OpsServerSection {
+    /// 是否启用运维监听
+    pub enable: bool,
+    /// 监听地址，建议只绑定内网或回环地址
+    pub ip: IpAddr,
+    /// 监听的 TCP 端口 (1-65535)
+    #[serde(deserialize_with = "de_from_str_or_num")]
+    #[schemars(schema_with = "port_schema")]
+    pub port: u16,
+    /// tokio 运行时指标的采集间隔，单位秒 (1-86400)
+    #[serde(deserialize_with = "de_from_str_or_num")]
+    #[schemars(schema_with = "interval_schema")]
+    pub runtime_interval: u64,
+    /// /admin 接口的 Bearer token，为空时禁用 /admin 接口
+    pub admin_token: String,
+}
+
+impl Default for OpsServerSection {
+    fn default() -> Self {
+        OpsServerSection {
+            enable: true,
+            ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 1221,
+            runtime_interval: 5,
+            admin_token: String::new(),
+        }
+    }
+}
+
+/// [health] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct HealthSection {
+    /// 是否启用健康检查监听
+    pub enable: bool,
+    /// 监听地址，供负载均衡器和编排系统探测
+    pub ip: IpAddr,
+    /// 监听的 TCP 端口 (1-65535)
+    #[serde(deserialize_with = "de_from_str_or_num")]
+    #[schemars(schema_with = "port_schema")]
+    pub port: u16,
+    /// Watch Dog 心跳超过该秒数未更新即视为不健康 (1-86400)
+    #[serde(deserialize_with = "de_from_str_or_num")]
+    #[schemars(schema_with = "interval_schema")]
+    pub heartbeat_timeout: u64,
+    /// 单项依赖检查的超时，单位毫秒
+    #[schemars(range(min = 1))]
+    pub check_timeout_ms: u64,
+}
+
+impl Default for HealthSection {
+    fn default() -> Self {
+        HealthSection {
+            enable: true,
+            ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 1222,
+            heartbeat_timeout: 30,
+            check_timeout_ms: 1000,
+        }
+    }
+}
+
+/// [control] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ControlSection {
+    /// 是否启用控制 socket
+    pub enable: bool,
+    /// Unix domain socket 路径
+    pub path: PathBuf,
+    /// CPU 剖析文件的输出目录，profile 命令只能指定其中的文件名，且不会覆盖已有文件
+    pub profile_dir: PathBuf,
+}
+
+impl Default for ControlSection {
+    fn default() -> Self {
+        ControlSection { enable: true, path: PathBuf::from("bitcomm.sock"), profile_dir: std::env::temp_dir() }
+    }
+}
+
+/// OTLP 传输协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OtlpProtocol {
+    /// gRPC (默认端口 4317)
+    Grpc,
+    /// HTTP/protobuf (默认端口 4318)
+    Http,
+}
+
+/// [otlp] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct OtlpSection {
+    /// 是否启用 OTLP 导出
+    pub enable: bool,
+    /// 传输协议
+    pub protocol: OtlpProtocol,
+    /// Collector 地址，例如 http://localhost:4317；http 协议下会自动追加 /v1/traces
+    #[schemars(url)]
+    pub endpoint: String,
+    /// 单次导出超时，单位秒 (1-86400)
+    #[serde(deserialize_with = "de_from_str_or_num")]
+    #[schemars(schema_with = "interval_schema")]
+    pub timeout: u64,
+    /// 资源属性 service.name
+    pub service_name: String,
+    /// 附加的资源属性，例如 deployment.environment = "dev"
+    pub resource: BTreeMap<String, String>,
+    /// 采样比例 (0.0-1.0)，1.0 表示全部采样
+    #[schemars(range(min = 0.0, max = 1.0))]
+    pub sampling_ratio: f64,
+    /// 批量导出队列长度上限
+    #[schemars(range(min = 1))]
+    pub max_queue_size: usize,
+    /// 单批导出的 span 数量上限
+    #[schemars(range(min = 1))]
+    pub max_export_batch_size: usize,
+    /// 批量导出间隔，单位毫秒
+    #[schemars(range(min = 1))]
+    pub scheduled_delay_ms: u64,
+}
+
+impl Default for OtlpSection {
+    fn default() -> Self {
+        OtlpSection {
+            enable: false,
+            protocol: OtlpProtocol::Grpc,
+            endpoint: "http://localhost:4317".to_string(),
+            timeout: 10,
+            service_name: "bitcomm".to_string(),
+            resource: BTreeMap::new(),
+            sampling_ratio: 1.0,
+            max_queue_size: 2048,
+            max_export_batch_size: 512,
+            scheduled_delay_ms: 5000,
+        }
+    }
+}
+
+/// [log] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct LogSection {
+    /// 启动时的日志过滤规则 (EnvFilter 语法)，设置了 RUST_LOG 时以 RUST_LOG 为准
+    pub filter: String,
+    /// 日志输出目标，可同时配置多个，缺省为一个文本格式的 stdout
+    pub sinks: Vec<LogSink>,
+    /// 内存中保留的最近日志条数，供 `bitcomm top` 显示最近的告警，0 为不保留
+    pub recent_lines: usize,
+}
+
+impl Default for LogSection {
+    fn default() -> Self {
+        LogSection { filter: "info,tower_http=debug".to_string(), sinks: vec![LogSink::default()], recent_lines: 500 }
+    }
+}
+
+/// 日志输出目标类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTarget {
+    /// 标准输出
+    Stdout,
+    /// 文件
+    File,
+}
+
+/// 日志格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// 便于阅读的文本格式
+    Text,
+    /// 每行一个 JSON 对象，字段固定为 timestamp/level/service/target/pid/message/fields/spans
+    Json,
+}
+
+/// 日志文件滚动方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    /// 不滚动
+    Never,
+    /// 按天滚动
+    Daily,
+    /// 按文件大小滚动 (见 max_size_mb)
+    Size,
+}
+
+/// [[log.sinks]] 单个日志输出目标
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct LogSink {
+    /// 输出目标
+    pub target: LogTarget,
+    /// 输出格式
+    pub format: LogFormat,
+    /// 日志目录 (仅 file)
+    pub directory: PathBuf,
+    /// 日志文件名 (仅 file)，按天滚动时作为前缀
+    pub file_name: String,
+    /// 滚动方式 (仅 file)
+    pub rotation: LogRotation,
+    /// 按大小滚动时单个文件的上限，单位 MB
+    #[schemars(range(min = 1))]
+    pub max_size_mb: u64,
+    /// 保留的历史文件个数
+    #[schemars(range(min = 1))]
+    pub max_files: usize,
+}
+
+impl Default for LogSink {
+    fn default() -> Self {
+        LogSink {
+            target: LogTarget::Stdout,
+            format: LogFormat::Text,
+            directory: PathBuf::from("logs"),
+            file_name: "bitcomm.log".to_string(),
+            rotation: LogRotation::Daily,
+            max_size_mb: 100,
+            max_files: 7,
+        }
+    }
+}
+
+/// [crash] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct CrashSection {
+    /// 是否在 panic 时写入崩溃报告
+    pub enable: bool,
+    /// 崩溃报告目录
+    pub directory: PathBuf,
+    /// 报告中附带的最近日志条数 (取自 [log] recent_lines 的内存缓冲)
+    pub recent_lines: usize,
+    /// `bitcomm supervise` 保留的报告个数，超出时删除最早的报告；0 表示不删除
+    pub max_reports: usize,
+}
+
+impl Default for CrashSection {
+    fn default() -> Self {
+        CrashSection { enable: true, directory: PathBuf::from("crash"), recent_lines: 200, max_reports: 20 }
+    }
+}
+
+/// [journal] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct JournalSection {
+    /// 事件日志文件 (JSON Lines)
+    pub path: PathBuf,
+    /// 保留的事件条数，超出后丢弃最早的事件
+    #[schemars(range(min = 1))]
+    pub max_events: usize,
+}
+
+impl Default for JournalSection {
+    fn default() -> Self {
+        JournalSection { path: PathBuf::from("bitcomm-journal.jsonl"), max_events: 1000 }
+    }
+}
+
+/// [stall] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct StallSection {
+    /// 是否启用卡顿检测
+    pub enable: bool,
+    /// 探测间隔，单位毫秒
+    #[schemars(range(min = 10))]
+    pub interval_ms: u64,
+    /// 调度延迟超过该值时告警并输出工作线程调用栈，单位毫秒
+    #[schemars(range(min = 1))]
+    pub threshold_ms: u64,
+    /// 卡顿时用信号抓取工作线程调用栈；信号处理函数中的栈回溯不是 async-signal-safe 的，
+    /// 目标线程恰好持有动态链接器的锁时该线程会死锁，默认关闭，仅在排查卡顿时开启
+    pub sample_stacks: bool,
+}
+
+impl Default for StallSection {
+    fn default() -> Self {
+        StallSection { enable: true, interval_ms: 1000, threshold_ms: 100, sample_stacks: false }
+    }
+}
+
+/// [console] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ConsoleSection {
+    /// tokio-console 连接的监听地址，应只绑定本地地址
+    pub ip: IpAddr,
+    /// 监听的 TCP 端口 (1-65535)
+    #[serde(deserialize_with = "de_from_str_or_num")]
+    #[schemars(schema_with = "port_schema")]
+    pub port: u16,
+}
+
+impl Default for ConsoleSection {
+    fn default() -> Self {
+        ConsoleSection { ip: IpAddr::V4(Ipv4Addr::LOCALHOST), port: 6669 }
+    }
+}
+
+/// [supervise] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct SuperviseSection {
+    /// 子进程异常退出后首次重启前的等待，单位毫秒；之后每次加倍
+    #[schemars(range(min = 1))]
+    pub backoff_initial_ms: u64,
+    /// 重启等待的上限，单位秒
+    #[schemars(range(min = 1))]
+    pub backoff_max_secs: u64,
+    /// 子进程运行超过该秒数后退出，重启等待回到 backoff_initial_ms
+    pub stable_secs: u64,
+}
+
+impl Default for SuperviseSection {
+    fn default() -> Self {
+        SuperviseSection { backoff_initial_ms: 1000, backoff_max_secs: 60, stable_secs: 60 }
+    }
+}
+
+/// [systemd] 配置段；没有 NOTIFY_SOCKET 环境变量 (不在 systemd 下运行) 时不发送任何通知
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct SystemdSection {
+    /// 是否向 systemd 发送 READY/STATUS/WATCHDOG/STOPPING 通知
+    pub enable: bool,
+    /// 更新 STATUS= 的间隔，单位秒
+    #[schemars(range(min = 1))]
+    pub status_interval_secs: u64,
+}
+
+impl Default for SystemdSection {
+    fn default() -> Self {
+        SystemdSection { enable: true, status_interval_secs: 10 }
+    }
+}
+
+/// [alert] 配置段
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct AlertSection {
+    /// 是否发送告警通知
+    pub enable: bool,
+    /// 同一告警持续期间重复通知的间隔，单位秒；间隔内的重复触发不再通知
+    #[schemars(range(min = 1))]
+    pub repeat_interval_secs: u64,
+    /// 每分钟最多发送的通知数 (所有告警合计)，超出的通知丢弃
+    #[schemars(range(min = 1))]
+    pub max_per_minute: usize,
+    /// 单个通道发送的超时，单位毫秒
+    #[schemars(range(min = 1))]
+    pub timeout_ms: u64,
+    /// 通知通道，每条告警发送到所有通道
+    pub sinks: Vec<AlertSink>,
+}
+
+impl Default for AlertSection {
+    fn default() -> Self {
+        AlertSection { enable: true, repeat_interval_secs: 3600, max_per_minute: 10, timeout_ms: 5000, sinks: Vec::new() }
+    }
+}
+
+/// [[alert.sinks]] 单个告警通道，kind 为 webhook、smtp 或 script
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum AlertSink {
+    /// 以 JSON POST 到 url
+    Webhook(WebhookSink),
+    /// 发送邮件
+    Smtp(SmtpSink),
+    /// 执行本地脚本
+    Script(ScriptSink),
+}
+
+impl AlertSink {
+    /// 用于日志的通道描述
+    pub fn describe(&self) -> String {
+        match self {
+            AlertSink::Webhook(sink) => format!("webhook {}", sink.url),
+            AlertSink::Smtp(sink) => format!("smtp {}:{}", sink.host, sink.port),
+            AlertSink::Script(sink) => format!("script {}", sink.path.display()),
+        }
+    }
+}
+
+/// webhook 告警通道
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookSink {
+    /// 接收告警的 URL
+    #[schemars(url)]
+    pub url: String,
+    /// HMAC-SHA256 签名密钥，签名放在 X-Bitcomm-Signature 头中 (sha256=<hex>)；为空时不签名
+    #[serde(default)]
+    pub secret: String,
+}
+
+/// SMTP 告警通道
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SmtpSink {
+    /// SMTP 服务器地址
+    pub host: String,
+    /// SMTP 服务器端口 (1-65535)
+    #[serde(default = "default_smtp_port", deserialize_with = "de_from_str_or_num")]
+    #[schemars(schema_with = "port_schema")]
+    pub port: u16,
+    /// 连接加密方式
+    #[serde(default)]
+    pub tls: SmtpTls,
+    /// 登录用户名，为空时不登录
+    #[serde(default)]
+    pub username: String,
+    /// 登录密码
+    #[serde(default)]
+    pub password: String,
+    /// 发件人
+    pub from: String,
+    /// 收件人
+    pub to: Vec<String>,
+}
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+/// SMTP 连接加密方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// 明文，只应用于本机或内网的中继
+    #[default]
+    None,
+    /// 明文连接后升级为 TLS
+    Starttls,
+    /// 直接以 TLS 连接
+    Tls,
+}
+
+/// 脚本告警通道
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ScriptSink {
+    /// 可执行文件路径；告警以 JSON 写入 stdin，主要字段同时放在 BITCOMM_ALERT_* 环境变量中
+    pub path: PathBuf,
+}
+
+impl BitcommConfig {
+    /// 从 TOML 文件加载配置
+    pub fn load(path: &Path) -> Result<BitcommConfig, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let config: BitcommConfig = toml::from_str(&text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 检查 JSON Schema 中声明的取值范围，列出所有越界的键
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let mut v = Violations::default();
+        v.check(
+            self.bitcomm.redis.starts_with("redis://") || self.bitcomm.redis.starts_with("rediss://"),
+            "bitcomm.redis",
+            "must start with redis:// or rediss://"
+        );
+        v.check(
+            self.bitcomm.nats.starts_with("nats://") || self.bitcomm.nats.starts_with("tls://"),
+            "bitcomm.nats",
+            "must start with nats:// or tls://"
+        );
+
+        v.port("imserver.port", self.imserver.port);
+        v.at_least("imserver.cert_reload_secs", self.imserver.cert_reload_secs, 1);
+        if let Some(port) = self.imserver.tcp_fallback.port {
+            v.port("imserver.tcp_fallback.port", port);
+        }
+        v.at_least("imserver.tcp_fallback.handshake_timeout_secs", self.imserver.tcp_fallback.handshake_timeout_secs, 1);
+        v.at_least("imserver.tcp_fallback.max_connections", self.imserver.tcp_fallback.max_connections, 1);
+        v.at_least("imserver.bridge.connect_timeout_secs", self.imserver.bridge.connect_timeout_secs, 1);
+
+        v.port("webserver.port", self.webserver.port);
+        v.port("webserver.websocket.port", self.webserver.websocket.port);
+        v.at_least("webserver.websocket.max_connections", self.webserver.websocket.max_connections, 1);
+
+        v.interval("wdserver.time", self.wdserver.time);
+        v.interval("wdserver.heartbeat_timeout", self.wdserver.heartbeat_timeout);
+
+        v.port("opsserver.port", self.opsserver.port);
+        v.interval("opsserver.runtime_interval", self.opsserver.runtime_interval);
+
+        v.port("health.port", self.health.port);
+        v.interval("health.heartbeat_timeout", self.health.heartbeat_timeout);
+        v.at_least("health.check_timeout_ms", self.health.check_timeout_ms, 1);
+
+        v.interval("otlp.timeout", self.otlp.timeout);
+        v.check((0.0..=1.0).contains(&self.otlp.sampling_ratio), "otlp.sampling_ratio", "must be 0.0-1.0");
+        v.at_least("otlp.max_queue_size", self.otlp.max_queue_size, 1);
+        v.at_least("otlp.max_export_batch_size", self.otlp.max_export_batch_size, 1);
+        v.at_least("otlp.scheduled_delay_ms", self.otlp.scheduled_delay_ms, 1);
+
+        for (index, sink) in self.log.sinks.iter().enumerate() {
+            v.at_least(&format!("log.sinks[{}].max_size_mb", index), sink.max_size_mb, 1);
+            v.at_least(&format!("log.sinks[{}].max_files", index), sink.max_files, 1);
+        }
+
+        v.at_least("journal.max_events", self.journal.max_events, 1);
+        v.at_least("stall.interval_ms", self.stall.interval_ms, 10);
+        v.at_least("stall.threshold_ms", self.stall.threshold_ms, 1);
+        v.port("console.port", self.console.port);
+        v.at_least("supervise.backoff_initial_ms", self.supervise.backoff_initial_ms, 1);
+        v.at_least("supervise.backoff_max_secs", self.supervise.backoff_max_secs, 1);
+        v.at_least("systemd.status_interval_secs", self.systemd.status_interval_secs, 1);
+
+        v.at_least("alert.repeat_interval_secs", self.alert.repeat_interval_secs, 1);
+        v.at_least("alert.max_per_minute", self.alert.max_per_minute, 1);
+        v.at_least("alert.timeout_ms", self.alert.timeout_ms, 1);
+        for (index, sink) in self.alert.sinks.iter().enumerate() {
+            if let AlertSink::Smtp(smtp) = sink {
+                v.port(&format!("alert.sinks[{}].port", index), smtp.port);
+            }
+        }
+
+        v.into_result()
+    }
+
+    /// 生效配置 (补齐默认值后) 的 SHA-256，用于确认崩溃或变更时运行的是哪份配置
+    pub fn digest(&self) -> String {
+        let text = toml::to_string(self).unwrap_or_default();
+        hex::encode(Sha256::digest(text.as_bytes()))
+    }
+}
+
+/// 生成配置文件的 JSON Schema
+pub fn config_schema() -> RootSchema {
+    schemars::schema_for!(BitcommConfig)
+}
+
+/// 生成格式化后的 JSON Schema 文本
+pub fn config_schema_json() -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&config_schema())
+}
+
+// server.toml 历史上把端口和时间写成字符串 (port = "1130")，
+// 这里同时接受字符串和整数两种写法
+fn de_from_str_or_num<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where D: Deserializer<'de>, T: TryFrom<u64> + std::str::FromStr, <T as std::str::FromStr>::Err: fmt::Display
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StrOrNum {
+        Str(String),
+        Num(u64),
+    }
+    match StrOrNum::deserialize(deserializer)? {
+        StrOrNum::Str(s) => s.trim().parse::<T>().map_err(de::Error::custom),
+        StrOrNum::Num(n) => T::try_from(n).map_err(|_| de::Error::custom(format!("{} is out of range", n))),
+    }
+}
+
+// validate 中收集到的越界项
+#[derive(Default)]
+struct Violations(Vec<String>);
+
+impl Violations {
+    fn check(&mut self, ok: bool, key: &str, rule: &str) {
+        if !ok {
+            self.0.push(format!("{} {}", key, rule));
+        }
+    }
+
+    fn at_least<T: PartialOrd + fmt::Display>(&mut self, key: &str, value: T, minimum: T) {
+        let ok = value >= minimum;
+        self.check(ok, key, &format!("must be at least {} (got {})", minimum, value));
+    }
+
+    fn port(&mut self, key: &str, port: u16) {
+        self.check(port != 0, key, "must be 1-65535 (got 0)");
+    }
+
+    fn interval(&mut self, key: &str, value: u64) {
+        self.check((1..=86400).contains(&value), key, &format!("must be 1-86400 (got {})", value));
+    }
+
+    fn into_result(self) -> Result<(), Box<dyn Error>> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("invalid configuration: {}", self.0.join("; ")).into())
+        }
+    }
+}
+
+// 整数或纯数字字符串，整数形式带取值范围
+fn ranged_schema(minimum: u64, maximum: u64) -> Schema {
+    serde_json
+        ::from_value(
+            serde_json::json!({
+                "anyOf": [
+                    { "type": "integer", "minimum": minimum, "maximum": maximum },
+                    { "type": "string", "pattern": "^[0-9]+$" }
+                ]
+            })
+        )
+        .expect("static schema is valid")
+}
+
+fn port_schema(_gen: &mut SchemaGenerator) -> Schema {
+    ranged_schema(1, 65535)
+}
+
+fn interval_schema(_gen: &mut SchemaGenerator) -> Schema {
+    ranged_schema(1, 86400)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 写入临时文件后用 load 加载
+    fn load_text(name: &str, text: &str) -> Result<BitcommConfig, Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("bitcomm-config-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, text).unwrap();
+        let result = BitcommConfig::load(&path);
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    fn load_error(name: &str, text: &str) -> String {
+        load_text(name, text).expect_err("configuration should be rejected").to_string()
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        BitcommConfig::default().validate().unwrap();
+    }
+
+    #[test]
+    fn shipped_server_toml_loads() {
+        let config = BitcommConfig::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("server.toml")).unwrap();
+        assert_eq!(config.imserver.port, 1130);
+        assert_eq!(config.wdserver.time, 300);
+    }
+
+    #[test]
+    fn schema_describes_defaults_and_ranges() {
+        let schema: serde_json::Value = serde_json::from_str(&config_schema_json().unwrap()).unwrap();
+        assert!(schema["properties"]["imserver"].is_object());
+        assert_eq!(schema["properties"]["imserver"]["default"]["port"], 1130);
+
+        let port = &schema["definitions"]["ImServerSection"]["properties"]["port"];
+        assert!(port["description"].as_str().unwrap().contains("1-65535"));
+        assert_eq!(port["anyOf"][0]["minimum"].as_f64(), Some(1.0));
+        assert_eq!(port["anyOf"][0]["maximum"].as_f64(), Some(65535.0));
+        assert_eq!(port["anyOf"][1]["pattern"], "^[0-9]+$");
+
+        let time = &schema["definitions"]["WdServerSection"]["properties"]["time"];
+        assert_eq!(time["anyOf"][0]["maximum"].as_f64(), Some(86400.0));
+        let action = &schema["definitions"]["WatchdogAction"];
+        assert!(action.to_string().contains("restart-service"), "{}", action);
+    }
+
+    #[test]
+    fn string_and_integer_ports_are_accepted() {
+        let config = load_text("ports", "[imserver]\nport = \"1140\"\n[webserver]\nport = 1240\n").unwrap();
+        assert_eq!(config.imserver.port, 1140);
+        assert_eq!(config.webserver.port, 1240);
+    }
+
+    #[test]
+    fn zero_intervals_are_rejected() {
+        assert!(load_error("wd-time", "[wdserver]\ntime = 0\n").contains("wdserver.time must be 1-86400"));
+        assert!(load_error("cert-reload", "[imserver]\ncert_reload_secs = 0\n").contains("imserver.cert_reload_secs"));
+        assert!(load_error("systemd", "[systemd]\nstatus_interval_secs = 0\n").contains("systemd.status_interval_secs"));
+        assert!(load_error("stall", "[stall]\ninterval_ms = 0\n").contains("stall.interval_ms must be at least 10"));
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        assert!(load_error("wd-max", "[wdserver]\ntime = \"86401\"\n").contains("wdserver.time"));
+        assert!(load_error("port", "[opsserver]\nport = \"0\"\n").contains("opsserver.port"));
+        assert!(load_error("fallback", "[imserver.tcp_fallback]\nmax_connections = 0\n").contains("imserver.tcp_fallback.max_connections"));
+        assert!(load_error("sampling", "[otlp]\nsampling_ratio = 1.5\n").contains("otlp.sampling_ratio"));
+        assert!(load_error("redis", "[bitcomm]\nredis = \"http://localhost\"\n").contains("bitcomm.redis"));
+    }
+
+    #[test]
+    fn all_violations_are_reported() {
+        let error = load_error("many", "[wdserver]\ntime = 0\n[systemd]\nstatus_interval_secs = 0\n");
+        assert!(error.contains("wdserver.time") && error.contains("systemd.status_interval_secs"), "{}", error);
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        assert!(load_text("syntax", "[imserver\nport = 1").is_err());
+        assert!(load_text("type", "[imserver]\nport = \"quic\"\n").is_err());
+        assert!(load_text("overflow", "[imserver]\nport = 70000\n").is_err());
+    }
+}
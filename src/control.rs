@@ -0,0 +1,331 @@
+// 本地控制 socket
+//
+// Unix domain socket 上的行协议：每行一个 JSON 请求，对应一行 JSON 应答。
+// 同一套命令也通过运维监听的 /admin 接口提供，二者都调用 dispatch。
+//
+//   {"cmd":"set-log-level","filter":"debug","ttl_secs":300}
+//   {"ok":true,"data":{"previous":"info","current":"debug"}}
+
+use crate::config::ControlSection;
+use crate::journal::{ self, JournalQuery };
+use crate::logring::{ self, RecentLog };
+use crate::metrics::{ self, MetricsSummary };
+use crate::profiling::{ self, ProfileFormat };
+use crate::supervisor::{ self, ServiceStatus };
+use crate::{ buildinfo, systemd, telemetry };
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+use std::error::Error;
+use std::io;
+use std::os::unix::fs::{ DirBuilderExt, PermissionsExt };
+use std::path::{ Component, Path, PathBuf };
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::io::{ AsyncBufReadExt, AsyncWriteExt, BufReader };
+use tokio::net::{ UnixListener, UnixStream };
+use tracing::{ debug, info, Level };
+
+// status 中返回的最近告警条数
+const STATUS_WARNINGS: usize = 20;
+
+// CPU 剖析文件的输出目录，控制服务启动时设置；未设置时使用临时目录
+static PROFILE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// 控制命令
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "kebab-case")]
+pub enum ControlRequest {
+    /// 查询当前日志过滤规则
+    GetLogLevel,
+    /// 修改日志过滤规则，ttl_secs 到期后自动回退
+    SetLogLevel {
+        filter: String,
+        #[serde(default)]
+        ttl_secs: Option<u64>,
+    },
+    /// 查询运行状态快照 (服务、指标、最近告警)
+    Status,
+    /// 开始排空，就绪检查随即失败
+    Drain,
+    /// 重启指定服务
+    RestartService {
+        service: String,
+    },
+    /// 查询进程事件日志
+    Journal(JournalQuery),
+    /// 采样 CPU seconds 秒并写入 [control] profile_dir 下名为 output 的文件，缺省按时间生成文件名；应答中返回文件路径
+    CpuProfile {
+        seconds: u64,
+        #[serde(default)]
+        format: ProfileFormat,
+        #[serde(default)]
+        output: Option<PathBuf>,
+    },
+    /// 查询堆统计
+    HeapStats,
+}
+
+/// status 命令返回的运行状态快照
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusSnapshot {
+    pub pid: u32,
+    pub version: String,
+    pub log_filter: Option<String>,
+    pub draining: bool,
+    /// 是否因资源告警拒绝新连接
+    #[serde(default)]
+    pub shedding: bool,
+    pub services: Vec<ServiceStatus>,
+    pub metrics: MetricsSummary,
+    /// 最近的 WARN / ERROR 日志
+    pub warnings: Vec<RecentLog>,
+}
+
+impl StatusSnapshot {
+    fn collect() -> StatusSnapshot {
+        let info = buildinfo::build_info();
+        StatusSnapshot {
+            pid: std::process::id(),
+            version: format!("{} ({})", info.version, info.commit()),
+            log_filter: telemetry::log_filter(),
+            draining: supervisor::is_draining(),
+            shedding: supervisor::is_shedding(),
+            services: supervisor::services(),
+            metrics: metrics::summary(),
+            warnings: logring::recent(Level::WARN, STATUS_WARNINGS),
+        }
+    }
+}
+
+/// 控制命令应答
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub data: Value,
+}
+
+impl ControlResponse {
+    pub fn ok(data: Value) -> ControlResponse {
+        ControlResponse { ok: true, error: None, data }
+    }
+
+    pub fn error(message: impl ToString) -> ControlResponse {
+        ControlResponse { ok: false, error: Some(message.to_string()), data: Value::Null }
+    }
+}
+
+/// 执行一条控制命令
+pub async fn dispatch(request: ControlRequest) -> ControlResponse {
+    match request {
+        ControlRequest::GetLogLevel => ControlResponse::ok(serde_json::json!({ "current": telemetry::log_filter() })),
+        ControlRequest::SetLogLevel { filter, ttl_secs } => {
+            match telemetry::set_log_filter(&filter, ttl_secs.map(Duration::from_secs)) {
+                Ok(previous) =>
+                    ControlResponse::ok(
+                        serde_json::json!({ "previous": previous, "current": filter, "ttl_secs": ttl_secs })
+                    ),
+                Err(e) => ControlResponse::error(e),
+            }
+        }
+        ControlRequest::Status => {
+            match serde_json::to_value(StatusSnapshot::collect()) {
+                Ok(data) => ControlResponse::ok(data),
+                Err(e) => ControlResponse::error(e),
+            }
+        }
+        ControlRequest::Drain => {
+            let already = supervisor::begin_drain();
+            ControlResponse::ok(serde_json::json!({ "draining": true, "already_draining": already }))
+        }
+        ControlRequest::RestartService { service } => {
+            match supervisor::restart_service(&service) {
+                Ok(()) => ControlResponse::ok(serde_json::json!({ "service": service })),
+                Err(e) => ControlResponse::error(e),
+            }
+        }
+        ControlRequest::Journal(query) => ControlResponse::ok(serde_json::json!({ "events": journal::query(&query) })),
+        ControlRequest::CpuProfile { seconds, format, output } => {
+            let dir = PROFILE_DIR.get().cloned().unwrap_or_else(std::env::temp_dir);
+            let output = match profile_output(&dir, output, format) {
+                Ok(output) => output,
+                Err(e) => return ControlResponse::error(e),
+            };
+            let result = match profiling::cpu_profile(seconds, format).await {
+                Ok(profile) => write_profile(&output, &profile).await.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match result {
+                Ok(()) => ControlResponse::ok(serde_json::json!({ "output": output, "format": format, "seconds": seconds })),
+                Err(e) => ControlResponse::error(e),
+            }
+        }
+        ControlRequest::HeapStats => {
+            match serde_json::to_value(profiling::heap_stats()) {
+                Ok(data) => ControlResponse::ok(data),
+                Err(e) => ControlResponse::error(e),
+            }
+        }
+    }
+}
+
+// 剖析文件只能写入输出目录，请求中的 output 必须是单个文件名，避免经控制命令写到任意路径
+fn profile_output(dir: &Path, output: Option<PathBuf>, format: ProfileFormat) -> Result<PathBuf, String> {
+    let Some(output) = output else {
+        let stamp = chrono::Local::now().format("%Y%m%dT%H%M%S");
+        return Ok(dir.join(format!("bitcomm-{}-{}.{}", std::process::id(), stamp, format.extension())));
+    };
+    let mut components = output.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) => Ok(dir.join(name)),
+        _ => Err(format!("output must be a file name inside the profile directory {}, got {}", dir.display(), output.display())),
+    }
+}
+
+// 只创建新文件，不覆盖已有文件，也不跟随已有的符号链接
+async fn write_profile(path: &Path, profile: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut file = tokio::fs::OpenOptions::new().write(true).create_new(true).open(path).await?;
+    file.write_all(profile).await?;
+    file.flush().await
+}
+
+/// 启动控制 socket 服务，直到监听出错才返回
+pub async fn start_control_server(config: ControlSection) -> Result<(), Box<dyn Error>> {
+    let _ = PROFILE_DIR.set(config.profile_dir.clone());
+    let listener = match systemd::activated_unix_listener("ctlserver", &config.path) {
+        // 权限由 socket 单元的 SocketMode= 决定
+        Some(listener) => UnixListener::from_std(listener)?,
+        None => {
+            // 清理上次异常退出遗留的 socket 文件
+            if config.path.exists() {
+                std::fs::remove_file(&config.path)?;
+            }
+            bind_private(&config.path)?
+        }
+    };
+    info!("Control socket listening on {}", config.path.display());
+
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                supervisor::accept_error("ctlserver", e).await;
+                continue;
+            }
+        };
+        // Unix socket 对端没有地址，以对端进程号命名连接任务
+        let name = match stream.peer_cred().ok().and_then(|cred| cred.pid()) {
+            Some(pid) => format!("ctlserver/conn/pid-{}", pid),
+            None => "ctlserver/conn/unknown".to_string(),
+        };
+        supervisor::spawn_named(&name, async move {
+            if let Err(e) = handle_connection(stream).await {
+                debug!("control connection closed: {}", e);
+            }
+        });
+    }
+}
+
+// 仅允许同一用户访问：先在只有当前用户可进入的临时目录中绑定并设为 0600，再移动到目标路径，
+// 避免 bind 与 chmod 之间其他用户连接到控制 socket
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = path.file_name().ok_or_else(|| io::Error::other(format!("{} is not a file path", path.display())))?;
+    let staging = parent.join(format!(".{}.{}", name.to_string_lossy(), std::process::id()));
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+
+    let staged = staging.join("socket");
+    let result = UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_dir_all(&staging);
+    result
+}
+
+async fn handle_connection(stream: UnixStream) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => dispatch(request).await,
+            Err(e) => ControlResponse::error(format!("invalid request: {}", e)),
+        };
+        let mut text = serde_json::to_string(&response)?;
+        text.push('\n');
+        writer.write_all(text.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// 连接到运行中实例的控制 socket 并发送一条命令
+pub async fn send_request(path: &Path, request: &ControlRequest) -> Result<ControlResponse, Box<dyn Error>> {
+    let stream = UnixStream::connect(path).await?;
+    let (reader, mut writer) = stream.into_split();
+    let mut text = serde_json::to_string(request)?;
+    text.push('\n');
+    writer.write_all(text.as_bytes()).await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    let line = lines.next_line().await?.ok_or("control socket closed without response")?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn control_socket_is_private_from_the_start() {
+        let dir = std::env::temp_dir().join(format!("bitcomm-control-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bitcomm.sock");
+
+        let listener = bind_private(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o600);
+        // 临时目录已清理，只剩 socket 本身
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let (accepted, connected) = tokio::join!(listener.accept(), UnixStream::connect(&path));
+        accepted.unwrap();
+        connected.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn profile_output_stays_in_the_profile_directory() {
+        let dir = Path::new("/var/lib/bitcomm/profiles");
+        assert_eq!(
+            profile_output(dir, Some(PathBuf::from("cpu.pb")), ProfileFormat::Pprof).unwrap(),
+            dir.join("cpu.pb")
+        );
+        let generated = profile_output(dir, None, ProfileFormat::Flamegraph).unwrap();
+        assert_eq!(generated.parent(), Some(dir));
+        assert_eq!(generated.extension().and_then(|ext| ext.to_str()), Some(ProfileFormat::Flamegraph.extension()));
+
+        for output in ["/etc/passwd", "../cpu.pb", "sub/cpu.pb", "..", ""] {
+            assert!(profile_output(dir, Some(PathBuf::from(output)), ProfileFormat::Pprof).is_err(), "{}", output);
+        }
+    }
+
+    #[tokio::test]
+    async fn profiles_never_overwrite_existing_files() {
+        let dir = std::env::temp_dir().join(format!("bitcomm-profiles-{}", std::process::id()));
+        let path = dir.join("cpu.pb");
+        write_profile(&path, b"first").await.unwrap();
+        assert_eq!(write_profile(&path, b"second").await.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
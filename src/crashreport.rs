@@ -0,0 +1,218 @@
+// panic 崩溃报告
+//
+// 全局 panic hook 在默认输出之外，把服务名、线程、backtrace、构建信息、配置摘要
+// 和 panic 前的最近日志写入 [crash] directory 下的一个 JSON 文件，并记入事件日志。
+// 下次启动时列出尚未上报过的报告，列出后标记为已上报。
+// 进程被信号终止 (OOM kill、abort) 时 panic hook 不会执行，由 `bitcomm supervise` 代为写入报告并清理旧报告。
+
+use crate::buildinfo::{ self, BuildInfo };
+use crate::config::{ BitcommConfig, CrashSection };
+use crate::journal::{ self, EventKind };
+use crate::logring::{ self, RecentLog };
+use crate::supervisor;
+use serde::Serialize;
+use serde_json::Value;
+use std::backtrace::Backtrace;
+use std::error::Error;
+use std::fs;
+use std::panic::PanicHookInfo;
+use std::path::{ Path, PathBuf };
+use std::sync::OnceLock;
+use tracing::{ error, warn, Level };
+
+// 报告文件名前缀
+const REPORT_PREFIX: &str = "crash-";
+
+/// 崩溃报告
+#[derive(Debug, Serialize)]
+pub struct CrashReport {
+    /// RFC 3339 时间
+    pub timestamp: String,
+    pub pid: u32,
+    /// 崩溃的服务，无法判断时为 unknown
+    pub service: String,
+    pub thread: String,
+    pub message: String,
+    /// panic 位置 file:line:column
+    pub location: Option<String>,
+    pub backtrace: String,
+    pub build: BuildInfo,
+    /// 生效配置的 SHA-256
+    pub config_hash: String,
+    /// panic 前的最近日志
+    pub recent_logs: Vec<RecentLog>,
+    /// 是否已在启动时上报
+    pub reported: bool,
+}
+
+struct CrashContext {
+    directory: PathBuf,
+    recent_lines: usize,
+    config_hash: String,
+}
+
+static CRASH: OnceLock<CrashContext> = OnceLock::new();
+
+/// 安装 panic hook，保留原有 hook 的 stderr 输出
+pub fn install(config: &BitcommConfig) {
+    if !config.crash.enable {
+        return;
+    }
+    let context = CrashContext {
+        directory: config.crash.directory.clone(),
+        recent_lines: config.crash.recent_lines,
+        config_hash: config.digest(),
+    };
+    if CRASH.set(context).is_err() {
+        return;
+    }
+
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(
+        Box::new(move |info| {
+            previous(info);
+            let Some(context) = CRASH.get() else {
+                return;
+            };
+            let report = build_report(context, info);
+            let service = report.service.clone();
+            match write_report(&context.directory, &report) {
+                Ok(path) => {
+                    journal::record(
+                        EventKind::Crashed,
+                        Some(&service),
+                        format!("{} ({})", report.message, path.display())
+                    );
+                    error!(service = %service, report = %path.display(), "panicked: {}", report.message);
+                }
+                Err(e) => {
+                    journal::record(EventKind::Crashed, Some(&service), report.message.clone());
+                    eprintln!("failed to write crash report to {}: {}", context.directory.display(), e);
+                }
+            }
+        })
+    );
+}
+
+fn build_report(context: &CrashContext, info: &PanicHookInfo<'_>) -> CrashReport {
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string());
+    let thread = std::thread::current();
+    let thread = match thread.name() {
+        Some(name) => format!("{} ({:?})", name, thread.id()),
+        None => format!("{:?}", thread.id()),
+    };
+
+    CrashReport {
+        timestamp: chrono::Local::now().to_rfc3339(),
+        pid: std::process::id(),
+        service: supervisor::current_service().unwrap_or("unknown").to_string(),
+        thread,
+        message,
+        location: info.location().map(|location| location.to_string()),
+        backtrace: Backtrace::force_capture().to_string(),
+        build: buildinfo::build_info(),
+        config_hash: context.config_hash.clone(),
+        recent_logs: logring::try_recent(Level::TRACE, context.recent_lines).unwrap_or_default(),
+        reported: false,
+    }
+}
+
+fn write_report(directory: &Path, report: &CrashReport) -> Result<PathBuf, Box<dyn Error>> {
+    fs::create_dir_all(directory)?;
+    let stamp = chrono::Local::now().format("%Y%m%dT%H%M%S%.3f");
+    let path = directory.join(format!("{}{}-{}.json", REPORT_PREFIX, stamp, report.pid));
+    fs::write(&path, serde_json::to_string_pretty(report)?)?;
+    Ok(path)
+}
+
+// 目录中的报告，文件名以时间开头，排序后即按时间先后
+fn list_reports(directory: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            name.starts_with(REPORT_PREFIX) && name.ends_with(".json")
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// 为没有留下报告就退出的进程 (被信号终止) 写入报告；该进程已有报告时返回 None
+pub fn write_exit_report(config: &BitcommConfig, pid: u32, message: &str) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let suffix = format!("-{}.json", pid);
+    let reported = list_reports(&config.crash.directory)
+        .iter()
+        .any(|path| path.to_string_lossy().ends_with(&suffix));
+    if reported {
+        return Ok(None);
+    }
+    let report = CrashReport {
+        timestamp: chrono::Local::now().to_rfc3339(),
+        pid,
+        service: "unknown".to_string(),
+        thread: "-".to_string(),
+        message: message.to_string(),
+        location: None,
+        backtrace: String::new(),
+        build: buildinfo::build_info(),
+        config_hash: config.digest(),
+        recent_logs: Vec::new(),
+        reported: false,
+    };
+    write_report(&config.crash.directory, &report).map(Some)
+}
+
+/// 只保留最近的 max_reports 个报告，返回删除的个数
+pub fn rotate(config: &CrashSection) -> usize {
+    if config.max_reports == 0 {
+        return 0;
+    }
+    let paths = list_reports(&config.directory);
+    let excess = paths.len().saturating_sub(config.max_reports);
+    let mut removed = 0;
+    for path in paths.iter().take(excess) {
+        match fs::remove_file(path) {
+            Ok(()) => removed += 1,
+            Err(e) => warn!("failed to remove crash report {}: {}", path.display(), e),
+        }
+    }
+    removed
+}
+
+/// 列出尚未上报的崩溃报告并标记为已上报，返回报告路径
+pub fn report_pending(config: &BitcommConfig) -> Vec<PathBuf> {
+    let mut pending = Vec::new();
+    for path in list_reports(&config.crash.directory) {
+        let report = fs::read_to_string(&path).ok().and_then(|text| serde_json::from_str::<Value>(&text).ok());
+        let Some(mut report) = report else {
+            warn!("unreadable crash report {}", path.display());
+            continue;
+        };
+        if report["reported"].as_bool().unwrap_or(false) {
+            continue;
+        }
+        warn!(
+            report = %path.display(),
+            service = report["service"].as_str().unwrap_or("unknown"),
+            timestamp = report["timestamp"].as_str().unwrap_or_default(),
+            "previous run crashed: {}",
+            report["message"].as_str().unwrap_or_default()
+        );
+        report["reported"] = Value::Bool(true);
+        let text = serde_json::to_string_pretty(&report).unwrap_or_default();
+        if let Err(e) = fs::write(&path, text) {
+            warn!("failed to mark crash report {} as reported: {}", path.display(), e);
+        }
+        pending.push(path);
+    }
+    pending
+}
@@ -0,0 +1,279 @@
+// 开发证书 (`bitcomm gen-cert`)
+//
+// 在输出目录中生成：
+// - ca.pem / ca-key.pem：自签名的开发 CA，目录中已有时沿用，开发者只需信任一次；
+// - imserver.pem / imserver-key.pem、webserver.pem / webserver-key.pem：由该 CA 签发的服务器证书，
+//   SAN 包含配置中的监听地址 (监听所有地址时为 localhost、127.0.0.1、::1 和本机主机名) 以及 --san 指定的名称。
+// 然后在开发配置中把 [imserver] cert_path / key_path 指向生成的证书，配置不存在时以 -c 指定的配置为模板创建，
+// 其余内容和注释保持不变。Web 服务器证书由 btcmweb 自行加载，这里只生成文件。
+// 仅用于开发和集成测试，不要在生产环境使用。
+
+use crate::alerting;
+use crate::config::BitcommConfig;
+use rcgen::{ BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose };
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{ Path, PathBuf };
+
+/// CA 证书的有效天数
+const CA_DAYS: i64 = 3650;
+
+/// gen-cert 的参数
+#[derive(Debug, Clone)]
+pub struct GenCertOptions {
+    /// 输出目录
+    pub dir: PathBuf,
+    /// 额外的主机名或 IP
+    pub sans: Vec<String>,
+    /// 要更新的开发配置
+    pub profile: PathBuf,
+    /// 创建开发配置时使用的模板
+    pub template: PathBuf,
+    /// 服务器证书的有效天数
+    pub days: u32,
+    /// 重新生成 CA
+    pub new_ca: bool,
+}
+
+/// 生成的一张服务器证书
+#[derive(Debug, Clone)]
+pub struct IssuedCert {
+    pub server: &'static str,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub names: Vec<String>,
+}
+
+/// gen-cert 的结果
+#[derive(Debug, Clone)]
+pub struct GenCertOutput {
+    pub ca_path: PathBuf,
+    /// CA 是否沿用了目录中已有的文件
+    pub ca_reused: bool,
+    pub certs: Vec<IssuedCert>,
+    /// 是否新建了开发配置
+    pub profile_created: bool,
+}
+
+/// 生成证书并更新开发配置
+pub fn generate(options: &GenCertOptions, config: &BitcommConfig) -> Result<GenCertOutput, Box<dyn Error>> {
+    fs::create_dir_all(&options.dir)?;
+    let ca_path = options.dir.join("ca.pem");
+    let ca_key_path = options.dir.join("ca-key.pem");
+
+    let ca_reused = !options.new_ca && ca_path.exists() && ca_key_path.exists();
+    let (ca, ca_key) = if ca_reused {
+        load_ca(&ca_path, &ca_key_path)?
+    } else {
+        let (ca, ca_key) = new_ca()?;
+        write_file(&ca_path, &ca.pem(), 0o644)?;
+        write_file(&ca_key_path, &ca_key.serialize_pem(), 0o600)?;
+        (ca, ca_key)
+    };
+
+    let servers = [("imserver", config.imserver.ip), ("webserver", config.webserver.ip)];
+    let mut certs = Vec::new();
+    for (server, ip) in servers {
+        let names = subject_alt_names(ip, &options.sans);
+        let (cert, key) = issue(server, &names, options.days, &ca, &ca_key)?;
+        let cert_path = options.dir.join(format!("{}.pem", server));
+        let key_path = options.dir.join(format!("{}-key.pem", server));
+        write_file(&cert_path, &cert.pem(), 0o644)?;
+        write_file(&key_path, &key.serialize_pem(), 0o600)?;
+        certs.push(IssuedCert { server, cert_path, key_path, names });
+    }
+
+    let imserver = &certs[0];
+    let profile_created = update_profile(&options.profile, &options.template, &imserver.cert_path, &imserver.key_path)?;
+    // 确认更新后的配置仍能加载
+    BitcommConfig::load(&options.profile).map_err(|e| format!("{}: {}", options.profile.display(), e))?;
+
+    Ok(GenCertOutput { ca_path, ca_reused, certs, profile_created })
+}
+
+// 监听所有地址时证书覆盖本机常用的名称，否则只覆盖监听地址
+fn subject_alt_names(ip: IpAddr, extra: &[String]) -> Vec<String> {
+    let mut names = BTreeSet::new();
+    if ip.is_unspecified() {
+        names.extend(["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()]);
+        let host = alerting::hostname();
+        if host != "unknown" && !host.is_empty() {
+            names.insert(host);
+        }
+    } else {
+        names.insert(ip.to_string());
+    }
+    names.extend(extra.iter().map(|name| name.trim().to_string()).filter(|name| !name.is_empty()));
+    names.into_iter().collect()
+}
+
+fn new_ca() -> Result<(Certificate, KeyPair), Box<dyn Error>> {
+    let key = KeyPair::generate()?;
+    let mut params = CertificateParams::new(Vec::<String>::new())?;
+    params.distinguished_name.push(DnType::OrganizationName, "bitcomm development");
+    params.distinguished_name.push(DnType::CommonName, format!("bitcomm dev CA ({})", alerting::hostname()));
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign, KeyUsagePurpose::DigitalSignature];
+    set_validity(&mut params, CA_DAYS);
+    Ok((params.self_signed(&key)?, key))
+}
+
+// 从目录中的 PEM 还原 CA，用于签发新的服务器证书
+fn load_ca(ca_path: &Path, ca_key_path: &Path) -> Result<(Certificate, KeyPair), Box<dyn Error>> {
+    let key = KeyPair::from_pem(&fs::read_to_string(ca_key_path)?).map_err(|e| format!("{}: {}", ca_key_path.display(), e))?;
+    let params = CertificateParams::from_ca_cert_pem(&fs::read_to_string(ca_path)?).map_err(|e| format!("{}: {}", ca_path.display(), e))?;
+    Ok((params.self_signed(&key)?, key))
+}
+
+fn issue(server: &str, names: &[String], days: u32, ca: &Certificate, ca_key: &KeyPair) -> Result<(Certificate, KeyPair), Box<dyn Error>> {
+    let key = KeyPair::generate()?;
+    let mut params = CertificateParams::new(names.to_vec())?;
+    params.distinguished_name.push(DnType::OrganizationName, "bitcomm development");
+    params.distinguished_name.push(DnType::CommonName, format!("bitcomm {}", server));
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature, KeyUsagePurpose::KeyEncipherment];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.use_authority_key_identifier_extension = true;
+    set_validity(&mut params, days as i64);
+    Ok((params.signed_by(&key, ca, ca_key)?, key))
+}
+
+// 从前一天开始生效，避免时钟偏差导致证书尚未生效
+fn set_validity(params: &mut CertificateParams, days: i64) {
+    let today = chrono::Utc::now().date_naive();
+    let ymd = |date: chrono::NaiveDate| {
+        use chrono::Datelike;
+        rcgen::date_time_ymd(date.year(), date.month() as u8, date.day() as u8)
+    };
+    params.not_before = ymd(today - chrono::Duration::days(1));
+    params.not_after = ymd(today + chrono::Duration::days(days));
+}
+
+// 只修改 [imserver] 的证书路径，保留其余内容和注释；返回是否新建了配置
+fn update_profile(profile: &Path, template: &Path, cert_path: &Path, key_path: &Path) -> Result<bool, Box<dyn Error>> {
+    let created = !profile.exists();
+    let text = if !created {
+        fs::read_to_string(profile)?
+    } else if template.exists() {
+        fs::read_to_string(template)?
+    } else {
+        String::new()
+    };
+    let mut document: toml_edit::DocumentMut = text.parse().map_err(|e| format!("{}: {}", profile.display(), e))?;
+    let imserver = document
+        .entry("imserver")
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .ok_or_else(|| format!("{}: imserver is not a table", profile.display()))?;
+    imserver.insert("cert_path", toml_edit::value(cert_path.to_string_lossy().as_ref()));
+    imserver.insert("key_path", toml_edit::value(key_path.to_string_lossy().as_ref()));
+    // 开发 CA 就是信任根，不需要随证书发送
+    imserver.remove("ca_path");
+    fs::write(profile, document.to_string())?;
+    Ok(created)
+}
+
+fn write_file(path: &Path, contents: &str, mode: u32) -> Result<(), Box<dyn Error>> {
+    // 私钥文件只允许当前用户读取；覆盖已有文件时一并收紧权限
+    let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(mode).open(path)?;
+    fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(mode))?;
+    file.write_all(contents.as_bytes()).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::client::danger::ServerCertVerifier;
+    use rustls::client::WebPkiServerVerifier;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{ CertificateDer, ServerName, UnixTime };
+    use rustls::RootCertStore;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Arc;
+
+    fn options(dir: &Path) -> GenCertOptions {
+        GenCertOptions {
+            dir: dir.join("certs"),
+            sans: vec![" chat.example.test ".to_string(), String::new()],
+            profile: dir.join("server.dev.toml"),
+            template: dir.join("server.toml"),
+            days: 30,
+            new_ca: false,
+        }
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    // 用生成的 CA 校验服务器证书对 name 有效
+    fn verify(ca_path: &Path, cert_path: &Path, name: &str) -> Result<(), rustls::Error> {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file(ca_path).unwrap()).unwrap();
+        let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), Arc::new(rustls::crypto::ring::default_provider()))
+            .build()
+            .unwrap();
+        let cert = CertificateDer::from_pem_file(cert_path).unwrap();
+        verifier.verify_server_cert(&cert, &[], &ServerName::try_from(name.to_string()).unwrap(), &[], UnixTime::now()).map(|_| ())
+    }
+
+    #[test]
+    fn generates_a_ca_signed_profile() {
+        let dir = std::env::temp_dir().join(format!("bitcomm-devcert-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("server.toml"), "# 模板注释\n[imserver]\nport = \"1130\"\nca_path = \"old-chain.pem\"\n").unwrap();
+        let options = options(&dir);
+
+        let output = generate(&options, &BitcommConfig::default()).unwrap();
+        assert!(!output.ca_reused);
+        assert!(output.profile_created);
+        assert_eq!(mode(&options.dir.join("ca-key.pem")), 0o600);
+        assert_eq!(output.certs.iter().map(|cert| cert.server).collect::<Vec<_>>(), vec!["imserver", "webserver"]);
+        for cert in &output.certs {
+            assert_eq!(mode(&cert.key_path), 0o600);
+            assert!(cert.names.contains(&"chat.example.test".to_string()), "{:?}", cert.names);
+            for name in ["localhost", "127.0.0.1", "chat.example.test"] {
+                verify(&output.ca_path, &cert.cert_path, name).unwrap();
+            }
+            assert!(verify(&output.ca_path, &cert.cert_path, "other.example.test").is_err());
+        }
+
+        // 开发配置基于模板创建，指向生成的证书
+        let text = fs::read_to_string(&options.profile).unwrap();
+        assert!(text.starts_with("# 模板注释\n"), "{}", text);
+        let profile = BitcommConfig::load(&options.profile).unwrap();
+        assert_eq!(profile.imserver.cert_path.as_deref(), Some(output.certs[0].cert_path.as_path()));
+        assert_eq!(profile.imserver.key_path.as_deref(), Some(output.certs[0].key_path.as_path()));
+        assert_eq!(profile.imserver.ca_path, None);
+
+        // 再次生成时沿用 CA 和已有的开发配置，新证书仍由同一 CA 签发
+        let ca = fs::read(&output.ca_path).unwrap();
+        fs::write(&options.profile, format!("{}# 开发者的修改\n", text)).unwrap();
+        let again = generate(&options, &BitcommConfig::default()).unwrap();
+        assert!(again.ca_reused);
+        assert!(!again.profile_created);
+        assert_eq!(fs::read(&again.ca_path).unwrap(), ca);
+        assert!(fs::read_to_string(&options.profile).unwrap().contains("# 开发者的修改"));
+        verify(&again.ca_path, &again.certs[0].cert_path, "localhost").unwrap();
+
+        // --new-ca 重新生成 CA
+        let renewed = generate(&GenCertOptions { new_ca: true, ..options.clone() }, &BitcommConfig::default()).unwrap();
+        assert!(!renewed.ca_reused);
+        assert_ne!(fs::read(&renewed.ca_path).unwrap(), ca);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn names_follow_the_listen_address() {
+        let specific = subject_alt_names("10.1.2.3".parse().unwrap(), &["b.test".to_string(), "a.test ".to_string(), "b.test".to_string()]);
+        assert_eq!(specific, vec!["10.1.2.3", "a.test", "b.test"]);
+
+        let any = subject_alt_names("0.0.0.0".parse().unwrap(), &[]);
+        for name in ["localhost", "127.0.0.1", "::1"] {
+            assert!(any.contains(&name.to_string()), "{:?}", any);
+        }
+    }
+}
@@ -0,0 +1,360 @@
+// 健康检查
+//
+// /healthz 存活检查：只看进程内部状态 (Watch Dog 心跳、运行时卡顿)，失败意味着需要重启进程；
+// /readyz  就绪检查：未在排空、未因资源告警拒绝新连接、各服务在运行、IM 端口已绑定、NATS / Redis / 数据库可达。
+// 两个接口都返回各组件的 JSON 明细，全部通过时 200，否则 503。
+// 健康检查单独监听，不经过公网的 Web Admin Server。
+
+use crate::config::{ BitcommConfig, HealthSection };
+use crate::journal::{ self, EventKind };
+use crate::{ metrics, stall, systemd };
+use crate::supervisor::{ self, ServiceState };
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{ IntoResponse, Response };
+use axum::routing::get;
+use axum::{ middleware, Json, Router };
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+use std::net::{ IpAddr, SocketAddr };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+use tokio::io::{ AsyncBufReadExt, AsyncWriteExt, BufReader };
+use tokio::net::TcpStream;
+use tracing::info;
+
+/// 就绪检查要求处于运行状态的服务
+const READY_SERVICES: &[&str] = &["mqserver", "imserver", "webserver", "wdserver"];
+
+// 上一次 /readyz 的结果
+static LAST_READY: AtomicBool = AtomicBool::new(false);
+
+/// 检查结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Fail,
+}
+
+/// 单个组件的检查结果
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    pub detail: String,
+}
+
+impl ComponentHealth {
+    fn ok(detail: impl ToString) -> ComponentHealth {
+        ComponentHealth { status: HealthStatus::Ok, detail: detail.to_string() }
+    }
+
+    fn fail(detail: impl ToString) -> ComponentHealth {
+        ComponentHealth { status: HealthStatus::Fail, detail: detail.to_string() }
+    }
+}
+
+/// 检查报告，任一组件失败则整体失败
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub components: BTreeMap<String, ComponentHealth>,
+}
+
+impl HealthReport {
+    fn new(components: BTreeMap<String, ComponentHealth>) -> HealthReport {
+        let healthy = components.values().all(|component| component.status == HealthStatus::Ok);
+        HealthReport { status: if healthy { HealthStatus::Ok } else { HealthStatus::Fail }, components }
+    }
+}
+
+/// 存活检查
+pub fn liveness(config: &HealthSection) -> HealthReport {
+    let mut components = BTreeMap::new();
+    components.insert("wdserver.heartbeat".to_string(), check_heartbeat("wdserver", config));
+    components.insert("runtime.scheduling".to_string(), check_stall());
+    HealthReport::new(components)
+}
+
+/// 就绪检查
+pub async fn readiness(config: &BitcommConfig) -> HealthReport {
+    let mut components = BTreeMap::new();
+    components.insert(
+        "drain".to_string(),
+        if supervisor::is_draining() { ComponentHealth::fail("draining") } else { ComponentHealth::ok("accepting traffic") }
+    );
+    components.insert(
+        "load".to_string(),
+        if supervisor::is_shedding() { ComponentHealth::fail("shedding new connections") } else { ComponentHealth::ok("within limits") }
+    );
+    for service in READY_SERVICES {
+        components.insert(service.to_string(), check_service(service));
+    }
+    components.insert("wdserver.heartbeat".to_string(), check_heartbeat("wdserver", &config.health));
+    components.insert(
+        "imserver.listener".to_string(),
+        check_udp_bound(SocketAddr::new(config.imserver.ip, config.imserver.port))
+    );
+
+    let timeout = Duration::from_millis(config.health.check_timeout_ms);
+    let (nats, redis, database) = tokio::join!(
+        check_nats(&config.bitcomm.nats, timeout),
+        check_redis(&config.bitcomm.redis, timeout),
+        check_database(&config.bitcomm.database, timeout)
+    );
+    components.insert("nats".to_string(), nats);
+    components.insert("redis".to_string(), redis);
+    if let Some(database) = database {
+        components.insert("database".to_string(), database);
+    }
+    HealthReport::new(components)
+}
+
+fn check_service(service: &str) -> ComponentHealth {
+    match supervisor::service_status(service) {
+        Some(status) if status.state == ServiceState::Running => ComponentHealth::ok("running"),
+        Some(status) => ComponentHealth::fail(format!("{:?}", status.state).to_lowercase()),
+        None => ComponentHealth::fail("not started"),
+    }
+}
+
+fn check_heartbeat(service: &str, config: &HealthSection) -> ComponentHealth {
+    let age = supervisor::service_status(service).and_then(|status| status.heartbeat_age_secs);
+    match age {
+        Some(age) if age <= (config.heartbeat_timeout as f64) => ComponentHealth::ok(format!("last heartbeat {:.1}s ago", age)),
+        Some(age) => ComponentHealth::fail(format!("last heartbeat {:.1}s ago", age)),
+        None => ComponentHealth::fail("no heartbeat"),
+    }
+}
+
+fn check_stall() -> ComponentHealth {
+    let status = stall::status();
+    let detail = format!("max scheduling delay {:.1}ms, {} stalls", status.last_max_delay_ms, status.stalls);
+    if status.stalled { ComponentHealth::fail(detail) } else { ComponentHealth::ok(detail) }
+}
+
+// IM 服务绑定的是 UDP 端口：在 /proc/net/udp 和 /proc/net/udp6 中查找，不自己绑定，以免与正在启动的 imserver 争用端口
+fn check_udp_bound(addr: SocketAddr) -> ComponentHealth {
+    match udp_bound(addr) {
+        Ok(true) => ComponentHealth::ok(format!("listening on udp {}", addr)),
+        Ok(false) => ComponentHealth::fail(format!("nothing is listening on udp {}", addr)),
+        Err(e) => ComponentHealth::fail(e),
+    }
+}
+
+fn udp_bound(addr: SocketAddr) -> io::Result<bool> {
+    for table in ["/proc/net/udp", "/proc/net/udp6"] {
+        let content = match std::fs::read_to_string(table) {
+            Ok(content) => content,
+            // 未启用 IPv6 时没有 udp6
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let mut locals = content.lines().skip(1).filter_map(|line| line.split_whitespace().nth(1)).filter_map(proc_net_addr);
+        // 绑定在通配地址上的 socket 覆盖所有地址
+        if locals.any(|local| {
+            let (local_ip, ip) = (local.ip().to_canonical(), addr.ip().to_canonical());
+            local.port() == addr.port() && (local_ip == ip || local_ip.is_unspecified() || ip.is_unspecified())
+        }) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// /proc/net/udp(6) 的 local_address：十六进制的地址和端口，地址按 32 位字以主机字节序打印
+fn proc_net_addr(field: &str) -> Option<SocketAddr> {
+    let (ip, port) = field.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let mut octets = Vec::with_capacity(16);
+    for word in 0..ip.len() / 8 {
+        octets.extend(u32::from_str_radix(ip.get(word * 8..word * 8 + 8)?, 16).ok()?.to_ne_bytes());
+    }
+    let ip = match octets.len() {
+        4 => IpAddr::from(<[u8; 4]>::try_from(octets).ok()?),
+        16 => IpAddr::from(<[u8; 16]>::try_from(octets).ok()?),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+// NATS 服务端在连接建立后首先发送 INFO
+async fn check_nats(url: &str, timeout: Duration) -> ComponentHealth {
+    match nats_round_trip(url, timeout).await {
+        Ok(elapsed) => ComponentHealth::ok(format!("{} in {:?}", url, elapsed)),
+        Err(e) => ComponentHealth::fail(e),
+    }
+}
+
+// Redis PING，未认证时返回 -NOAUTH 也说明服务可用
+async fn check_redis(url: &str, timeout: Duration) -> ComponentHealth {
+    match redis_round_trip(url, timeout).await {
+        Ok(elapsed) => ComponentHealth::ok(format!("{} in {:?}", url, elapsed)),
+        Err(e) => ComponentHealth::fail(e),
+    }
+}
+
+/// 连接 NATS 并读到 INFO 问候所用的时间
+pub async fn nats_round_trip(url: &str, timeout: Duration) -> Result<Duration, String> {
+    let Some(addr) = host_port(url, 4222) else {
+        return Err(format!("invalid url {}", url));
+    };
+    let start = Instant::now();
+    let result = tokio::time::timeout(timeout, async {
+        let stream = TcpStream::connect(&addr).await?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await?;
+        Ok::<_, io::Error>(line)
+    }).await;
+    match result {
+        Ok(Ok(line)) if line.starts_with("INFO") => Ok(start.elapsed()),
+        Ok(Ok(line)) => Err(format!("{} unexpected greeting {:?}", addr, line.trim())),
+        Ok(Err(e)) => Err(format!("{} {}", addr, e)),
+        Err(_) => Err(format!("{} timed out", addr)),
+    }
+}
+
+/// 连接 Redis 并完成一次 PING 所用的时间，未认证时返回 -NOAUTH 也视为成功
+pub async fn redis_round_trip(url: &str, timeout: Duration) -> Result<Duration, String> {
+    let Some(addr) = host_port(url, 6379) else {
+        return Err(format!("invalid url {}", url));
+    };
+    let start = Instant::now();
+    let result = tokio::time::timeout(timeout, async {
+        let mut stream = TcpStream::connect(&addr).await?;
+        stream.write_all(b"PING\r\n").await?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await?;
+        Ok::<_, io::Error>(line)
+    }).await;
+    match result {
+        Ok(Ok(line)) if line.starts_with("+PONG") || line.starts_with("-NOAUTH") => Ok(start.elapsed()),
+        Ok(Ok(line)) => Err(format!("{} unexpected reply {:?}", addr, line.trim())),
+        Ok(Err(e)) => Err(format!("{} {}", addr, e)),
+        Err(_) => Err(format!("{} timed out", addr)),
+    }
+}
+
+// 数据库只检查 TCP 可达，未配置时跳过
+async fn check_database(url: &str, timeout: Duration) -> Option<ComponentHealth> {
+    if url.is_empty() {
+        return None;
+    }
+    let default_port = if url.starts_with("mysql") { 3306 } else { 5432 };
+    let Some(addr) = host_port(url, default_port) else {
+        return Some(ComponentHealth::fail("invalid url"));
+    };
+    let start = Instant::now();
+    let health = match tokio::time::timeout(timeout, TcpStream::connect(&addr)).await {
+        Ok(Ok(_)) => ComponentHealth::ok(format!("{} in {:?}", addr, start.elapsed())),
+        Ok(Err(e)) => ComponentHealth::fail(format!("{} {}", addr, e)),
+        Err(_) => ComponentHealth::fail(format!("{} timed out", addr)),
+    };
+    Some(health)
+}
+
+/// 从 scheme://user@host:port/path 形式的地址中取出 host:port，缺省端口用 default_port
+pub fn host_port(url: &str, default_port: u16) -> Option<String> {
+    let rest = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let authority = rest.split(['/', '?']).next()?;
+    let authority = authority.rsplit_once('@').map(|(_, host)| host).unwrap_or(authority);
+    // NATS 允许逗号分隔多个地址，只检查第一个
+    let authority = authority.split(',').next()?;
+    if authority.is_empty() {
+        return None;
+    }
+    let has_port = authority.rfind(':').is_some_and(|index| !authority[index..].contains(']'));
+    if has_port {
+        Some(authority.to_string())
+    } else {
+        Some(format!("{}:{}", authority, default_port))
+    }
+}
+
+/// 健康检查路由
+pub fn health_router(config: Arc<BitcommConfig>) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route_layer(middleware::from_fn(metrics::track_http_metrics))
+        .route_layer(middleware::from_fn_with_state("hcserver", supervisor::track_work))
+        .with_state(config)
+}
+
+fn report_response(report: HealthReport) -> Response {
+    let status = match report.status {
+        HealthStatus::Ok => StatusCode::OK,
+        HealthStatus::Fail => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(report)).into_response()
+}
+
+/// GET /healthz
+async fn healthz(State(config): State<Arc<BitcommConfig>>) -> Response {
+    report_response(liveness(&config.health))
+}
+
+/// GET /readyz
+async fn readyz(State(config): State<Arc<BitcommConfig>>) -> Response {
+    let report = readiness(&config).await;
+    record_transition(&report);
+    report_response(report)
+}
+
+// 就绪状态变化时记入事件日志；启动阶段的失败不记录，只记录首次就绪及之后的变化
+fn record_transition(report: &HealthReport) {
+    let ready = report.status == HealthStatus::Ok;
+    let was_ready = LAST_READY.swap(ready, Ordering::SeqCst);
+    if ready && !was_ready {
+        journal::record(EventKind::Ready, None, "readiness check passed");
+    } else if !ready && was_ready {
+        let failed: Vec<&str> = report.components
+            .iter()
+            .filter(|(_, component)| component.status == HealthStatus::Fail)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        journal::record(EventKind::NotReady, None, format!("failing: {}", failed.join(",")));
+    }
+}
+
+/// 启动健康检查 HTTP 服务，直到监听出错才返回
+pub async fn start_health_server(config: Arc<BitcommConfig>) -> Result<(), Box<dyn Error>> {
+    let addr = SocketAddr::new(config.health.ip, config.health.port);
+    let listener = match systemd::activated_tcp_listener("hcserver", addr) {
+        Some(listener) => tokio::net::TcpListener::from_std(listener)?,
+        None => tokio::net::TcpListener::bind(addr).await?,
+    };
+    info!("Health Server listening on {}", addr);
+
+    axum::serve(listener, health_router(config)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{ Ipv4Addr, Ipv6Addr, UdpSocket };
+
+    #[test]
+    fn bound_udp_ports_are_found_without_binding() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = socket.local_addr().unwrap();
+        assert!(udp_bound(addr).unwrap());
+        assert!(udp_bound(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), addr.port())).unwrap(), "configured to listen on all addresses");
+        assert!(!udp_bound(SocketAddr::new(Ipv4Addr::new(127, 0, 0, 2).into(), addr.port())).unwrap(), "bound to another address");
+        assert_eq!(check_udp_bound(addr).status, HealthStatus::Ok);
+
+        // 双栈的通配地址覆盖 IPv4
+        if let Ok(socket) = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)) {
+            let port = socket.local_addr().unwrap().port();
+            assert!(udp_bound(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port)).unwrap());
+            assert!(udp_bound(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)).unwrap());
+        }
+
+        drop(socket);
+        assert_eq!(check_udp_bound(addr).status, HealthStatus::Fail);
+    }
+}
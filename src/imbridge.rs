@@ -0,0 +1,559 @@
+// 把 bitcomm 接入的 IM 连接转接到本机 QUIC 端点
+//
+// 会话登记、JWT 认证和消息路由都在 btcmnetwork 的 imserver 中，它只接受 QUIC 连接，启动入口也不接受外部交来的连接。
+// TCP+TLS 备用传输 (imtcp) 和 WebSocket 网关 (imws) 接入的连接由这里转接：每个客户端连接对应一条到 [imserver] port 的
+// QUIC 连接，在其上打开一个双向流，两个方向原样复制字节 (WebSocket 上为二进制消息的内容)。
+// 这里假定 IM 协议在每个 QUIC 连接上只使用一条由客户端打开的双向流；协议需要多条流或数据报时，这两种传输无法承载。
+// 该假定尚未对照 btcmnetwork 的协议实现确认，启用备用传输或 WebSocket 网关前需先确认。
+//
+// imserver 看到的对端是本机地址。[imserver.bridge] 设置了 identity_secret 时，每条流在客户端数据之前先写入一行身份前导：
+//   BITCOMM-BRIDGE/1 ts=<Unix 秒> transport=<tcp-tls|websocket> [peer=<客户端地址>] [user=<用户 ID 的 UTF-8 hex>] mac=<hex>\n
+// mac 为 HMAC-SHA256(identity_secret, " mac=" 之前的内容)。imserver 须用同一密钥校验 (参考 verify_preamble)，
+// 通过后以 transport 和 peer 登记会话 (代替本机地址)，并把会话认证为 user 中的用户 (客户端证书映射出的用户 ID)，
+// 不再要求 JWT；没有 user 时客户端仍在协议内用 JWT 认证。
+// 未设置密钥时不发送前导行，客户端证书 (mTLS) 只作为接入备用传输的门槛。
+// QUIC 端点的证书由 btcmnetwork 加载：[imserver.bridge] 未设置 ca_path 时，只接受 [imserver] cert_path 中的证书
+// (启动时加载的或热加载后的当前证书，btcmnetwork 未必随之重新加载)。
+//
+// 转接中的流记入 bitcomm_im_open_streams，转接的字节数记入 bitcomm_im_received_bytes_total / bitcomm_im_sent_bytes_total
+// (都带标签 transport)，连不上 QUIC 端点时记入 bitcomm_im_bridge_errors_total。
+// 写入 QUIC 流的数据块计为 imserver 进行中的工作，从流上读到数据计一次 imserver 的进度，看门狗据此发现不再读取的 imserver。
+
+use crate::config::{ ClientAuthMode, ImBridgeSection, ImServerSection };
+use crate::imtcp::{ self, TcpTlsConnection };
+use crate::imws::{ self, WsConnection };
+use crate::tls::{ self, ReloadingCert };
+use crate::metrics::{ self, GaugeGuard };
+use crate::{ supervisor, telemetry };
+use ::metrics::{ counter, gauge, Counter };
+use axum::extract::ws::{ Message, WebSocket };
+use hmac::{ Hmac, Mac };
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::{ Connection, Endpoint, RecvStream, SendStream };
+use rustls::client::danger::{ HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier };
+use rustls::crypto::{ ring, CryptoProvider };
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{ CertificateDer, ServerName, UnixTime };
+use rustls::{ CertificateError, DigitallySignedStruct, RootCertStore, SignatureScheme };
+use sha2::Sha256;
+use std::error::Error;
+use std::io;
+use std::net::{ IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr };
+use std::sync::Arc;
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+use tokio::io::{ AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt };
+use tracing::{ debug, info, warn, Instrument };
+
+// 关闭 QUIC 连接前等待已写入的数据被对端确认的最长时间
+const CLOSE_LINGER: Duration = Duration::from_secs(2);
+
+// QUIC 端点所在的服务
+const IM_SERVICE: &str = "imserver";
+
+// 身份前导行的协议标识
+const PREAMBLE_MAGIC: &str = "BITCOMM-BRIDGE/1";
+
+/// 到 QUIC 端点的转接客户端
+#[derive(Debug)]
+pub struct Bridge {
+    endpoint: Endpoint,
+    client_config: quinn::ClientConfig,
+    server: SocketAddr,
+    server_name: String,
+    connect_timeout: Duration,
+    identity_secret: Option<String>,
+}
+
+/// 转接连接的身份，设置了 identity_secret 时随前导行传给 imserver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// 客户端接入的传输方式 (tcp-tls / websocket)
+    pub transport: String,
+    /// 客户端地址
+    pub peer: Option<SocketAddr>,
+    /// 客户端证书映射出的用户 ID
+    pub user_id: Option<String>,
+}
+
+impl Identity {
+    /// 未认证用户的连接
+    pub fn new(transport: &str, peer: Option<SocketAddr>) -> Identity {
+        Identity { transport: transport.to_string(), peer, user_id: None }
+    }
+
+    /// 签名的前导行 (含结尾换行)，timestamp 为 Unix 秒
+    pub fn preamble(&self, secret: &str, timestamp: u64) -> String {
+        let mut line = format!("{} ts={} transport={}", PREAMBLE_MAGIC, timestamp, self.transport);
+        if let Some(peer) = self.peer {
+            line.push_str(&format!(" peer={}", peer));
+        }
+        if let Some(user_id) = &self.user_id {
+            line.push_str(&format!(" user={}", hex::encode(user_id)));
+        }
+        let mac = hex::encode(preamble_mac(secret, &line).finalize().into_bytes());
+        format!("{} mac={}\n", line, mac)
+    }
+}
+
+/// 校验前导行 (不含结尾换行) 并取出身份，即 imserver 一侧应做的检查；
+/// 签名不符、格式错误或时间戳与 now 相差超过 max_skew_secs 秒时返回错误，不认识的字段忽略
+pub fn verify_preamble(line: &str, secret: &str, now: u64, max_skew_secs: u64) -> Result<Identity, String> {
+    let (signed, mac) = line.rsplit_once(" mac=").ok_or("missing mac")?;
+    let mac = hex::decode(mac).map_err(|_| "malformed mac")?;
+    preamble_mac(secret, signed).verify_slice(&mac).map_err(|_| "signature mismatch")?;
+
+    let mut fields = signed.split(' ');
+    if fields.next() != Some(PREAMBLE_MAGIC) {
+        return Err("not a bridge preamble".into());
+    }
+    let mut timestamp = None;
+    let mut identity = Identity::new("", None);
+    for field in fields {
+        match field.split_once('=') {
+            Some(("ts", value)) => timestamp = value.parse::<u64>().ok(),
+            Some(("transport", value)) => identity.transport = value.to_string(),
+            Some(("peer", value)) => identity.peer = Some(value.parse().map_err(|_| "malformed peer")?),
+            Some(("user", value)) => {
+                let user_id = hex::decode(value).ok().and_then(|user_id| String::from_utf8(user_id).ok());
+                identity.user_id = Some(user_id.ok_or("malformed user")?);
+            }
+            _ => {}
+        }
+    }
+    let timestamp = timestamp.ok_or("missing ts")?;
+    if identity.transport.is_empty() {
+        return Err("missing transport".into());
+    }
+    if timestamp.abs_diff(now) > max_skew_secs {
+        return Err(format!("timestamp {} is too far from {}", timestamp, now));
+    }
+    Ok(identity)
+}
+
+fn preamble_mac(secret: &str, signed: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(signed.as_bytes());
+    mac
+}
+
+/// 一条转接连接：QUIC 连接及其上由客户端打开的双向流
+#[derive(Debug)]
+pub struct Upstream {
+    pub connection: Connection,
+    pub send: SendStream,
+    pub recv: RecvStream,
+}
+
+impl Bridge {
+    /// 按 [imserver] 配置连接本机的 QUIC 端点
+    pub fn new(config: &ImServerSection) -> Result<Bridge, Box<dyn Error>> {
+        let ip = match config.ip {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        let bridge = Bridge::connect_to(SocketAddr::new(ip, config.port), &config.bridge, tls::imserver())?;
+        info!(server = %bridge.server, server_name = bridge.server_name, "IM bridge relays to the QUIC endpoint");
+        if config.client_auth.mode != ClientAuthMode::None && bridge.identity_secret.is_none() {
+            warn!("[imserver.bridge] identity_secret is not set, user IDs from client certificates are not forwarded to imserver");
+        }
+        Ok(bridge)
+    }
+
+    // pinned 为 ca_path 未设置时接受的服务器证书
+    fn connect_to(server: SocketAddr, config: &ImBridgeSection, pinned: Option<Arc<ReloadingCert>>) -> Result<Bridge, Box<dyn Error>> {
+        let provider = Arc::new(ring::default_provider());
+        let builder = rustls::ClientConfig::builder_with_provider(provider.clone()).with_protocol_versions(&[&rustls::version::TLS13])?;
+        let builder = match (&config.ca_path, pinned) {
+            (Some(ca_path), _) => {
+                let mut roots = RootCertStore::empty();
+                let certificates = CertificateDer::pem_file_iter(ca_path)
+                    .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+                    .map_err(|e| format!("{}: {}", ca_path.display(), e))?;
+                for certificate in certificates {
+                    roots.add(certificate).map_err(|e| format!("{}: {}", ca_path.display(), e))?;
+                }
+                builder.with_root_certificates(roots)
+            }
+            (None, Some(cert)) => builder.dangerous().with_custom_certificate_verifier(Arc::new(PinnedCert::new(cert, provider))),
+            (None, None) => return Err("[imserver.bridge] ca_path is required when [imserver] cert_path is not set".into()),
+        };
+        let mut crypto = builder.with_no_client_auth();
+        crypto.alpn_protocols = config.alpn.iter().map(|alpn| alpn.as_bytes().to_vec()).collect();
+        let client_config = quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto)?));
+
+        let bind = match server {
+            SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        };
+        Ok(Bridge {
+            endpoint: Endpoint::client(bind)?,
+            client_config,
+            server,
+            server_name: config.server_name.clone(),
+            connect_timeout: Duration::from_secs(config.connect_timeout_secs),
+            identity_secret: Some(config.identity_secret.clone()).filter(|secret| !secret.is_empty()),
+        })
+    }
+
+    /// 建立一条到 QUIC 端点的连接并打开双向流，设置了 identity_secret 时先写入 identity 的前导行
+    pub async fn connect(&self, identity: &Identity) -> Result<Upstream, Box<dyn Error + Send + Sync>> {
+        let connecting = self.endpoint.connect_with(self.client_config.clone(), self.server, &self.server_name)?;
+        let connection = tokio::time::timeout(self.connect_timeout, connecting)
+            .await
+            .map_err(|_| format!("timed out connecting to {}", self.server))??;
+        let (mut send, recv) = connection.open_bi().await?;
+        if let Some(secret) = &self.identity_secret {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            send.write_all(identity.preamble(secret, now).as_bytes()).await?;
+        }
+        Ok(Upstream { connection, send, recv })
+    }
+
+    // 连不上时计入 bitcomm_im_bridge_errors_total
+    async fn connect_for(&self, identity: &Identity) -> Result<Upstream, Box<dyn Error + Send + Sync>> {
+        let upstream = self.connect(identity).await;
+        if upstream.is_err() {
+            counter!(metrics::IM_BRIDGE_ERRORS, "transport" => identity.transport.clone()).increment(1);
+        }
+        upstream
+    }
+
+    /// 在客户端字节流和 QUIC 端点之间双向复制，直到两个方向都结束或任一方向出错
+    pub async fn relay<S>(&self, identity: &Identity, stream: S) -> Result<(), Box<dyn Error + Send + Sync>>
+    where
+        S: AsyncRead + AsyncWrite,
+    {
+        let mut upstream = self.connect_for(identity).await?;
+        let transport = identity.transport.clone();
+        let _stream = GaugeGuard::new(gauge!(metrics::IM_OPEN_STREAMS, "transport" => transport.clone()));
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        tokio::try_join!(
+            pump(&mut reader, &mut upstream.send, Toward::Server, counter!(metrics::IM_BYTES_IN, "transport" => transport.clone())),
+            pump(&mut upstream.recv, &mut writer, Toward::Client, counter!(metrics::IM_BYTES_OUT, "transport" => transport))
+        )?;
+        upstream.close().await;
+        Ok(())
+    }
+
+    /// 在 WebSocket 连接和 QUIC 端点之间转接：二进制消息的内容按顺序写入双向流，流上收到的数据作为二进制消息发回；
+    /// 任一方关闭后结束，文本消息不属于 IM 协议，收到时断开
+    pub async fn relay_websocket(&self, identity: &Identity, mut socket: WebSocket) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut upstream = self.connect_for(identity).await?;
+        let _stream = GaugeGuard::new(gauge!(metrics::IM_OPEN_STREAMS, "transport" => imws::TRANSPORT));
+        let bytes_in = counter!(metrics::IM_BYTES_IN, "transport" => imws::TRANSPORT);
+        let bytes_out = counter!(metrics::IM_BYTES_OUT, "transport" => imws::TRANSPORT);
+        let mut buf = vec![0; 16 * 1024];
+        loop {
+            tokio::select! {
+                message = socket.recv() => match message.transpose()? {
+                    Some(Message::Binary(data)) => {
+                        let span = telemetry::im_message_span(imws::TRANSPORT, "in", data.len());
+                        let work = supervisor::start_work(IM_SERVICE);
+                        upstream.send.write_all(&data).instrument(span).await?;
+                        drop(work);
+                        bytes_in.increment(data.len() as u64);
+                    }
+                    Some(Message::Text(_)) => {
+                        let _ = socket.send(Message::Close(None)).await;
+                        return Err("text messages are not part of the IM protocol".into());
+                    }
+                    Some(Message::Ping(_) | Message::Pong(_)) => {}
+                    Some(Message::Close(_)) | None => break,
+                },
+                read = upstream.recv.read(&mut buf) => match read? {
+                    Some(read) => {
+                        supervisor::record_progress(IM_SERVICE, 1);
+                        let span = telemetry::im_message_span(imws::TRANSPORT, "out", read);
+                        socket.send(Message::Binary(buf[..read].to_vec())).instrument(span).await?;
+                        bytes_out.increment(read as u64);
+                    }
+                    None => {
+                        let _ = socket.send(Message::Close(None)).await;
+                        break;
+                    }
+                },
+            }
+        }
+        upstream.close().await;
+        Ok(())
+    }
+}
+
+impl Upstream {
+    /// 结束发送方向，等待已写入的数据被确认 (最多 CLOSE_LINGER) 后关闭连接
+    pub async fn close(mut self) {
+        let _ = self.send.finish();
+        let _ = tokio::time::timeout(CLOSE_LINGER, self.send.stopped()).await;
+        self.connection.close(0u32.into(), b"");
+    }
+}
+
+/// 把 TCP+TLS 备用传输的连接转接到 bridge 的处理函数
+pub fn tcp_handler(bridge: Arc<Bridge>) -> imtcp::ConnectionHandler {
+    Arc::new(move |connection: TcpTlsConnection| {
+        let bridge = bridge.clone();
+        Box::pin(async move {
+            let peer = connection.peer;
+            let identity = Identity { user_id: connection.user_id, ..Identity::new(imtcp::TRANSPORT, Some(peer)) };
+            match bridge.relay(&identity, connection.stream).await {
+                Ok(()) => debug!(%peer, "IM TCP+TLS relay finished"),
+                Err(e) if e.is::<io::Error>() || e.is::<quinn::ConnectionError>() => debug!(%peer, "IM TCP+TLS relay ended: {}", e),
+                Err(e) => warn!(%peer, "failed to relay IM TCP+TLS connection: {}", e),
+            }
+        })
+    })
+}
+
+/// 把 WebSocket 网关的连接转接到 bridge 的处理函数
+pub fn ws_handler(bridge: Arc<Bridge>) -> imws::ConnectionHandler {
+    Arc::new(move |connection: WsConnection| {
+        let bridge = bridge.clone();
+        Box::pin(async move {
+            let peer = connection.peer;
+            match bridge.relay_websocket(&Identity::new(imws::TRANSPORT, peer), connection.socket).await {
+                Ok(()) => debug!(?peer, "IM WebSocket relay finished"),
+                Err(e) if e.is::<axum::Error>() || e.is::<quinn::ReadError>() || e.is::<quinn::WriteError>() => {
+                    debug!(?peer, "IM WebSocket relay ended: {}", e)
+                }
+                Err(e) => warn!(?peer, "failed to relay IM WebSocket connection: {}", e),
+            }
+        })
+    })
+}
+
+// 转接的方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Toward {
+    // 客户端 -> QUIC 端点，写入计为 imserver 进行中的工作
+    Server,
+    // QUIC 端点 -> 客户端，读到数据计一次 imserver 的进度
+    Client,
+}
+
+// 从 reader 复制到 writer，读到 EOF 后关闭 writer 的发送方向
+async fn pump<R, W>(reader: &mut R, writer: &mut W, toward: Toward, bytes: Counter) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; 16 * 1024];
+    loop {
+        let read = reader.read(&mut buf).await?;
+        if read == 0 {
+            return writer.shutdown().await;
+        }
+        let _work = match toward {
+            Toward::Server => Some(supervisor::start_work(IM_SERVICE)),
+            Toward::Client => {
+                supervisor::record_progress(IM_SERVICE, 1);
+                None
+            }
+        };
+        writer.write_all(&buf[..read]).await?;
+        writer.flush().await?;
+        bytes.increment(read as u64);
+    }
+}
+
+// 只接受 [imserver] cert_path 中的证书
+#[derive(Debug)]
+struct PinnedCert {
+    cert: Arc<ReloadingCert>,
+    // 启动时加载的证书，btcmnetwork 可能仍在使用
+    initial: CertificateDer<'static>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedCert {
+    fn new(cert: Arc<ReloadingCert>, provider: Arc<CryptoProvider>) -> PinnedCert {
+        let initial = cert.current().cert[0].clone();
+        PinnedCert { cert, initial, provider }
+    }
+}
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if *end_entity == self.initial || self.cert.current().cert.first() == Some(end_entity) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::metrics::tests::local_recorder;
+    use crate::tls::tests::{ load_cert, temp_dir, TestPki };
+    use quinn::crypto::rustls::QuicServerConfig;
+    use rustls::pki_types::PrivateKeyDer;
+    use std::path::Path;
+    use tokio::io::duplex;
+
+    /// 充当 imserver 的 QUIC 端点：每条双向流把收到的数据原样发回
+    pub(crate) fn echo_endpoint(cert_path: &Path, key_path: &Path, alpn: &str) -> Endpoint {
+        let chain = CertificateDer::pem_file_iter(cert_path).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        let key = PrivateKeyDer::from_pem_file(key_path).unwrap();
+        let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        crypto.alpn_protocols = vec![alpn.as_bytes().to_vec()];
+        let config = quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto).unwrap()));
+        let endpoint = Endpoint::server(config, SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+        let server = endpoint.clone();
+        tokio::spawn(async move {
+            while let Some(incoming) = server.accept().await {
+                tokio::spawn(async move {
+                    let connection = incoming.await?;
+                    let (mut send, mut recv) = connection.accept_bi().await?;
+                    tokio::io::copy(&mut recv, &mut send).await?;
+                    send.finish()?;
+                    connection.closed().await;
+                    Ok::<_, Box<dyn Error + Send + Sync>>(())
+                });
+            }
+        });
+        endpoint
+    }
+
+    /// 连接到 echo_endpoint 的转接客户端，证书写入 dir
+    pub(crate) fn echo_bridge(dir: &Path) -> (Bridge, Endpoint) {
+        let mut pki = TestPki::new(dir, "main");
+        let (cert_path, key_path) = pki.server("server");
+        let server = echo_endpoint(&cert_path, &key_path, "bitcomm-im");
+        let bridge = Bridge::connect_to(server.local_addr().unwrap(), &bridge_config("bitcomm-im", Some(&pki.ca_path())), None).unwrap();
+        (bridge, server)
+    }
+
+    fn bridge_config(alpn: &str, ca_path: Option<&Path>) -> ImBridgeSection {
+        ImBridgeSection {
+            alpn: vec![alpn.to_string()],
+            ca_path: ca_path.map(Path::to_path_buf),
+            connect_timeout_secs: 1,
+            ..ImBridgeSection::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn relays_both_directions_and_counts_bytes() {
+        let dir = temp_dir("imbridge-relay");
+        let (bridge, _server) = echo_bridge(&dir);
+
+        let (recorder, summary) = local_recorder();
+        let _recorder = ::metrics::set_default_local_recorder(&recorder);
+        let (mut client, relayed) = duplex(1024);
+        let session = async {
+            client.write_all(b"hello").await.unwrap();
+            client.shutdown().await.unwrap();
+            let mut echoed = Vec::new();
+            client.read_to_end(&mut echoed).await.unwrap();
+            echoed
+        };
+        let identity = Identity::new("tcp-tls", None);
+        let (relay, echoed) = tokio::join!(bridge.relay(&identity, relayed), session);
+        relay.unwrap();
+        assert_eq!(echoed, b"hello");
+        assert_eq!(summary().im_bytes_in, 5.0);
+        assert_eq!(summary().im_bytes_out, 5.0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_trusted_endpoints_are_used() {
+        let dir = temp_dir("imbridge-trust");
+        let mut pki = TestPki::new(&dir, "main");
+        let (cert_path, key_path) = pki.server("server");
+        let (other_cert, other_key) = pki.server("other");
+        let server = echo_endpoint(&cert_path, &key_path, "bitcomm-im");
+        let addr = server.local_addr().unwrap();
+
+        // 未设置 ca_path 时只接受 [imserver] 的证书
+        let pinned = Bridge::connect_to(addr, &bridge_config("bitcomm-im", None), Some(load_cert(&cert_path, &key_path))).unwrap();
+        assert!(pinned.connect(&Identity::new("tcp-tls", None)).await.is_ok());
+        let mismatched = Bridge::connect_to(addr, &bridge_config("bitcomm-im", None), Some(load_cert(&other_cert, &other_key))).unwrap();
+        assert!(mismatched.connect(&Identity::new("tcp-tls", None)).await.is_err());
+        assert!(Bridge::connect_to(addr, &bridge_config("bitcomm-im", None), None).is_err(), "no way to verify the endpoint");
+
+        let wrong_alpn = Bridge::connect_to(addr, &bridge_config("other", Some(&pki.ca_path())), None).unwrap();
+        assert!(wrong_alpn.connect(&Identity::new("tcp-tls", None)).await.is_err());
+
+        // 连不上时在超时后返回，客户端连接随之关闭
+        server.close(0u32.into(), b"");
+        server.wait_idle().await;
+        drop(server);
+        let unreachable = Bridge::connect_to(addr, &bridge_config("bitcomm-im", Some(&pki.ca_path())), None).unwrap();
+        let (_client, relayed) = duplex(1024);
+        assert!(unreachable.relay(&Identity::new("tcp-tls", None), relayed).await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn signed_identities_precede_the_client_bytes() {
+        let dir = temp_dir("imbridge-identity");
+        let mut pki = TestPki::new(&dir, "main");
+        let (cert_path, key_path) = pki.server("server");
+        let server = echo_endpoint(&cert_path, &key_path, "bitcomm-im");
+        let config = ImBridgeSection { identity_secret: "s3cret".to_string(), ..bridge_config("bitcomm-im", Some(&pki.ca_path())) };
+        let bridge = Bridge::connect_to(server.local_addr().unwrap(), &config, None).unwrap();
+
+        let (mut client, relayed) = duplex(1024);
+        let session = async {
+            client.write_all(b"hello").await.unwrap();
+            client.shutdown().await.unwrap();
+            let mut echoed = Vec::new();
+            client.read_to_end(&mut echoed).await.unwrap();
+            String::from_utf8(echoed).unwrap()
+        };
+        let identity = Identity { user_id: Some("alice smith".to_string()), ..Identity::new("tcp-tls", Some(([192, 0, 2, 7], 40000).into())) };
+        let (relay, echoed) = tokio::join!(bridge.relay(&identity, relayed), session);
+        relay.unwrap();
+        let (preamble, rest) = echoed.split_once('\n').unwrap();
+        assert_eq!(rest, "hello");
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        assert_eq!(verify_preamble(preamble, "s3cret", now, 30).unwrap(), identity);
+
+        assert!(verify_preamble(preamble, "other", now, 30).is_err(), "wrong secret");
+        assert!(verify_preamble(&preamble.replace("ts=", "ts=1"), "s3cret", now, 30).is_err(), "tampered");
+        assert!(verify_preamble(preamble, "s3cret", now + 60, 30).is_err(), "stale");
+        let anonymous = Identity::new("websocket", None);
+        assert_eq!(verify_preamble(anonymous.preamble("s3cret", now).trim_end(), "s3cret", now, 30).unwrap(), anonymous);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
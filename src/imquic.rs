@@ -0,0 +1,56 @@
+// IM 服务器的 QUIC 端点
+//
+// btcmnetwork 的 imserver::start_instant_message_server 自行绑定 [imserver] 端口并加载证书，不接受外部的端点或配置，
+// 所以 systemd 传入的 imserver socket、证书热加载和 QUIC 上的客户端证书认证目前都不生效。
+// endpoint() 按 bitcomm 的配置准备 imserver 应使用的端点：优先使用名为 imserver (或未命名且地址相同) 的激活 UDP socket，
+// 否则自行绑定；TLS 使用 tls::quic_server_config (可替换证书和 [imserver.client_auth])，ALPN 取 [imserver.bridge] alpn。
+// btcmnetwork 提供接受 quinn::Endpoint 的入口后由 bin/bitcomm.rs 调用并传入；在此之前 bin 不调用 endpoint()，
+// 否则会与 imserver 争用同一端口。btcmweb 的 webserver::star_webserver 同样自行绑定端口，名为 webserver 的激活 socket 仍不会被使用。
+
+use crate::config::ImServerSection;
+use crate::{ systemd, tls };
+use quinn::{ Endpoint, EndpointConfig };
+use std::error::Error;
+use std::net::{ SocketAddr, UdpSocket };
+
+/// 按 [imserver] 配置创建 QUIC 服务器端点，需在 tls::init 之后、tokio 运行时中调用
+pub fn endpoint(config: &ImServerSection) -> Result<Endpoint, Box<dyn Error>> {
+    let cert = tls::imserver().ok_or("the QUIC endpoint requires [imserver] cert_path and key_path")?;
+    let server_config = tls::quic_server_config(cert, tls::imserver_client_auth(), &config.bridge.alpn)?;
+    bind(SocketAddr::new(config.ip, config.port), server_config)
+}
+
+fn bind(addr: SocketAddr, server_config: quinn::ServerConfig) -> Result<Endpoint, Box<dyn Error>> {
+    let socket = match systemd::activated_udp_socket("imserver", addr) {
+        Some(socket) => socket,
+        None => UdpSocket::bind(addr)?,
+    };
+    let runtime = quinn::default_runtime().ok_or("no async runtime for the QUIC endpoint")?;
+    Ok(Endpoint::new(EndpointConfig::default(), Some(server_config), socket, runtime)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::tests::{ load_cert, quic_handshake, temp_dir, TestPki };
+    use std::net::Ipv4Addr;
+
+    #[tokio::test]
+    async fn binds_the_port_without_an_activated_socket() {
+        let dir = temp_dir("imquic-bind");
+        let mut pki = TestPki::new(&dir, "main");
+        let (cert_path, key_path) = pki.server("server");
+        let server_config = tls::quic_server_config(load_cert(&cert_path, &key_path), None, &["bitcomm-im".to_string()]).unwrap();
+        let endpoint = bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)), server_config).unwrap();
+        let server = endpoint.clone();
+        tokio::spawn(async move {
+            let connection = server.accept().await.unwrap().await.unwrap();
+            connection.closed().await;
+        });
+
+        assert!(quic_handshake(&endpoint, pki.client_config(None)).await.is_ok());
+
+        endpoint.close(0u32.into(), b"");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
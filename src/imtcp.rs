@@ -0,0 +1,304 @@
+// IM 服务器的 TCP+TLS 备用传输
+//
+// 企业网络常屏蔽 UDP，QUIC 连不上的客户端可改连 [imserver.tcp_fallback] 的 TCP 端口 (默认与 QUIC 端口号相同)。
+// 这里负责监听、连接数上限、降载/排空时拒绝新连接以及 TLS 握手：服务器证书与客户端证书认证和 QUIC 共用
+// tls::imserver() / tls::imserver_client_auth()，证书重新加载后新握手立即生效。
+// 握手完成后连接交给启动前通过 set_handler 注册的处理函数；帧格式、JWT 认证、会话登记和消息路由都在 btcmnetwork 中，
+// bitcomm 注册的是 imbridge::tcp_handler，把连接转接到本机 QUIC 端点。
+// 客户端证书映射不出用户 ID 时关闭连接，计入 bitcomm_im_connections_total{result="auth_error"}；
+// 尚未注册处理函数时握手后立即关闭连接，计入 result="unhandled"。
+
+use crate::config::{ ImServerSection, TcpFallbackSection };
+use crate::metrics::{ self, GaugeGuard };
+use crate::tls::ClientAuth;
+use crate::{ supervisor, systemd, telemetry, tls };
+use ::metrics::{ counter, gauge };
+use std::error::Error;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{ Arc, OnceLock };
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{ TcpListener, TcpStream };
+use tokio::sync::Semaphore;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::{ debug, info, warn, Instrument };
+
+/// 备用传输在指标和会话中的名称
+pub const TRANSPORT: &str = "tcp-tls";
+
+/// 握手完成的 TCP+TLS 连接
+#[derive(Debug)]
+pub struct TcpTlsConnection {
+    pub peer: SocketAddr,
+    /// 客户端证书映射出的用户 ID；未启用客户端证书认证或 optional 模式下未提供证书时为 None，需用 JWT 认证
+    pub user_id: Option<String>,
+    pub stream: TlsStream<TcpStream>,
+}
+
+impl TcpTlsConnection {
+    /// 会话的传输方式
+    pub fn transport(&self) -> &'static str {
+        TRANSPORT
+    }
+}
+
+/// 处理一个连接，返回的 future 结束时连接关闭
+pub type ConnectionHandler = Arc<dyn Fn(TcpTlsConnection) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+static HANDLER: OnceLock<ConnectionHandler> = OnceLock::new();
+
+/// 注册连接处理函数，需在 start_tcp_fallback 之前调用，只能注册一次；已注册时返回 false
+pub fn set_handler(handler: ConnectionHandler) -> bool {
+    HANDLER.set(handler).is_ok()
+}
+
+/// 监听地址
+pub fn listen_addr(config: &ImServerSection) -> SocketAddr {
+    SocketAddr::new(config.ip, config.tcp_fallback.port.unwrap_or(config.port))
+}
+
+/// 启动 TCP+TLS 备用传输，直到监听出错才返回
+pub async fn start_tcp_fallback(config: ImServerSection) -> Result<(), Box<dyn Error>> {
+    let cert = tls::imserver().ok_or("[imserver.tcp_fallback] requires [imserver] cert_path and key_path")?;
+    let client_auth = tls::imserver_client_auth();
+    let acceptor = TlsAcceptor::from(Arc::new(tls::server_config(cert, client_auth.clone())?));
+    let addr = listen_addr(&config);
+    let listener = match systemd::activated_tcp_listener("imtcpserver", addr) {
+        Some(listener) => TcpListener::from_std(listener)?,
+        None => TcpListener::bind(addr).await?,
+    };
+    info!("IM TCP+TLS fallback listening on {}", addr);
+    let handler = HANDLER.get().cloned();
+    if handler.is_none() {
+        warn!("no IM connection handler registered, TCP+TLS connections will be closed after the handshake");
+    }
+    accept_loop(listener, acceptor, client_auth, handler, &config.tcp_fallback).await
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    client_auth: Option<Arc<ClientAuth>>,
+    handler: Option<ConnectionHandler>,
+    config: &TcpFallbackSection
+) -> Result<(), Box<dyn Error>> {
+    let handshake_timeout = Duration::from_secs(config.handshake_timeout_secs);
+    let permits = Arc::new(Semaphore::new(config.max_connections));
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                supervisor::accept_error("imtcpserver", e).await;
+                continue;
+            }
+        };
+        if !supervisor::admit_connection(TRANSPORT) {
+            continue;
+        }
+        let Ok(permit) = permits.clone().try_acquire_owned() else {
+            record_result("limit");
+            debug!(%peer, "IM TCP+TLS connection limit reached");
+            continue;
+        };
+        let (acceptor, client_auth, handler) = (acceptor.clone(), client_auth.clone(), handler.clone());
+        supervisor::spawn_named(&format!("imtcpserver/conn/{}", peer), async move {
+            let _permit = permit;
+            // TLS 握手计为 imtcpserver 的一个工作单位，转接后的流量由 imbridge 计入 imserver
+            let work = supervisor::start_work("imtcpserver");
+            let stream = match tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    record_result("handshake_error");
+                    debug!(%peer, "IM TCP+TLS handshake failed: {}", e);
+                    return;
+                }
+                Err(_) => {
+                    record_result("handshake_error");
+                    debug!(%peer, "IM TCP+TLS handshake timed out");
+                    return;
+                }
+            };
+            drop(work);
+            serve(peer, stream, client_auth, handler).await;
+        });
+    }
+}
+
+async fn serve(peer: SocketAddr, mut stream: TlsStream<TcpStream>, client_auth: Option<Arc<ClientAuth>>, handler: Option<ConnectionHandler>) {
+    let Some(handler) = handler else {
+        record_result("unhandled");
+        let _ = stream.shutdown().await;
+        return;
+    };
+    // 客户端证书已在握手中校验，这里只取出用户 ID
+    let user_id = match client_auth.map(|client_auth| client_auth.user_id(stream.get_ref().1.peer_certificates().unwrap_or_default())) {
+        Some(Ok(user_id)) => user_id,
+        Some(Err(e)) => {
+            record_result("auth_error");
+            debug!(%peer, "IM TCP+TLS client certificate rejected: {}", e);
+            let _ = stream.shutdown().await;
+            return;
+        }
+        None => None,
+    };
+    record_result("accepted");
+    debug!(%peer, user_id = user_id.as_deref().unwrap_or("-"), "IM TCP+TLS connection accepted");
+
+    let _connections = GaugeGuard::new(gauge!(metrics::IM_CONNECTIONS, "transport" => TRANSPORT));
+    handler(TcpTlsConnection { peer, user_id, stream }).instrument(telemetry::im_connection_span(TRANSPORT, Some(peer))).await;
+}
+
+fn record_result(result: &'static str) {
+    counter!(metrics::IM_CONNECTIONS_TOTAL, "transport" => TRANSPORT, "result" => result).increment(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ ClientAuthMode, ClientAuthSection };
+    use crate::metrics::tests::local_recorder;
+    use crate::supervisor::tests::ADMISSION;
+    use crate::tls::tests::{ load_cert, load_client_auth, temp_dir, TestPki };
+    use rustls::pki_types::ServerName;
+    use std::path::{ Path, PathBuf };
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use tokio::io::AsyncReadExt;
+    use tokio_rustls::client;
+    use tokio_rustls::TlsConnector;
+
+    // 写回用户 ID，然后等待客户端关闭
+    fn echo_user_id(calls: Arc<AtomicUsize>) -> ConnectionHandler {
+        Arc::new(move |mut connection: TcpTlsConnection| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                let reply = format!("{}\n", connection.user_id.as_deref().unwrap_or("-"));
+                let _ = connection.stream.write_all(reply.as_bytes()).await;
+                let _ = connection.stream.read_to_end(&mut Vec::new()).await;
+            })
+        })
+    }
+
+    async fn start(
+        pki: &mut TestPki,
+        verifier: &ClientAuthSection,
+        client_auth: &ClientAuthSection,
+        handler: Option<ConnectionHandler>,
+        max_connections: usize
+    ) -> SocketAddr {
+        let (cert_path, key_path) = pki.server("server");
+        let acceptor = TlsAcceptor::from(Arc::new(tls::server_config(load_cert(&cert_path, &key_path), Some(load_client_auth(verifier))).unwrap()));
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = TcpFallbackSection { max_connections, ..TcpFallbackSection::default() };
+        let client_auth = Some(load_client_auth(client_auth));
+        tokio::spawn(async move { accept_loop(listener, acceptor, client_auth, handler, &config).await.unwrap() });
+        addr
+    }
+
+    async fn connect(addr: SocketAddr, pki: &TestPki, client: (&Path, &Path)) -> std::io::Result<client::TlsStream<TcpStream>> {
+        let connector = TlsConnector::from(Arc::new(pki.client_config(Some(client))));
+        connector.connect(ServerName::try_from("localhost").unwrap(), TcpStream::connect(addr).await?).await
+    }
+
+    // 读出一行；连接被服务器关闭时返回 None
+    async fn read_line(stream: &mut client::TlsStream<TcpStream>) -> Option<String> {
+        let mut line = Vec::new();
+        let mut byte = [0u8];
+        while matches!(stream.read(&mut byte).await, Ok(1)) {
+            if byte[0] == b'\n' {
+                return Some(String::from_utf8(line).unwrap());
+            }
+            line.push(byte[0]);
+        }
+        None
+    }
+
+    fn client_auth(prefix: &str, ca_path: PathBuf) -> ClientAuthSection {
+        ClientAuthSection { mode: ClientAuthMode::Required, ca_path: Some(ca_path), user_id_prefix: prefix.to_string(), ..ClientAuthSection::default() }
+    }
+
+    #[tokio::test]
+    async fn connections_are_authenticated_limited_and_tracked() {
+        let dir = temp_dir("imtcp-accept");
+        let mut pki = TestPki::new(&dir, "main");
+        let (alice_cert, alice_key, _) = pki.client("alice", "user-alice", Vec::new());
+        let (bob_cert, bob_key, _) = pki.client("bob", "user-bob", Vec::new());
+        let config = client_auth("user-", pki.ca_path());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let addr = start(&mut pki, &config, &config, Some(echo_user_id(calls.clone())), 1).await;
+
+        let _admission = ADMISSION.lock().await;
+        let (recorder, summary) = local_recorder();
+        let _recorder = ::metrics::set_default_local_recorder(&recorder);
+        let mut alice = connect(addr, &pki, (&alice_cert, &alice_key)).await.unwrap();
+        assert_eq!(read_line(&mut alice).await.as_deref(), Some("alice"));
+        assert_eq!(summary().im_connections, 1.0);
+
+        // 达到连接数上限时新连接不经握手直接关闭
+        assert!(connect(addr, &pki, (&bob_cert, &bob_key)).await.is_err());
+
+        alice.shutdown().await.unwrap();
+        drop(alice);
+        while summary().im_connections != 0.0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut bob = connect(addr, &pki, (&bob_cert, &bob_key)).await.unwrap();
+        assert_eq!(read_line(&mut bob).await.as_deref(), Some("bob"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unmapped_or_unhandled_connections_are_closed() {
+        let dir = temp_dir("imtcp-reject");
+        let mut pki = TestPki::new(&dir, "main");
+        let (alice_cert, alice_key, _) = pki.client("alice", "user-alice", Vec::new());
+
+        // 握手按无前缀的配置通过，但取用户 ID 时要求另一个前缀
+        let (any, staff, user) = (client_auth("", pki.ca_path()), client_auth("staff-", pki.ca_path()), client_auth("user-", pki.ca_path()));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let _admission = ADMISSION.lock().await;
+        let addr = start(&mut pki, &any, &staff, Some(echo_user_id(calls.clone())), 10).await;
+        let mut alice = connect(addr, &pki, (&alice_cert, &alice_key)).await.unwrap();
+        assert_eq!(read_line(&mut alice).await, None);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let addr = start(&mut pki, &user, &user, None, 10).await;
+        let mut alice = connect(addr, &pki, (&alice_cert, &alice_key)).await.unwrap();
+        assert_eq!(read_line(&mut alice).await, None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn new_connections_are_shed_under_resource_pressure() {
+        let dir = temp_dir("imtcp-shed");
+        let mut pki = TestPki::new(&dir, "main");
+        let (alice_cert, alice_key, _) = pki.client("alice", "user-alice", Vec::new());
+        let (bob_cert, bob_key, _) = pki.client("bob", "user-bob", Vec::new());
+        let config = client_auth("user-", pki.ca_path());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let addr = start(&mut pki, &config, &config, Some(echo_user_id(calls.clone())), 10).await;
+
+        let _admission = ADMISSION.lock().await;
+        let mut alice = connect(addr, &pki, (&alice_cert, &alice_key)).await.unwrap();
+        assert_eq!(read_line(&mut alice).await.as_deref(), Some("alice"));
+
+        // 拒绝新连接期间新连接不经握手直接关闭，已有连接不受影响
+        supervisor::set_shedding(true);
+        let shed = connect(addr, &pki, (&bob_cert, &bob_key)).await;
+        supervisor::set_shedding(false);
+        assert!(shed.is_err());
+        alice.write_all(b"still open").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let mut bob = connect(addr, &pki, (&bob_cert, &bob_key)).await.unwrap();
+        assert_eq!(read_line(&mut bob).await.as_deref(), Some("bob"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,312 @@
+// 浏览器 IM 客户端的 WebSocket 网关
+//
+// 浏览器无法直接打开到 imserver 的 QUIC 流，启用 [webserver.websocket] 后提供 WebSocket 入口。
+// btcmweb 的 webserver::star_webserver 不接受外部路由，网关目前由 start_ws_gateway 在 [webserver.websocket] 的端口上单独监听
+// (服务名 imwsserver，可使用 systemd 激活的 socket)，需要 wss 时由前置的反向代理终结 TLS。
+// router() 返回同一套路由，Web 服务器提供接受 Router 的入口后可直接合并，与其共用端口。
+// 升级前依次检查：降载/排空、Origin (必须在 allowed_origins 中，列表为空时全部拒绝)、连接数上限，
+// 失败时分别返回 503 / 403 / 503，不建立 WebSocket。
+// 网关不做用户认证：bitcomm 注册的处理函数 imbridge::ws_handler 把连接转接到本机 QUIC 端点，
+// 客户端与 QUIC 客户端一样在协议内用 JWT 认证，会话登记和消息路由由 btcmnetwork 处理。
+// 尚未注册处理函数时返回 503，计入 bitcomm_im_connections_total{result="unhandled"}。
+
+use crate::config::WebSocketSection;
+use crate::metrics::{ self, GaugeGuard };
+use crate::{ supervisor, systemd, telemetry };
+use ::metrics::{ counter, gauge };
+use axum::extract::ws::{ WebSocket, WebSocketUpgrade };
+use axum::extract::{ ConnectInfo, State };
+use axum::http::{ header, HeaderMap, StatusCode };
+use axum::response::{ IntoResponse, Response };
+use axum::routing::get;
+use axum::Router;
+use std::error::Error;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{ Arc, OnceLock };
+use tokio::sync::{ OwnedSemaphorePermit, Semaphore };
+use tracing::{ debug, info, warn, Instrument };
+
+/// WebSocket 在指标和会话中的传输名称
+pub const TRANSPORT: &str = "websocket";
+
+/// 通过检查的 WebSocket 连接
+#[derive(Debug)]
+pub struct WsConnection {
+    /// 对端地址；Web 服务器未提供 ConnectInfo 时为 None
+    pub peer: Option<SocketAddr>,
+    pub socket: WebSocket,
+}
+
+impl WsConnection {
+    /// 会话的传输方式
+    pub fn transport(&self) -> &'static str {
+        TRANSPORT
+    }
+}
+
+/// 处理一个连接，返回的 future 结束时连接关闭
+pub type ConnectionHandler = Arc<dyn Fn(WsConnection) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+static HANDLER: OnceLock<ConnectionHandler> = OnceLock::new();
+
+static CONFIG: OnceLock<WebSocketSection> = OnceLock::new();
+
+/// 注册连接处理函数，需在网关启动 (或 Web 服务器调用 router()) 之前调用，只能注册一次；已注册时返回 false
+pub fn set_handler(handler: ConnectionHandler) -> bool {
+    HANDLER.set(handler).is_ok()
+}
+
+struct Gateway {
+    config: WebSocketSection,
+    handler: Option<ConnectionHandler>,
+    permits: Arc<Semaphore>,
+}
+
+/// 检查并保存配置，在启动各服务之前调用
+pub fn init(config: &WebSocketSection) -> Result<(), Box<dyn Error>> {
+    if config.enable && !config.path.starts_with('/') {
+        return Err("[webserver.websocket] path must start with /".into());
+    }
+    if config.enable && config.allowed_origins.is_empty() {
+        return Err("[webserver.websocket] allowed_origins must list the origins of the web client".into());
+    }
+    let _ = CONFIG.set(config.clone());
+    Ok(())
+}
+
+/// 网关路由，未启用时为空路由；Web 服务器合并时需以 into_make_service_with_connect_info::<SocketAddr>() 提供服务才能取得对端地址
+pub fn router() -> Router {
+    match CONFIG.get() {
+        Some(config) if config.enable => {
+            let handler = HANDLER.get().cloned();
+            if handler.is_none() {
+                warn!("no IM connection handler registered, WebSocket upgrades will be refused");
+            }
+            gateway_router(config.clone(), handler)
+        }
+        _ => Router::new(),
+    }
+}
+
+/// 在 [webserver.websocket] 的地址上启动网关，直到监听出错才返回；需先调用 init
+pub async fn start_ws_gateway() -> Result<(), Box<dyn Error>> {
+    let Some(config) = CONFIG.get() else {
+        return Err("IM WebSocket gateway is not initialized".into());
+    };
+    let addr = SocketAddr::new(config.ip, config.port);
+    let listener = match systemd::activated_tcp_listener("imwsserver", addr) {
+        Some(listener) => tokio::net::TcpListener::from_std(listener)?,
+        None => tokio::net::TcpListener::bind(addr).await?,
+    };
+    info!("IM WebSocket gateway listening on ws://{}{}", addr, config.path);
+
+    axum::serve(listener, router().into_make_service_with_connect_info::<SocketAddr>()).await?;
+    Ok(())
+}
+
+fn gateway_router(config: WebSocketSection, handler: Option<ConnectionHandler>) -> Router {
+    let path = config.path.clone();
+    let gateway = Arc::new(Gateway { permits: Arc::new(Semaphore::new(config.max_connections)), config, handler });
+    Router::new().route(&path, get(upgrade)).with_state(gateway)
+}
+
+async fn upgrade(
+    State(gateway): State<Arc<Gateway>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade
+) -> Response {
+    let peer = connect_info.map(|ConnectInfo(peer)| peer);
+    if !supervisor::admit_connection(TRANSPORT) {
+        return (StatusCode::SERVICE_UNAVAILABLE, "server is shedding load").into_response();
+    }
+    if !origin_allowed(&gateway.config.allowed_origins, &headers) {
+        debug!(?peer, origin = ?headers.get(header::ORIGIN), "IM WebSocket origin rejected");
+        return reject("forbidden_origin", StatusCode::FORBIDDEN, "origin not allowed");
+    }
+    let Some(handler) = gateway.handler.clone() else {
+        return reject("unhandled", StatusCode::SERVICE_UNAVAILABLE, "IM gateway not available");
+    };
+    let Ok(permit) = gateway.permits.clone().try_acquire_owned() else {
+        return reject("limit", StatusCode::SERVICE_UNAVAILABLE, "too many connections");
+    };
+
+    record_result("accepted");
+    supervisor::record_progress("webserver", 1);
+    debug!(?peer, "IM WebSocket connection accepted");
+    // 升级后的连接放入以对端地址命名的任务中，便于在 tokio-console 中定位
+    ws.on_upgrade(move |socket| async move {
+        let name = match peer {
+            Some(peer) => format!("imwsserver/conn/{}", peer),
+            None => "imwsserver/conn/unknown".to_string(),
+        };
+        let _ = supervisor::spawn_named(&name, serve(handler, WsConnection { peer, socket }, permit)).await;
+    })
+}
+
+async fn serve(handler: ConnectionHandler, connection: WsConnection, _permit: OwnedSemaphorePermit) {
+    let _connections = GaugeGuard::new(gauge!(metrics::IM_CONNECTIONS, "transport" => TRANSPORT));
+    let span = telemetry::im_connection_span(TRANSPORT, connection.peer);
+    handler(connection).instrument(span).await;
+}
+
+fn reject(result: &'static str, status: StatusCode, message: &'static str) -> Response {
+    record_result(result);
+    (status, message).into_response()
+}
+
+fn record_result(result: &'static str) {
+    counter!(metrics::IM_CONNECTIONS_TOTAL, "transport" => TRANSPORT, "result" => result).increment(1);
+}
+
+// 只放行 allowed_origins 中的 Origin；不带 Origin 的请求同样拒绝，非浏览器客户端应使用 QUIC 或 TCP+TLS
+fn origin_allowed(allowed: &[String], headers: &HeaderMap) -> bool {
+    match headers.get(header::ORIGIN).and_then(|origin| origin.to_str().ok()) {
+        Some(origin) => allowed.iter().any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin)),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imbridge;
+    use crate::metrics::tests::local_recorder;
+    use crate::supervisor::tests::ADMISSION;
+    use crate::tls::tests::temp_dir;
+    use futures_util::{ SinkExt, StreamExt };
+    use std::time::Duration;
+    use tokio::io::{ AsyncReadExt, AsyncWriteExt };
+    use tokio::net::{ TcpListener, TcpStream };
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::http::HeaderValue;
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+    use tokio_tungstenite::{ MaybeTlsStream, WebSocketStream };
+
+    const ORIGIN: &str = "https://chat.example.test";
+
+    fn config(allowed_origins: &[&str], max_connections: usize) -> WebSocketSection {
+        WebSocketSection {
+            enable: true,
+            allowed_origins: allowed_origins.iter().map(|origin| origin.to_string()).collect(),
+            max_connections,
+            ..WebSocketSection::default()
+        }
+    }
+
+    // 等待客户端关闭
+    fn hold() -> ConnectionHandler {
+        Arc::new(|mut connection: WsConnection| Box::pin(async move { while connection.socket.recv().await.is_some() {} }))
+    }
+
+    // 在测试监听上提供网关，与 Web 服务器一样带 ConnectInfo
+    async fn serve_gateway(config: WebSocketSection, handler: Option<ConnectionHandler>) -> SocketAddr {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = gateway_router(config, handler).into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        addr
+    }
+
+    // 发送升级请求，返回应答的状态码
+    async fn upgrade_status(addr: SocketAddr, origin: Option<&str>) -> u16 {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let origin = origin.map(|origin| format!("Origin: {}\r\n", origin)).unwrap_or_default();
+        let request = format!(
+            "GET /im/ws HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n{}\r\n",
+            origin
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut status_line = [0u8; 12];
+        stream.read_exact(&mut status_line).await.unwrap();
+        String::from_utf8_lossy(&status_line[9..]).parse().unwrap()
+    }
+
+    async fn connect(addr: SocketAddr) -> WebSocketStream<MaybeTlsStream<TcpStream>> {
+        let mut request = format!("ws://{}/im/ws", addr).into_client_request().unwrap();
+        request.headers_mut().insert("Origin", HeaderValue::from_static(ORIGIN));
+        tokio_tungstenite::connect_async(request).await.unwrap().0
+    }
+
+    #[tokio::test]
+    async fn only_listed_origins_are_upgraded() {
+        let _admission = ADMISSION.lock().await;
+        let addr = serve_gateway(config(&["https://chat.example.test/", "https://admin.example.test"], 10), Some(hold())).await;
+        assert_eq!(upgrade_status(addr, Some(ORIGIN)).await, 101);
+        assert_eq!(upgrade_status(addr, Some("https://admin.example.test")).await, 101);
+        assert_eq!(upgrade_status(addr, Some("https://evil.example.test")).await, 403);
+        assert_eq!(upgrade_status(addr, Some("https://chat.example.test.evil.test")).await, 403);
+        assert_eq!(upgrade_status(addr, None).await, 403, "requests without Origin are refused");
+
+        // 拒绝新连接期间不再升级
+        supervisor::set_shedding(true);
+        let shed = upgrade_status(addr, Some(ORIGIN)).await;
+        supervisor::set_shedding(false);
+        assert_eq!(shed, 503);
+
+        let addr = serve_gateway(config(&[], 10), Some(hold())).await;
+        assert_eq!(upgrade_status(addr, Some(ORIGIN)).await, 403, "an empty list allows nothing");
+        assert_eq!(upgrade_status(addr, None).await, 403);
+
+        assert!(init(&config(&[], 10)).is_err());
+        assert!(init(&WebSocketSection { path: "im/ws".to_string(), ..config(&[ORIGIN], 10) }).is_err());
+    }
+
+    #[tokio::test]
+    async fn connections_are_limited_and_tracked() {
+        let _admission = ADMISSION.lock().await;
+        let (recorder, summary) = local_recorder();
+        let _recorder = ::metrics::set_default_local_recorder(&recorder);
+        let addr = serve_gateway(config(&[ORIGIN], 1), Some(hold())).await;
+
+        let mut first = connect(addr).await;
+        while summary().im_connections != 1.0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(upgrade_status(addr, Some(ORIGIN)).await, 503);
+        first.close(None).await.unwrap();
+        while summary().im_connections != 0.0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        connect(addr).await.close(None).await.unwrap();
+
+        let addr = serve_gateway(config(&[ORIGIN], 1), None).await;
+        assert_eq!(upgrade_status(addr, Some(ORIGIN)).await, 503, "no handler registered");
+    }
+
+    #[tokio::test]
+    async fn binary_messages_are_relayed_to_the_quic_endpoint() {
+        let _admission = ADMISSION.lock().await;
+        let dir = temp_dir("imws-relay");
+        let (bridge, _server) = imbridge::tests::echo_bridge(&dir);
+        let (recorder, summary) = local_recorder();
+        let _recorder = ::metrics::set_default_local_recorder(&recorder);
+        let addr = serve_gateway(config(&[ORIGIN], 10), Some(imbridge::ws_handler(Arc::new(bridge)))).await;
+
+        let mut client = connect(addr).await;
+        client.send(ClientMessage::binary(b"hello".to_vec())).await.unwrap();
+        client.send(ClientMessage::binary(b" world".to_vec())).await.unwrap();
+        let mut echoed = Vec::new();
+        while echoed.len() < 11 {
+            match client.next().await.unwrap().unwrap() {
+                ClientMessage::Binary(data) => echoed.extend(data),
+                other => panic!("unexpected message {:?}", other),
+            }
+        }
+        assert_eq!(echoed, b"hello world");
+        assert_eq!(summary().im_bytes_in, 11.0);
+        assert_eq!(summary().im_bytes_out, 11.0);
+
+        // 文本消息不属于 IM 协议，网关关闭连接
+        client.send(ClientMessage::text("hello")).await.unwrap();
+        match client.next().await {
+            Some(Ok(ClientMessage::Close(_)) | Err(_)) | None => {}
+            Some(Ok(other)) => panic!("unexpected message {:?}", other),
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
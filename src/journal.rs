@@ -0,0 +1,195 @@
+// 进程事件日志
+//
+// 记录服务启停、就绪、重启、配置变更、排空、信号等事件，
+// 以 JSON Lines 追加写入 [journal] path，进程重启后仍可查到之前发生的事件。
+// 内存中保留最近 max_events 条供控制 socket 和 /admin/journal 查询，
+// 文件超过 2 * max_events 行时压缩为最近的 max_events 条。
+
+use crate::config::JournalSection;
+use serde::{ Deserialize, Serialize };
+use std::collections::VecDeque;
+use std::fs::{ self, OpenOptions };
+use std::io::{ self, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::{ Mutex, OnceLock, PoisonError };
+
+/// 事件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// 进程或服务启动
+    Started,
+    /// 服务正常退出
+    Stopped,
+    /// 服务异常退出
+    Failed,
+    /// 服务被重启
+    Restarted,
+    /// 就绪检查由失败变为通过
+    Ready,
+    /// 就绪检查由通过变为失败
+    NotReady,
+    /// 运行时配置变更 (日志过滤规则)
+    ConfigReloaded,
+    /// 开始排空
+    DrainBegan,
+    /// 收到信号
+    SignalReceived,
+    /// 服务 panic
+    Crashed,
+    /// 看门狗判定服务卡住
+    Stuck,
+    /// 资源检查越过阈值
+    ThresholdCrossed,
+}
+
+/// 一条事件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEvent {
+    /// RFC 3339 时间
+    pub timestamp: String,
+    pub kind: EventKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    pub detail: String,
+}
+
+/// 查询条件
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JournalQuery {
+    /// 最多返回的条数 (最近的)，缺省为全部
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// 只返回该服务的事件
+    #[serde(default)]
+    pub service: Option<String>,
+    /// 只返回该类型的事件
+    #[serde(default)]
+    pub kind: Option<EventKind>,
+}
+
+struct Journal {
+    path: PathBuf,
+    max_events: usize,
+    state: Mutex<JournalState>,
+}
+
+struct JournalState {
+    events: VecDeque<JournalEvent>,
+    // 文件中的行数
+    file_lines: usize,
+}
+
+static JOURNAL: OnceLock<Journal> = OnceLock::new();
+
+/// 打开事件日志并载入最近的 max_events 条，未调用时 record 不做任何事
+pub fn init(config: &JournalSection) -> io::Result<()> {
+    let (events, file_lines) = load(&config.path, config.max_events)?;
+    let journal = Journal {
+        path: config.path.clone(),
+        max_events: config.max_events,
+        state: Mutex::new(JournalState { events, file_lines }),
+    };
+    let _ = JOURNAL.set(journal);
+    Ok(())
+}
+
+fn load(path: &Path, max_events: usize) -> io::Result<(VecDeque<JournalEvent>, usize)> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let mut events = VecDeque::with_capacity(max_events);
+    let mut file_lines = 0;
+    for line in text.lines() {
+        file_lines += 1;
+        // 跳过被截断的行 (例如写入时进程被 kill)
+        let Ok(event) = serde_json::from_str::<JournalEvent>(line) else {
+            continue;
+        };
+        if events.len() == max_events {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+    Ok((events, file_lines))
+}
+
+/// 记录一条事件
+///
+/// 可能在 panic hook 中调用：内存中的事件正被占用时只写文件，写文件失败时只输出到 stderr。
+pub fn record(kind: EventKind, service: Option<&str>, detail: impl Into<String>) {
+    let Some(journal) = JOURNAL.get() else {
+        return;
+    };
+    let event = JournalEvent {
+        timestamp: chrono::Local::now().to_rfc3339(),
+        kind,
+        service: service.map(str::to_string),
+        detail: detail.into(),
+    };
+
+    let result = match journal.state.try_lock() {
+        Ok(mut state) => journal.append(&mut state, event),
+        Err(_) => append_line(&journal.path, &event),
+    };
+    if let Err(e) = result {
+        eprintln!("failed to write journal {}: {}", journal.path.display(), e);
+    }
+}
+
+impl Journal {
+    fn append(&self, state: &mut JournalState, event: JournalEvent) -> io::Result<()> {
+        append_line(&self.path, &event)?;
+        state.file_lines += 1;
+        if state.events.len() >= self.max_events {
+            state.events.pop_front();
+        }
+        state.events.push_back(event);
+
+        if state.file_lines > self.max_events.saturating_mul(2) {
+            self.compact(state)?;
+        }
+        Ok(())
+    }
+
+    // 用内存中的最近事件重写文件，先写临时文件再 rename
+    fn compact(&self, state: &mut JournalState) -> io::Result<()> {
+        let mut text = String::new();
+        for event in &state.events {
+            text.push_str(&serde_json::to_string(event)?);
+            text.push('\n');
+        }
+        let tmp = self.path.with_extension("jsonl.tmp");
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, &self.path)?;
+        state.file_lines = state.events.len();
+        Ok(())
+    }
+}
+
+// 整行一次写入，O_APPEND 保证多个线程同时写时行不交错
+fn append_line(path: &Path, event: &JournalEvent) -> io::Result<()> {
+    let mut line = serde_json::to_string(event)?;
+    line.push('\n');
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(line.as_bytes())
+}
+
+/// 按条件查询事件，按时间先后排列
+pub fn query(query: &JournalQuery) -> Vec<JournalEvent> {
+    let Some(journal) = JOURNAL.get() else {
+        return Vec::new();
+    };
+    let state = journal.state.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut events: Vec<JournalEvent> = state.events
+        .iter()
+        .rev()
+        .filter(|event| query.service.as_ref().is_none_or(|service| event.service.as_ref() == Some(service)))
+        .filter(|event| query.kind.is_none_or(|kind| event.kind == kind))
+        .take(query.limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect();
+    events.reverse();
+    events
+}
@@ -0,0 +1,35 @@
+// bitcomm 库，供 bin/bitcomm.rs 使用的公共模块
+
+// tokio 只在 tokio_unstable 下输出 tokio-console 需要的任务数据
+#[cfg(all(feature = "console", not(tokio_unstable)))]
+compile_error!("the console feature requires RUSTFLAGS=\"--cfg tokio_unstable\"");
+
+pub mod alerting;
+pub mod buildinfo;
+pub mod cli;
+pub mod config;
+pub mod control;
+pub mod devcert;
+pub mod crashreport;
+pub mod health;
+pub mod imbridge;
+pub mod imquic;
+pub mod imtcp;
+pub mod imws;
+pub mod journal;
+pub mod logring;
+pub mod logsink;
+pub mod metrics;
+pub mod opsserver;
+pub mod profiling;
+pub mod resources;
+pub mod slogbridge;
+pub mod stall;
+pub mod supervise;
+pub mod supervisor;
+pub mod systemd;
+pub mod telemetry;
+pub mod tls;
+pub mod top;
+pub mod watchdog;
+
@@ -0,0 +1,119 @@
+// 最近日志的内存环形缓冲
+//
+// 作为一个 layer 挂在全局过滤器之后，只保存通过过滤的事件；
+// `bitcomm top` 从这里读取最近的告警，崩溃报告从这里取 panic 前的日志。
+
+use crate::logsink::BoxedLayer;
+use serde::{ Deserialize, Serialize };
+use std::collections::VecDeque;
+use std::fmt::{ self, Write };
+use std::sync::{ Mutex, PoisonError, TryLockError };
+use tracing::field::{ Field, Visit };
+use tracing::{ Event, Level, Subscriber };
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// 一条日志记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentLog {
+    /// RFC 3339 时间
+    pub timestamp: String,
+    pub level: String,
+    pub target: String,
+    /// 消息正文，其余字段以 key=value 追加在后面
+    pub message: String,
+}
+
+static RECENT: Mutex<VecDeque<RecentLog>> = Mutex::new(VecDeque::new());
+
+/// 构建环形缓冲 layer，最多保留 capacity 条
+pub fn ring_layer(capacity: usize) -> BoxedLayer {
+    RingLayer { capacity }.boxed()
+}
+
+/// 最近的日志，按时间先后排列；只返回不低于 min_level 的记录，最多 limit 条
+pub fn recent(min_level: Level, limit: usize) -> Vec<RecentLog> {
+    let recent = RECENT.lock().unwrap_or_else(PoisonError::into_inner);
+    select(&recent, min_level, limit)
+}
+
+/// 同 recent，但缓冲正被占用时直接返回 None；供 panic hook 使用，
+/// 避免在写缓冲时 panic 的线程上死锁
+pub fn try_recent(min_level: Level, limit: usize) -> Option<Vec<RecentLog>> {
+    match RECENT.try_lock() {
+        Ok(recent) => Some(select(&recent, min_level, limit)),
+        Err(TryLockError::Poisoned(recent)) => Some(select(&recent.into_inner(), min_level, limit)),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+fn select(recent: &VecDeque<RecentLog>, min_level: Level, limit: usize) -> Vec<RecentLog> {
+    let mut logs: Vec<RecentLog> = recent
+        .iter()
+        .rev()
+        .filter(|log| log.level.parse::<Level>().is_ok_and(|level| level <= min_level))
+        .take(limit)
+        .cloned()
+        .collect();
+    logs.reverse();
+    logs
+}
+
+struct RingLayer {
+    capacity: usize,
+}
+
+impl<S: Subscriber> Layer<S> for RingLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if self.capacity == 0 {
+            return;
+        }
+        // log 桥接过来的事件使用原始的 target
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let log = RecentLog {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message: visitor.message + &visitor.fields,
+        };
+
+        let mut recent = RECENT.lock().unwrap_or_else(PoisonError::into_inner);
+        while recent.len() >= self.capacity {
+            recent.pop_front();
+        }
+        recent.push_back(log);
+    }
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => {
+                let _ = write!(self.message, "{:?}", value);
+            }
+            name if name.starts_with("log.") => {}
+            name => {
+                let _ = write!(self.fields, " {}={:?}", name, value);
+            }
+        }
+    }
+}
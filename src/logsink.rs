@@ -0,0 +1,230 @@
+// 日志输出目标 ([[log.sinks]])
+//
+// 每个 sink 对应一个 fmt layer：stdout 或文件，文本或 JSON。
+// 文件按天滚动使用 tracing-appender，按大小滚动由 SizeRollingWriter 实现。
+
+use crate::config::{ LogFormat, LogRotation, LogSink, LogTarget };
+use serde_json::{ Map, Value };
+use std::error::Error;
+use std::fmt;
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, IsTerminal, Write };
+use std::path::{ Path, PathBuf };
+use tracing::field::{ Field, Visit };
+use tracing::{ Event, Subscriber };
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{ RollingFileAppender, Rotation };
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::fmt::format::{ JsonFields, Writer };
+use tracing_subscriber::fmt::{ FmtContext, FormatEvent, FormatFields, FormattedFields };
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{ Layer, Registry };
+
+/// 装箱后的 sink layer，便于把不同 writer / 格式的 layer 放进同一个 Vec
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// 按配置构建一个 sink layer；文件 sink 额外返回后台写线程的 guard，
+/// guard 释放时会把缓冲的日志刷到磁盘
+pub fn sink_layer(sink: &LogSink) -> Result<(BoxedLayer, Option<WorkerGuard>), Box<dyn Error>> {
+    let layer = match sink.target {
+        LogTarget::Stdout => (format_layer(sink.format, io::stdout, stdout_color_enabled()), None),
+        LogTarget::File => {
+            let (writer, guard) = match sink.rotation {
+                LogRotation::Size => {
+                    let writer = SizeRollingWriter::new(
+                        &sink.directory,
+                        &sink.file_name,
+                        sink.max_size_mb * 1024 * 1024,
+                        sink.max_files
+                    )?;
+                    tracing_appender::non_blocking(writer)
+                }
+                LogRotation::Daily | LogRotation::Never => {
+                    let rotation = if sink.rotation == LogRotation::Daily { Rotation::DAILY } else { Rotation::NEVER };
+                    let writer = RollingFileAppender::builder()
+                        .rotation(rotation)
+                        .filename_prefix(sink.file_name.clone())
+                        .max_log_files(sink.max_files)
+                        .build(&sink.directory)?;
+                    tracing_appender::non_blocking(writer)
+                }
+            };
+            (format_layer(sink.format, writer, false), Some(guard))
+        }
+    };
+    Ok(layer)
+}
+
+/// stdout 是终端且未设置 NO_COLOR 时才输出颜色 (https://no-color.org)
+pub fn stdout_color_enabled() -> bool {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    !no_color && io::stdout().is_terminal()
+}
+
+/// 是否有以 JSON 格式输出到 stdout 的 sink
+pub fn stdout_is_json(sinks: &[LogSink]) -> bool {
+    sinks.iter().any(|sink| sink.target == LogTarget::Stdout && sink.format == LogFormat::Json)
+}
+
+fn format_layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+    where W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static
+{
+    match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(ansi).boxed(),
+        LogFormat::Json =>
+            tracing_subscriber::fmt
+                ::layer()
+                .with_writer(writer)
+                .fmt_fields(JsonFields::new())
+                .event_format(JsonFormat)
+                .boxed(),
+    }
+}
+
+/// JSON 日志格式，每行一个对象：
+/// timestamp / level / service / target / pid / message / fields / spans
+///
+/// service 取最内层带 `service` 字段的 span，即 bin/bitcomm.rs 中各服务的根 span。
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+    where S: Subscriber + for<'a> LookupSpan<'a>, N: for<'a> FormatFields<'a> + 'static
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        // 由 log 桥接过来的事件需还原原始的 target / 模块路径
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        let mut visitor = JsonVisitor::default();
+        event.record(&mut visitor);
+        let message = visitor.fields.remove("message").unwrap_or(Value::Null);
+
+        let mut service = Value::Null;
+        let mut spans = Vec::new();
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let mut object = Map::new();
+                object.insert("name".to_string(), Value::from(span.name()));
+                // 与 JsonFields 配合使用时，span 字段已被格式化为 JSON 对象
+                if let Some(fields) = span.extensions().get::<FormattedFields<N>>() {
+                    if let Ok(Value::Object(fields)) = serde_json::from_str::<Value>(fields) {
+                        if let Some(name) = fields.get("service") {
+                            service = name.clone();
+                        }
+                        object.extend(fields);
+                    }
+                }
+                spans.push(Value::Object(object));
+            }
+        }
+
+        let line =
+            serde_json::json!({
+            "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            "level": metadata.level().as_str(),
+            "service": service,
+            "target": metadata.target(),
+            "pid": std::process::id(),
+            "message": message,
+            "fields": visitor.fields,
+            "spans": spans,
+        });
+        writeln!(writer, "{}", line)
+    }
+}
+
+// 收集事件字段为 JSON 对象，跳过 tracing-log 附加的 log.* 元数据字段
+#[derive(Default)]
+struct JsonVisitor {
+    fields: Map<String, Value>,
+}
+
+impl JsonVisitor {
+    fn insert(&mut self, field: &Field, value: Value) {
+        if !field.name().starts_with("log.") {
+            self.fields.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl Visit for JsonVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, Value::from(format!("{:?}", value)));
+    }
+}
+
+/// 按大小滚动的日志文件
+///
+/// 当前文件写满 max_bytes 后依次改名为 `<file>.1`、`<file>.2` ...，
+/// 最多保留 max_files 个历史文件。
+pub struct SizeRollingWriter {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl SizeRollingWriter {
+    pub fn new(directory: &Path, file_name: &str, max_bytes: u64, max_files: usize) -> io::Result<SizeRollingWriter> {
+        fs::create_dir_all(directory)?;
+        let path = directory.join(file_name);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(SizeRollingWriter { path, max_bytes, max_files, file, written })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRollingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + (buf.len() as u64) > self.max_bytes {
+            self.rotate()?;
+        }
+        let size = self.file.write(buf)?;
+        self.written += size as u64;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,522 @@
+// bitcomm 指标注册表
+//
+// 指标通过 `metrics` 门面记录，进程内只安装一个 Prometheus recorder；
+// btcmnetwork / btcmweb 等依赖库只需按下面的指标名调用 `metrics::counter!` 等宏，
+// 无需依赖本 crate 即可汇总到同一个 /metrics 输出中。
+// IM 流量与 NATS 指标由 btcmnetwork 记录 (bitcomm 转接的 TCP+TLS 和 WebSocket 连接由 imbridge 以 transport 标签记录)，这里不预先登记为 0，
+// 依赖库尚未上报时面板显示为未上报，而不是 0。HTTP 耗时由 track_http_metrics 记录在 bitcomm 自己的路由上，
+// btcmweb 的路由需由 btcmweb 挂载该中间件。
+//
+// 面板 (summary) 和看门狗 (service_progress) 读取的序列在写入 Prometheus recorder 的同时累加到进程内的原子变量，
+// 读取时不需要渲染和解析 /metrics 文本。
+
+use crate::supervisor;
+use ::metrics::{
+    counter,
+    describe_counter,
+    describe_gauge,
+    describe_histogram,
+    gauge,
+    histogram,
+    Counter,
+    CounterFn,
+    Gauge,
+    GaugeFn,
+    Histogram,
+    HistogramFn,
+    Key,
+    KeyName,
+    Metadata,
+    Recorder,
+    SharedString,
+    Unit,
+};
+use axum::extract::{ MatchedPath, Request };
+use axum::middleware::Next;
+use axum::response::Response;
+use metrics_exporter_prometheus::{ Matcher, PrometheusBuilder, PrometheusHandle };
+use serde::{ Deserialize, Serialize };
+use std::collections::{ BTreeMap, HashMap };
+use std::error::Error;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::sync::{ Arc, Mutex, OnceLock };
+use std::time::{ Duration, Instant };
+
+/// 服务是否在运行 (1/0)，标签 service
+pub const SERVICE_UP: &str = "bitcomm_service_up";
+/// 服务重启次数，标签 service
+pub const SERVICE_RESTARTS: &str = "bitcomm_service_restarts_total";
+/// 服务处理进度计数，标签 service；依赖库可直接累加，看门狗据此判断服务是否仍在推进
+pub const SERVICE_PROGRESS: &str = "bitcomm_service_progress_total";
+/// 服务进行中的工作数，标签 service；供不经过 supervisor::start_work 的依赖库 (btcmnetwork) 增减，
+/// 看门狗把它与 start_work 登记的工作数相加，有进行中的工作而进度计数不增长时判定卡住
+pub const SERVICE_IN_FLIGHT: &str = "bitcomm_service_in_flight";
+/// 看门狗判定服务卡住的次数，标签 service / action
+pub const WATCHDOG_TRIGGERS: &str = "bitcomm_watchdog_triggers_total";
+/// 资源检查的最新数值，标签 check
+pub const RESOURCE_VALUE: &str = "bitcomm_resource_value";
+/// 资源检查的级别 (0 正常、1 告警、2 严重)，标签 check
+pub const RESOURCE_LEVEL: &str = "bitcomm_resource_level";
+/// 告警通知数，标签 status (firing / resolved) / result (sent / rate_limited)
+pub const ALERT_NOTIFICATIONS: &str = "bitcomm_alert_notifications_total";
+/// 告警通道发送失败次数，标签 sink
+pub const ALERT_SINK_ERRORS: &str = "bitcomm_alert_sink_errors_total";
+
+/// IM 当前连接数；bitcomm 转接的连接带标签 transport (tcp-tls / websocket)
+pub const IM_CONNECTIONS: &str = "bitcomm_im_connections";
+/// IM 新连接数，标签 transport / result (accepted / handshake_error / auth_error / limit / shed / unhandled)
+pub const IM_CONNECTIONS_TOTAL: &str = "bitcomm_im_connections_total";
+/// IM 当前打开的流数量；bitcomm 转接的流带标签 transport
+pub const IM_OPEN_STREAMS: &str = "bitcomm_im_open_streams";
+/// IM 接收字节数；bitcomm 转接的连接带标签 transport
+pub const IM_BYTES_IN: &str = "bitcomm_im_received_bytes_total";
+/// IM 发送字节数；bitcomm 转接的连接带标签 transport
+pub const IM_BYTES_OUT: &str = "bitcomm_im_sent_bytes_total";
+/// 转接连接未能连上 QUIC 端点的次数，标签 transport
+pub const IM_BRIDGE_ERRORS: &str = "bitcomm_im_bridge_errors_total";
+/// 证书距到期的秒数 (已过期时为负数)，标签 cert
+pub const TLS_CERT_EXPIRY: &str = "bitcomm_tls_cert_expiry_seconds";
+/// 证书重新加载次数，标签 cert / result (ok / error)
+pub const TLS_CERT_RELOADS: &str = "bitcomm_tls_cert_reloads_total";
+/// 被拒绝的客户端证书数，标签 reason (revoked / expired / unknown-issuer / no-user-id / invalid)
+pub const TLS_CLIENT_AUTH_FAILURES: &str = "bitcomm_tls_client_auth_failures_total";
+
+/// NATS 发布消息数，由 telemetry::mq_publish_span 计入
+pub const MQ_PUBLISHED: &str = "bitcomm_nats_published_total";
+/// NATS 消费消息数，由 telemetry::mq_consume_span 计入
+pub const MQ_CONSUMED: &str = "bitcomm_nats_consumed_total";
+/// NATS 消费滞后 (未消费的消息数)，标签 stream / consumer；取自 JetStream 消息 ack subject 中的 pending
+pub const MQ_LAG: &str = "bitcomm_nats_consumer_lag";
+
+/// HTTP 请求耗时，标签 method / path / status
+pub const HTTP_REQUEST_DURATION: &str = "bitcomm_http_request_duration_seconds";
+
+/// tokio 工作线程数
+pub const RUNTIME_WORKERS: &str = "bitcomm_tokio_workers";
+/// tokio 存活任务数
+pub const RUNTIME_ALIVE_TASKS: &str = "bitcomm_tokio_alive_tasks";
+/// tokio 全局队列深度
+pub const RUNTIME_GLOBAL_QUEUE_DEPTH: &str = "bitcomm_tokio_global_queue_depth";
+/// 探测任务的调度延迟
+pub const RUNTIME_SCHEDULING_DELAY: &str = "bitcomm_tokio_scheduling_delay_seconds";
+/// 检测到的运行时卡顿次数
+pub const RUNTIME_STALLS: &str = "bitcomm_tokio_stalls_total";
+
+// HTTP 耗时直方图的分桶，单位秒
+const HTTP_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// 调度延迟直方图的分桶，单位秒
+const SCHEDULING_BUCKETS: &[f64] = &[0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+// 需要在进程内读取的指标
+const TRACKED_METRICS: &[&str] = &[
+    IM_CONNECTIONS,
+    IM_OPEN_STREAMS,
+    IM_BYTES_IN,
+    IM_BYTES_OUT,
+    MQ_PUBLISHED,
+    MQ_CONSUMED,
+    MQ_LAG,
+    HTTP_REQUEST_DURATION,
+    SERVICE_PROGRESS,
+    SERVICE_IN_FLIGHT,
+];
+
+static PROMETHEUS: OnceLock<PrometheusHandle> = OnceLock::new();
+
+static TRACKED: OnceLock<Arc<TrackedSeries>> = OnceLock::new();
+
+/// 安装全局 Prometheus recorder 并登记所有指标，重复调用无副作用
+pub fn init_metrics() -> Result<(), Box<dyn Error>> {
+    if PROMETHEUS.get().is_some() {
+        return Ok(());
+    }
+    let prometheus = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Full(HTTP_REQUEST_DURATION.to_string()), HTTP_BUCKETS)?
+        .set_buckets_for_metric(Matcher::Full(RUNTIME_SCHEDULING_DELAY.to_string()), SCHEDULING_BUCKETS)?
+        .build_recorder();
+    let handle = prometheus.handle();
+    let tracked = Arc::new(TrackedSeries::default());
+    ::metrics::set_global_recorder(TrackingRecorder { inner: prometheus, tracked: tracked.clone() })?;
+    let _ = PROMETHEUS.set(handle);
+    let _ = TRACKED.set(tracked);
+
+    describe_gauge!(SERVICE_UP, "Whether the supervised service is running (1) or not (0)");
+    describe_counter!(SERVICE_RESTARTS, "Number of times the supervised service was restarted");
+    describe_counter!(SERVICE_PROGRESS, "Units of work completed by the supervised service");
+    describe_gauge!(SERVICE_IN_FLIGHT, "Units of work in flight reported by the service's library");
+    describe_counter!(WATCHDOG_TRIGGERS, "Number of times the watchdog found the service stuck");
+    describe_gauge!(RESOURCE_VALUE, "Latest value of the watchdog resource check");
+    describe_gauge!(RESOURCE_LEVEL, "Watchdog resource check level: 0 ok, 1 warn, 2 critical");
+    describe_counter!(ALERT_NOTIFICATIONS, "Alert notifications sent or dropped by the rate limit");
+    describe_counter!(ALERT_SINK_ERRORS, "Alert notifications an alert sink failed to deliver");
+    describe_gauge!(IM_CONNECTIONS, "Open connections on the IM server");
+    describe_counter!(IM_CONNECTIONS_TOTAL, "Incoming IM connections by transport and result");
+    describe_gauge!(IM_OPEN_STREAMS, "Open QUIC streams on the IM server");
+    describe_counter!(IM_BYTES_IN, Unit::Bytes, "Bytes received by the IM server");
+    describe_counter!(IM_BYTES_OUT, Unit::Bytes, "Bytes sent by the IM server");
+    describe_counter!(IM_BRIDGE_ERRORS, "Relayed IM connections that could not reach the QUIC endpoint");
+    describe_gauge!(TLS_CERT_EXPIRY, Unit::Seconds, "Seconds until the TLS certificate expires");
+    describe_counter!(TLS_CERT_RELOADS, "TLS certificate reloads after the files changed");
+    describe_counter!(TLS_CLIENT_AUTH_FAILURES, "Client certificates rejected during the TLS handshake");
+    describe_counter!(MQ_PUBLISHED, "Messages published to NATS");
+    describe_counter!(MQ_CONSUMED, "Messages consumed from NATS");
+    describe_gauge!(MQ_LAG, "Messages pending for the NATS consumer");
+    describe_histogram!(HTTP_REQUEST_DURATION, Unit::Seconds, "HTTP request latency");
+    describe_gauge!(RUNTIME_WORKERS, "Tokio runtime worker threads");
+    describe_gauge!(RUNTIME_ALIVE_TASKS, "Tokio tasks currently alive");
+    describe_gauge!(RUNTIME_GLOBAL_QUEUE_DEPTH, "Tasks waiting in the tokio global queue");
+    describe_histogram!(RUNTIME_SCHEDULING_DELAY, Unit::Seconds, "Delay between spawning a stall probe and its first poll");
+    describe_counter!(RUNTIME_STALLS, "Probe rounds whose scheduling delay exceeded the stall threshold");
+
+    // 预先登记由 bitcomm 自己记录的指标，保证即使还没有数据 /metrics 中也能看到
+    counter!(RUNTIME_STALLS).absolute(0);
+    Ok(())
+}
+
+/// 以 Prometheus 文本格式输出当前所有指标
+pub fn render() -> String {
+    PROMETHEUS.get()
+        .map(|handle| handle.render())
+        .unwrap_or_default()
+}
+
+/// 面板关心的指标汇总，计数器为累计值，速率由调用方按两次采样的差值计算
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetricsSummary {
+    /// 是否已有 IM 指标上报
+    #[serde(default)]
+    pub im_reported: bool,
+    pub im_connections: f64,
+    pub im_open_streams: f64,
+    pub im_bytes_in: f64,
+    pub im_bytes_out: f64,
+    /// 是否已有 NATS 指标上报
+    #[serde(default)]
+    pub mq_reported: bool,
+    pub mq_published: f64,
+    pub mq_consumed: f64,
+    pub mq_lag: f64,
+    /// HTTP 请求总数
+    pub http_requests: f64,
+    /// 其中 5xx 应答数
+    pub http_errors: f64,
+}
+
+/// 汇总面板指标，带标签的序列按名称求和
+pub fn summary() -> MetricsSummary {
+    TRACKED.get().map(|tracked| tracked.summary()).unwrap_or_default()
+}
+
+/// 各服务当前的进度计数，从未上报进度的服务不在结果中
+pub fn service_progress() -> BTreeMap<String, f64> {
+    TRACKED.get().map(|tracked| tracked.service_progress()).unwrap_or_default()
+}
+
+/// 依赖库通过 bitcomm_service_in_flight 上报的各服务进行中的工作数
+pub fn service_in_flight() -> BTreeMap<String, f64> {
+    TRACKED.get().map(|tracked| tracked.service_in_flight()).unwrap_or_default()
+}
+
+// 需要在进程内读取的序列的当前值：计数器为累计值，仪表为 f64 的位模式，直方图为记录次数
+#[derive(Debug, Default)]
+struct TrackedSeries {
+    series: Mutex<HashMap<Key, Arc<AtomicU64>>>,
+}
+
+impl TrackedSeries {
+    fn slot(&self, key: &Key) -> Option<Arc<AtomicU64>> {
+        if !TRACKED_METRICS.contains(&key.name()) {
+            return None;
+        }
+        Some(self.series.lock().unwrap().entry(key.clone()).or_default().clone())
+    }
+
+    fn summary(&self) -> MetricsSummary {
+        let mut summary = MetricsSummary::default();
+        for (key, value) in self.series.lock().unwrap().iter() {
+            let count = value.load(Ordering::Relaxed) as f64;
+            let level = f64::from_bits(value.load(Ordering::Relaxed));
+            match key.name() {
+                IM_CONNECTIONS => summary.im_connections += level,
+                IM_OPEN_STREAMS => summary.im_open_streams += level,
+                IM_BYTES_IN => summary.im_bytes_in += count,
+                IM_BYTES_OUT => summary.im_bytes_out += count,
+                MQ_PUBLISHED => summary.mq_published += count,
+                MQ_CONSUMED => summary.mq_consumed += count,
+                MQ_LAG => summary.mq_lag += level,
+                HTTP_REQUEST_DURATION => {
+                    summary.http_requests += count;
+                    if key.labels().any(|label| label.key() == "status" && label.value().starts_with('5')) {
+                        summary.http_errors += count;
+                    }
+                }
+                _ => continue,
+            }
+            summary.im_reported |= key.name().starts_with("bitcomm_im_");
+            summary.mq_reported |= key.name().starts_with("bitcomm_nats_");
+        }
+        summary
+    }
+
+    fn service_progress(&self) -> BTreeMap<String, f64> {
+        self.by_service(SERVICE_PROGRESS, |count| count as f64)
+    }
+
+    fn service_in_flight(&self) -> BTreeMap<String, f64> {
+        self.by_service(SERVICE_IN_FLIGHT, f64::from_bits)
+    }
+
+    // 按 service 标签求和，value 把存储的位转换为数值
+    fn by_service(&self, name: &str, value: impl Fn(u64) -> f64) -> BTreeMap<String, f64> {
+        let mut sums = BTreeMap::new();
+        for (key, stored) in self.series.lock().unwrap().iter() {
+            if key.name() != name {
+                continue;
+            }
+            if let Some(service) = key.labels().find(|label| label.key() == "service") {
+                *sums.entry(service.value().to_string()).or_insert(0.0) += value(stored.load(Ordering::Relaxed));
+            }
+        }
+        sums
+    }
+}
+
+// 包装 Prometheus recorder，把 TRACKED_METRICS 中的序列同时写入 TrackedSeries
+struct TrackingRecorder<R> {
+    inner: R,
+    tracked: Arc<TrackedSeries>,
+}
+
+// 同时更新下层 recorder 的序列和进程内的值
+struct Tee<H> {
+    inner: H,
+    value: Arc<AtomicU64>,
+}
+
+impl CounterFn for Tee<Counter> {
+    fn increment(&self, value: u64) {
+        self.inner.increment(value);
+        self.value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn absolute(&self, value: u64) {
+        self.inner.absolute(value);
+        self.value.fetch_max(value, Ordering::Relaxed);
+    }
+}
+
+impl GaugeFn for Tee<Gauge> {
+    fn increment(&self, value: f64) {
+        self.inner.increment(value);
+        update_f64(&self.value, |current| current + value);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.inner.decrement(value);
+        update_f64(&self.value, |current| current - value);
+    }
+
+    fn set(&self, value: f64) {
+        self.inner.set(value);
+        self.value.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl HistogramFn for Tee<Histogram> {
+    fn record(&self, value: f64) {
+        self.inner.record(value);
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<R: Recorder> Recorder for TrackingRecorder<R> {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_counter(key, unit, description)
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_gauge(key, unit, description)
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_histogram(key, unit, description)
+    }
+
+    fn register_counter(&self, key: &Key, metadata: &Metadata<'_>) -> Counter {
+        let inner = self.inner.register_counter(key, metadata);
+        match self.tracked.slot(key) {
+            Some(value) => Counter::from_arc(Arc::new(Tee { inner, value })),
+            None => inner,
+        }
+    }
+
+    fn register_gauge(&self, key: &Key, metadata: &Metadata<'_>) -> Gauge {
+        let inner = self.inner.register_gauge(key, metadata);
+        match self.tracked.slot(key) {
+            Some(value) => Gauge::from_arc(Arc::new(Tee { inner, value })),
+            None => inner,
+        }
+    }
+
+    fn register_histogram(&self, key: &Key, metadata: &Metadata<'_>) -> Histogram {
+        let inner = self.inner.register_histogram(key, metadata);
+        match self.tracked.slot(key) {
+            Some(value) => Histogram::from_arc(Arc::new(Tee { inner, value })),
+            None => inner,
+        }
+    }
+}
+
+fn update_f64(value: &AtomicU64, update: impl Fn(f64) -> f64) {
+    let _ = value.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| Some(update(f64::from_bits(bits)).to_bits()));
+}
+
+/// 仪表加一并在 drop 时减一，连接数等计数在任务被取消或 panic 时也能恢复
+#[must_use = "the gauge is decremented when the guard is dropped"]
+pub struct GaugeGuard(Gauge);
+
+impl GaugeGuard {
+    pub fn new(gauge: Gauge) -> GaugeGuard {
+        gauge.increment(1.0);
+        GaugeGuard(gauge)
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.decrement(1.0);
+    }
+}
+
+/// 记录一次看门狗动作
+pub fn record_watchdog_trigger(service: &str, action: &'static str) {
+    counter!(WATCHDOG_TRIGGERS, "service" => service.to_string(), "action" => action).increment(1);
+}
+
+/// 记录一次服务重启
+pub fn record_service_restart(service: &'static str) {
+    counter!(SERVICE_RESTARTS, "service" => service).increment(1);
+}
+
+/// 更新服务运行状态 bitcomm_service_up
+pub fn service_up(service: &'static str, up: bool) {
+    gauge!(SERVICE_UP, "service" => service).set(if up { 1.0 } else { 0.0 });
+    // 确保每个服务的重启计数都出现在输出中
+    counter!(SERVICE_RESTARTS, "service" => service).increment(0);
+}
+
+/// 周期性采集 tokio 运行时统计
+pub fn spawn_runtime_collector(interval: Duration) -> tokio::task::JoinHandle<()> {
+    let runtime = tokio::runtime::Handle::current();
+    supervisor::spawn_named("metrics/runtime", async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let stats = runtime.metrics();
+            gauge!(RUNTIME_WORKERS).set(stats.num_workers() as f64);
+            gauge!(RUNTIME_ALIVE_TASKS).set(stats.num_alive_tasks() as f64);
+            gauge!(RUNTIME_GLOBAL_QUEUE_DEPTH).set(stats.global_queue_depth() as f64);
+        }
+    })
+}
+
+/// axum 中间件，记录 HTTP 请求耗时，需通过 `route_layer` 挂载以取得路由模板
+pub async fn track_http_metrics(req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(req).await;
+
+    let status = response.status().as_u16().to_string();
+    histogram!(HTTP_REQUEST_DURATION, "method" => method, "path" => path, "status" => status).record(
+        start.elapsed().as_secs_f64()
+    );
+    response
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// 只在当前线程生效的 recorder 和读取其汇总的函数，供其它模块的测试使用
+    pub(crate) fn local_recorder() -> (impl Recorder, impl Fn() -> MetricsSummary) {
+        let tracked = Arc::new(TrackedSeries::default());
+        let reader = tracked.clone();
+        (TrackingRecorder { inner: ::metrics::NoopRecorder, tracked }, move || reader.summary())
+    }
+
+    fn with_tracking(record: impl FnOnce()) -> Arc<TrackedSeries> {
+        let tracked = Arc::new(TrackedSeries::default());
+        let recorder = TrackingRecorder { inner: ::metrics::NoopRecorder, tracked: tracked.clone() };
+        ::metrics::with_local_recorder(&recorder, record);
+        tracked
+    }
+
+    #[test]
+    fn summary_sums_labelled_series() {
+        let tracked = with_tracking(|| {
+            gauge!(IM_CONNECTIONS).set(3.0);
+            gauge!(IM_CONNECTIONS, "transport" => "tcp-tls").increment(2.0);
+            gauge!(IM_CONNECTIONS, "transport" => "tcp-tls").decrement(1.0);
+            counter!(IM_BYTES_IN).increment(100);
+            counter!(IM_BYTES_IN, "transport" => "tcp-tls").increment(20);
+            histogram!(HTTP_REQUEST_DURATION, "status" => "200").record(0.1);
+            histogram!(HTTP_REQUEST_DURATION, "status" => "503").record(0.2);
+            histogram!(HTTP_REQUEST_DURATION, "status" => "503").record(0.3);
+        });
+        let summary = tracked.summary();
+        assert_eq!(summary.im_connections, 4.0);
+        assert_eq!(summary.im_bytes_in, 120.0);
+        assert_eq!(summary.http_requests, 3.0);
+        assert_eq!(summary.http_errors, 2.0);
+        assert!(summary.im_reported);
+        assert!(!summary.mq_reported);
+    }
+
+    #[test]
+    fn unreported_metrics_are_flagged() {
+        let summary = with_tracking(|| {}).summary();
+        assert!(!summary.im_reported && !summary.mq_reported);
+        assert_eq!(summary.mq_published, 0.0);
+    }
+
+    #[test]
+    fn service_progress_is_grouped_by_service() {
+        let tracked = with_tracking(|| {
+            counter!(SERVICE_PROGRESS, "service" => "imserver").increment(5);
+            counter!(SERVICE_PROGRESS, "service" => "imserver").increment(2);
+            counter!(SERVICE_PROGRESS, "service" => "mqserver").absolute(9);
+            counter!(RUNTIME_STALLS).increment(1);
+            gauge!(SERVICE_IN_FLIGHT, "service" => "imserver").increment(3.0);
+            gauge!(SERVICE_IN_FLIGHT, "service" => "imserver").decrement(1.0);
+        });
+        let progress = tracked.service_progress();
+        assert_eq!(progress.get("imserver"), Some(&7.0));
+        assert_eq!(progress.get("mqserver"), Some(&9.0));
+        assert_eq!(tracked.service_in_flight().get("imserver"), Some(&2.0));
+        assert_eq!(tracked.series.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn gauge_guard_decrements_on_drop() {
+        let (recorder, summary) = local_recorder();
+        ::metrics::with_local_recorder(&recorder, || {
+            let first = GaugeGuard::new(gauge!(IM_CONNECTIONS, "transport" => "websocket"));
+            let second = GaugeGuard::new(gauge!(IM_CONNECTIONS, "transport" => "websocket"));
+            assert_eq!(summary().im_connections, 2.0);
+            drop(first);
+            let unwound = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+                let _second = second;
+                panic!("connection handler panicked");
+            }));
+            assert!(unwound.is_err());
+            assert_eq!(summary().im_connections, 0.0);
+        });
+    }
+}
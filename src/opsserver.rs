@@ -0,0 +1,224 @@
+// 内部运维 HTTP 监听
+//
+// 与公网的 Web Admin Server 分开监听，默认只绑定回环地址，
+// 用于 Prometheus 抓取等运维接口。/admin 下的接口需要 Bearer token。
+
+use crate::buildinfo;
+use crate::config::OpsServerSection;
+use crate::control::{ self, ControlRequest, ControlResponse };
+use crate::journal::JournalQuery;
+use crate::metrics;
+use crate::profiling::{ self, ProfileFormat };
+use crate::{ supervisor, systemd };
+use axum::extract::{ Path, Query, Request, State };
+use axum::http::{ header, StatusCode };
+use axum::middleware::Next;
+use axum::response::{ IntoResponse, Response };
+use axum::routing::{ get, post };
+use axum::{ middleware, Json, Router };
+use serde::Deserialize;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tower_http::trace::TraceLayer;
+use tracing::info;
+
+/// 启动运维 HTTP 服务，直到监听出错才返回
+pub async fn start_ops_server(config: OpsServerSection) -> Result<(), Box<dyn Error>> {
+    let addr = SocketAddr::new(config.ip, config.port);
+    let listener = match systemd::activated_tcp_listener("opsserver", addr) {
+        Some(listener) => tokio::net::TcpListener::from_std(listener)?,
+        None => tokio::net::TcpListener::bind(addr).await?,
+    };
+    info!("Ops Server listening on {}", addr);
+
+    axum::serve(listener, ops_router(&config)).await?;
+    Ok(())
+}
+
+/// 运维接口路由
+pub fn ops_router(config: &OpsServerSection) -> Router {
+    let admin_token = Arc::new(config.admin_token.clone());
+    let admin = Router::new()
+        .route("/admin/log-level", get(get_log_level).put(set_log_level))
+        .route("/admin/status", get(get_status))
+        .route("/admin/drain", post(drain))
+        .route("/admin/services/:service/restart", post(restart_service))
+        .route("/admin/journal", get(get_journal))
+        .route("/admin/profile/heap", get(heap_stats))
+        .route_layer(middleware::from_fn_with_state("opsserver", supervisor::track_work))
+        // CPU 采样可持续 profiling::MAX_SECONDS，不计为看门狗检查的进行中工作
+        .route("/admin/profile/cpu", get(cpu_profile))
+        .route_layer(middleware::from_fn_with_state(admin_token, require_admin_token));
+
+    Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/version", get(version_handler))
+        .route_layer(middleware::from_fn_with_state("opsserver", supervisor::track_work))
+        .merge(admin)
+        .route_layer(middleware::from_fn(metrics::track_http_metrics))
+        .layer(TraceLayer::new_for_http())
+}
+
+/// GET /version，构建信息
+async fn version_handler() -> Json<buildinfo::BuildInfo> {
+    Json(buildinfo::build_info())
+}
+
+/// 校验 Authorization: Bearer <admin_token>，未配置 token 时拒绝所有请求
+async fn require_admin_token(State(token): State<Arc<String>>, req: Request, next: Next) -> Response {
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match provided {
+        Some(provided) if !token.is_empty() && constant_time_eq(provided.as_bytes(), token.as_bytes()) => {
+            next.run(req).await
+        }
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+// 比较耗时与内容无关，避免逐字节比较泄露 token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// 控制命令的 HTTP 应答，失败时返回 400
+fn control_response(response: ControlResponse) -> Response {
+    let status = if response.ok { StatusCode::OK } else { StatusCode::BAD_REQUEST };
+    (status, Json(response)).into_response()
+}
+
+/// PUT /admin/log-level 请求体
+#[derive(Debug, Deserialize)]
+struct LogLevelBody {
+    filter: String,
+    #[serde(default)]
+    ttl_secs: Option<u64>,
+}
+
+/// GET /admin/log-level
+async fn get_log_level() -> Response {
+    control_response(control::dispatch(ControlRequest::GetLogLevel).await)
+}
+
+/// PUT /admin/log-level
+async fn set_log_level(Json(body): Json<LogLevelBody>) -> Response {
+    let request = ControlRequest::SetLogLevel { filter: body.filter, ttl_secs: body.ttl_secs };
+    control_response(control::dispatch(request).await)
+}
+
+/// GET /admin/status
+async fn get_status() -> Response {
+    control_response(control::dispatch(ControlRequest::Status).await)
+}
+
+/// POST /admin/drain
+async fn drain() -> Response {
+    control_response(control::dispatch(ControlRequest::Drain).await)
+}
+
+/// POST /admin/services/:service/restart
+async fn restart_service(Path(service): Path<String>) -> Response {
+    control_response(control::dispatch(ControlRequest::RestartService { service }).await)
+}
+
+/// GET /admin/journal?limit=&service=&kind=
+async fn get_journal(Query(query): Query<JournalQuery>) -> Response {
+    control_response(control::dispatch(ControlRequest::Journal(query)).await)
+}
+
+/// GET /admin/profile/cpu 查询参数
+#[derive(Debug, Deserialize)]
+struct CpuProfileParams {
+    #[serde(default = "default_profile_seconds")]
+    seconds: u64,
+    #[serde(default)]
+    format: ProfileFormat,
+}
+
+fn default_profile_seconds() -> u64 {
+    30
+}
+
+/// GET /admin/profile/cpu?seconds=30&format=pprof|flamegraph，直接返回剖析数据
+async fn cpu_profile(Query(params): Query<CpuProfileParams>) -> Response {
+    match profiling::cpu_profile(params.seconds, params.format).await {
+        Ok(profile) => {
+            let file_name = format!("attachment; filename=\"bitcomm.{}\"", params.format.extension());
+            (
+                [(header::CONTENT_TYPE, params.format.content_type().to_string()), (header::CONTENT_DISPOSITION, file_name)],
+                profile,
+            ).into_response()
+        }
+        Err(e) => control_response(ControlResponse::error(e)),
+    }
+}
+
+/// GET /admin/profile/heap
+async fn heap_stats() -> Response {
+    control_response(control::dispatch(ControlRequest::HeapStats).await)
+}
+
+/// GET /metrics，Prometheus 文本格式
+async fn metrics_handler() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics::render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imbridge::{ self, Identity };
+    use crate::telemetry;
+    use crate::tls::tests::temp_dir;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn metrics_are_served_in_prometheus_text_format() {
+        metrics::init_metrics().unwrap();
+        metrics::service_up("opstest", true);
+        metrics::record_service_restart("opstest");
+        let collector = metrics::spawn_runtime_collector(Duration::from_millis(10));
+
+        // MQ 的 span 和一次转接记录的指标
+        drop(telemetry::mq_publish_span("im.msg.opstest"));
+        drop(telemetry::mq_consume_span("im.msg.opstest", Some("$JS.ACK.OPSTEST.worker.1.2.2.1700000000000000000.5"), []));
+        let dir = temp_dir("opsserver-metrics");
+        let (bridge, _server) = imbridge::tests::echo_bridge(&dir);
+        let (mut client, relayed) = tokio::io::duplex(1024);
+        client.shutdown().await.unwrap();
+        bridge.relay(&Identity::new("tcp-tls", None), relayed).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, ops_router(&OpsServerSection::default())).await.unwrap() });
+        let client = reqwest::Client::new();
+        assert!(client.get(format!("http://{}/version", addr)).send().await.unwrap().status().is_success());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let response = client.get(format!("http://{}/metrics", addr)).send().await.unwrap();
+        assert!(response.headers()[reqwest::header::CONTENT_TYPE].to_str().unwrap().starts_with("text/plain"));
+        let body = response.text().await.unwrap();
+        collector.abort();
+
+        assert!(body.contains("bitcomm_service_up{service=\"opstest\"} 1"), "{}", body);
+        assert!(body.contains("bitcomm_service_restarts_total{service=\"opstest\"} 1"), "{}", body);
+        assert!(body.contains("# TYPE bitcomm_http_request_duration_seconds histogram"), "{}", body);
+        assert!(body.lines().any(|line| line.starts_with("bitcomm_http_request_duration_seconds_count") && line.contains("path=\"/version\"")), "{}", body);
+        assert!(body.contains("bitcomm_tokio_workers 1"), "{}", body);
+        assert!(body.contains("bitcomm_tokio_stalls_total 0"), "{}", body);
+        assert!(body.lines().any(|line| line.starts_with("bitcomm_nats_published_total ")), "{}", body);
+        assert!(body.lines().any(|line| line.starts_with("bitcomm_nats_consumed_total ")), "{}", body);
+        assert!(
+            body.lines().any(|line| line.starts_with("bitcomm_nats_consumer_lag{") && line.contains("stream=\"OPSTEST\"") && line.ends_with(" 5")),
+            "{}",
+            body
+        );
+        assert!(body.contains("bitcomm_im_open_streams{transport=\"tcp-tls\"} 0"), "{}", body);
+    }
+}
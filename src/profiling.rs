@@ -0,0 +1,157 @@
+// 按需性能剖析
+//
+// CPU：在运行中的进程上按固定频率采样 N 秒，输出 pprof (protobuf) 或火焰图 (SVG)，
+//      同一时间只允许一次采样。
+// 堆：glibc malloc 下通过 mallinfo2 读取分配器统计，其他分配器只返回 RSS。
+// 通过 /admin/profile/* (需要 admin token) 和控制 socket 提供。
+
+use pprof::protos::Message;
+use serde::{ Deserialize, Serialize };
+use std::error::Error;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::time::Duration;
+use tracing::info;
+
+/// 默认采样频率 (Hz)，避开 100 以免与定时任务同频
+pub const DEFAULT_FREQUENCY: i32 = 99;
+/// 单次 CPU 采样的最长时间，单位秒
+pub const MAX_SECONDS: u64 = 300;
+
+// 不在这些库中采样，避免在信号处理中展开 libc 等内部栈时出错
+const BLOCKLIST: &[&str] = &["libc", "libgcc", "pthread", "vdso"];
+
+static PROFILING: AtomicBool = AtomicBool::new(false);
+
+/// CPU 剖析输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileFormat {
+    /// pprof protobuf，可用 `go tool pprof` 打开
+    #[default]
+    Pprof,
+    /// 火焰图 SVG
+    Flamegraph,
+}
+
+impl ProfileFormat {
+    /// 输出文件的扩展名
+    pub fn extension(self) -> &'static str {
+        match self {
+            ProfileFormat::Pprof => "pb",
+            ProfileFormat::Flamegraph => "svg",
+        }
+    }
+
+    /// HTTP 应答的 Content-Type
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ProfileFormat::Pprof => "application/octet-stream",
+            ProfileFormat::Flamegraph => "image/svg+xml",
+        }
+    }
+}
+
+impl std::str::FromStr for ProfileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ProfileFormat, String> {
+        match s {
+            "pprof" => Ok(ProfileFormat::Pprof),
+            "flamegraph" | "svg" => Ok(ProfileFormat::Flamegraph),
+            other => Err(format!("unknown profile format {}, expected pprof or flamegraph", other)),
+        }
+    }
+}
+
+// 采样期间持有，析构时允许下一次采样
+struct ProfilingGuard;
+
+impl ProfilingGuard {
+    fn acquire() -> Result<ProfilingGuard, Box<dyn Error + Send + Sync>> {
+        if PROFILING.swap(true, Ordering::SeqCst) {
+            return Err("a CPU profile is already being captured".into());
+        }
+        Ok(ProfilingGuard)
+    }
+}
+
+impl Drop for ProfilingGuard {
+    fn drop(&mut self) {
+        PROFILING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// 采样 CPU seconds 秒，返回指定格式的剖析数据
+pub async fn cpu_profile(seconds: u64, format: ProfileFormat) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    if seconds == 0 || seconds > MAX_SECONDS {
+        return Err(format!("seconds must be between 1 and {}", MAX_SECONDS).into());
+    }
+    let _running = ProfilingGuard::acquire()?;
+    info!(seconds, ?format, "CPU profile started");
+
+    let guard = pprof::ProfilerGuardBuilder::default().frequency(DEFAULT_FREQUENCY).blocklist(BLOCKLIST).build()?;
+    tokio::time::sleep(Duration::from_secs(seconds)).await;
+    let report = guard.report().build()?;
+    drop(guard);
+
+    let mut output = Vec::new();
+    match format {
+        ProfileFormat::Pprof => report.pprof()?.encode(&mut output)?,
+        ProfileFormat::Flamegraph => report.flamegraph(&mut output)?,
+    }
+    info!(seconds, ?format, bytes = output.len(), "CPU profile finished");
+    Ok(output)
+}
+
+/// 堆统计
+#[derive(Debug, Clone, Serialize)]
+pub struct HeapStats {
+    /// 分配器名称，不支持统计时为 None
+    pub allocator: Option<&'static str>,
+    /// 进程常驻内存
+    pub rss_bytes: Option<u64>,
+    /// brk 分配的堆大小
+    pub arena_bytes: Option<u64>,
+    /// mmap 分配的大块内存
+    pub mmap_bytes: Option<u64>,
+    /// 已分配给程序使用的字节数
+    pub in_use_bytes: Option<u64>,
+    /// 堆中空闲的字节数
+    pub free_bytes: Option<u64>,
+    /// 堆顶可通过 malloc_trim 归还给系统的字节数
+    pub releasable_bytes: Option<u64>,
+}
+
+/// 读取堆统计
+pub fn heap_stats() -> HeapStats {
+    let mut stats = HeapStats {
+        allocator: None,
+        rss_bytes: rss_bytes(),
+        arena_bytes: None,
+        mmap_bytes: None,
+        in_use_bytes: None,
+        free_bytes: None,
+        releasable_bytes: None,
+    };
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    {
+        // SAFETY: mallinfo2 只读取分配器的统计信息
+        let info = unsafe { libc::mallinfo2() };
+        stats.allocator = Some("glibc malloc");
+        stats.arena_bytes = Some(info.arena as u64);
+        stats.mmap_bytes = Some(info.hblkhd as u64);
+        stats.in_use_bytes = Some((info.uordblks + info.hblkhd) as u64);
+        stats.free_bytes = Some(info.fordblks as u64);
+        stats.releasable_bytes = Some(info.keepcost as u64);
+    }
+    stats
+}
+
+// /proc/self/statm 第二列为常驻页数
+fn rss_bytes() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf 只查询系统配置
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * (page_size.max(0) as u64))
+}
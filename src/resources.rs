@@ -0,0 +1,257 @@
+// 看门狗资源检查
+//
+// 每个巡检周期依次执行 [[wdserver.checks]]，数值写入 bitcomm_resource_value，级别写入 bitcomm_resource_level
+// (0 正常、1 告警、2 严重)。级别变化时执行该级别配置的动作，同一级别持续期间不重复执行。
+// shed 在所有检查都不再要求时自动解除，期间就绪检查失败，bitcomm 自己接入的 TCP+TLS 备用传输和 WebSocket 网关
+// 经 supervisor::admit_connection 拒绝新连接。QUIC 连接由 btcmnetwork 接受，不经过这项检查，
+// 只能靠就绪检查失败让负载均衡停止分配新流量；
+// drain-and-restart 开始排空后等待 drain_grace_secs 秒重启进程。
+
+use crate::alerting::{ self, AlertSeverity };
+use crate::config::{ BitcommConfig, ResourceAction, ResourceCheck, ResourceKind };
+use crate::journal::{ self, EventKind };
+use crate::{ health, metrics, profiling, supervisor, watchdog };
+use ::metrics::gauge;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tracing::{ error, info, warn };
+
+const MB: f64 = 1024.0 * 1024.0;
+
+/// 检查结果的级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Ok,
+    Warn,
+    Critical,
+}
+
+/// 按配置执行资源检查并处理级别变化
+pub struct ResourceMonitor {
+    checks: Vec<ResourceCheck>,
+    levels: Vec<Severity>,
+    redis: String,
+    nats: String,
+    timeout: Duration,
+    drain_grace: Duration,
+}
+
+impl ResourceMonitor {
+    pub fn new(config: &BitcommConfig) -> ResourceMonitor {
+        ResourceMonitor {
+            checks: config.wdserver.checks.clone(),
+            levels: vec![Severity::Ok; config.wdserver.checks.len()],
+            redis: config.bitcomm.redis.clone(),
+            nats: config.bitcomm.nats.clone(),
+            timeout: Duration::from_millis(config.health.check_timeout_ms),
+            drain_grace: Duration::from_secs(config.wdserver.drain_grace_secs),
+        }
+    }
+
+    /// 执行一轮检查
+    pub async fn check(&mut self) {
+        let mut shed = false;
+        for index in 0..self.checks.len() {
+            let check = self.checks[index].clone();
+            let name = check.name();
+            let (level, detail) = match self.measure(&check).await {
+                Ok(value) => {
+                    gauge!(metrics::RESOURCE_VALUE, "check" => name.clone()).set(value);
+                    (severity(&check, value), format!("value {:.1} (warn {}, critical {})", value, check.warn, check.critical))
+                }
+                Err(e) => (Severity::Critical, e),
+            };
+            gauge!(metrics::RESOURCE_LEVEL, "check" => name.clone()).set(level as u8 as f64);
+
+            let previous = std::mem::replace(&mut self.levels[index], level);
+            if level != previous {
+                match level {
+                    Severity::Ok => {
+                        alerting::resolve(format!("resource:{}", name), detail.clone());
+                        info!(check = %name, "resource check back to normal: {}", detail)
+                    }
+                    Severity::Warn => self.act(&name, level, check.on_warn, &detail),
+                    Severity::Critical => self.act(&name, level, check.on_critical, &detail),
+                }
+            }
+            shed |= match level {
+                Severity::Ok => false,
+                Severity::Warn => check.on_warn == ResourceAction::Shed,
+                Severity::Critical => check.on_critical == ResourceAction::Shed,
+            };
+        }
+        supervisor::set_shedding(shed);
+    }
+
+    async fn measure(&self, check: &ResourceCheck) -> Result<f64, String> {
+        match check.kind {
+            ResourceKind::Rss => {
+                profiling::heap_stats().rss_bytes.map(|bytes| (bytes as f64) / MB).ok_or_else(|| "RSS unavailable".to_string())
+            }
+            ResourceKind::Fds => fd_usage_percent(),
+            ResourceKind::Disk => disk_free_mb(&check.path),
+            ResourceKind::Redis => {
+                health::redis_round_trip(&self.redis, self.timeout).await.map(|elapsed| elapsed.as_secs_f64() * 1000.0)
+            }
+            ResourceKind::Nats => {
+                health::nats_round_trip(&self.nats, self.timeout).await.map(|elapsed| elapsed.as_secs_f64() * 1000.0)
+            }
+            ResourceKind::QueueDepth => Ok(Handle::current().metrics().global_queue_depth() as f64),
+        }
+    }
+
+    fn act(&self, name: &str, level: Severity, action: ResourceAction, detail: &str) {
+        if action != ResourceAction::Log {
+            let severity = if level == Severity::Critical { AlertSeverity::Critical } else { AlertSeverity::Warning };
+            alerting::fire(format!("resource:{}", name), severity, None, format!("resource check {} over threshold", name), detail);
+        }
+        let level = if level == Severity::Critical { "critical" } else { "warn" };
+        match action {
+            ResourceAction::Log => warn!(check = name, level, "resource check over threshold: {}", detail),
+            ResourceAction::Alert => {
+                journal::record(EventKind::ThresholdCrossed, None, format!("{} {}: {}", name, level, detail));
+                error!(check = name, level, "resource check over threshold: {}", detail);
+            }
+            ResourceAction::Shed => {
+                journal::record(EventKind::ThresholdCrossed, None, format!("{} {}: {}, shedding", name, level, detail));
+                warn!(check = name, level, "resource check over threshold, shedding new connections: {}", detail);
+            }
+            ResourceAction::DrainAndRestart => {
+                journal::record(EventKind::ThresholdCrossed, None, format!("{} {}: {}, drain and restart", name, level, detail));
+                error!(check = name, level, grace_secs = self.drain_grace.as_secs(), "resource check over threshold, draining before restart: {}", detail);
+                if !supervisor::begin_drain() {
+                    let grace = self.drain_grace;
+                    supervisor::spawn_named("wdserver/drain-restart", async move {
+                        tokio::time::sleep(grace).await;
+                        watchdog::restart_process();
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn severity(check: &ResourceCheck, value: f64) -> Severity {
+    let over = |threshold: f64| if check.kind.lower_is_worse() { value <= threshold } else { value >= threshold };
+    if over(check.critical) {
+        Severity::Critical
+    } else if over(check.warn) {
+        Severity::Warn
+    } else {
+        Severity::Ok
+    }
+}
+
+// 打开的文件描述符数占软限制的百分比
+fn fd_usage_percent() -> Result<f64, String> {
+    let open = open_fds()?;
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: getrlimit 只写入传入的结构体
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(format!("getrlimit {}", std::io::Error::last_os_error()));
+    }
+    Ok(usage_percent(open, limit.rlim_cur))
+}
+
+// /proc/self/fd 中的条目数，不含 read_dir 自己打开的目录
+fn open_fds() -> Result<usize, String> {
+    Ok(std::fs::read_dir("/proc/self/fd").map_err(|e| format!("/proc/self/fd {}", e))?.count().saturating_sub(1))
+}
+
+// 没有软限制时按 0 计
+fn usage_percent(open: usize, soft_limit: libc::rlim_t) -> f64 {
+    if soft_limit == libc::RLIM_INFINITY || soft_limit == 0 {
+        return 0.0;
+    }
+    ((open as f64) / (soft_limit as f64)) * 100.0
+}
+
+// 非特权用户可用的剩余空间
+fn disk_free_mb(path: &Path) -> Result<f64, String> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| format!("{} {}", path.display(), e))?;
+    // SAFETY: 以零值初始化 statvfs 后由系统调用填写
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(format!("{} {}", path.display(), std::io::Error::last_os_error()));
+    }
+    Ok(((stat.f_bavail as f64) * (stat.f_frsize as f64)) / MB)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::HealthStatus;
+    use crate::supervisor::tests::ADMISSION;
+    use std::fs::File;
+    use std::path::PathBuf;
+
+    fn check(kind: ResourceKind, warn: f64, critical: f64) -> ResourceCheck {
+        ResourceCheck { kind, path: PathBuf::from("/"), warn, critical, on_warn: ResourceAction::Log, on_critical: ResourceAction::Alert }
+    }
+
+    #[test]
+    fn thresholds_map_to_severity() {
+        let fds = check(ResourceKind::Fds, 80.0, 95.0);
+        assert_eq!(severity(&fds, 10.0), Severity::Ok);
+        assert_eq!(severity(&fds, 80.0), Severity::Warn);
+        assert_eq!(severity(&fds, 94.9), Severity::Warn);
+        assert_eq!(severity(&fds, 95.0), Severity::Critical);
+        assert_eq!(severity(&fds, 120.0), Severity::Critical);
+
+        // 剩余空间越小越糟糕
+        let disk = check(ResourceKind::Disk, 1024.0, 256.0);
+        assert_eq!(severity(&disk, 4096.0), Severity::Ok);
+        assert_eq!(severity(&disk, 1024.0), Severity::Warn);
+        assert_eq!(severity(&disk, 300.0), Severity::Warn);
+        assert_eq!(severity(&disk, 256.0), Severity::Critical);
+        assert_eq!(severity(&disk, 0.0), Severity::Critical);
+    }
+
+    #[test]
+    fn fd_usage_follows_open_files_and_the_soft_limit() {
+        assert_eq!(usage_percent(50, 1000), 5.0);
+        assert_eq!(usage_percent(1000, 1000), 100.0);
+        assert_eq!(usage_percent(50, libc::RLIM_INFINITY), 0.0);
+        assert_eq!(usage_percent(50, 0), 0.0);
+
+        // 其他测试并行打开和关闭 socket，只比较大致的增量
+        let before = open_fds().unwrap();
+        let files: Vec<File> = (0..100).map(|_| File::open("/dev/null").unwrap()).collect();
+        assert!(open_fds().unwrap() >= before + 90);
+        let percent = fd_usage_percent().unwrap();
+        assert!(percent > 0.0 && percent < 100.0, "{}", percent);
+        drop(files);
+        assert!(open_fds().unwrap() < before + 90);
+    }
+
+    #[tokio::test]
+    async fn shed_checks_refuse_new_connections_until_back_to_normal() {
+        let _admission = ADMISSION.lock().await;
+        let mut config = BitcommConfig::default();
+        config.wdserver.checks = vec![ResourceCheck { on_warn: ResourceAction::Shed, ..check(ResourceKind::Fds, 0.0, 100.0) }];
+        let mut monitor = ResourceMonitor::new(&config);
+
+        // 外部依赖指向不可达的地址，就绪检查只关心 load 一项
+        config.bitcomm.nats = "nats://127.0.0.1:1".to_string();
+        config.bitcomm.redis = "redis://127.0.0.1:1".to_string();
+        config.health.check_timeout_ms = 100;
+
+        monitor.check().await;
+        assert_eq!(monitor.levels, vec![Severity::Warn]);
+        let admitted = supervisor::admit_connection("test");
+        let shedding = supervisor::is_shedding();
+        let load = health::readiness(&config).await.components["load"].status;
+
+        monitor.checks[0].warn = 100.0;
+        monitor.check().await;
+        assert!(shedding && !admitted);
+        assert_eq!(load, HealthStatus::Fail);
+        assert_eq!(health::readiness(&config).await.components["load"].status, HealthStatus::Ok);
+        assert_eq!(monitor.levels, vec![Severity::Ok]);
+        assert!(!supervisor::is_shedding());
+        assert!(supervisor::admit_connection("test"));
+    }
+}
@@ -0,0 +1,303 @@
+// slog -> tracing 桥接
+//
+// btcmtools / btcmnetwork / btcmweb 仍然通过 slog 输出日志。
+// TracingDrain 把每条 slog 记录转发为 tracing 事件，target 取 slog 记录的模块路径，
+// 键值对作为事件的结构化字段 (与 tracing 宏的字段相同，JSON sink 中位于 fields 下)，
+// 因此 [log] filter / RUST_LOG 中按 crate 配置的过滤规则、日志 sink 和 OTLP 导出
+// 对这些依赖库同样生效。
+//
+// tracing 的字段名在 callsite 中静态声明，而 slog 的键值对在运行时才确定，
+// 这里按 (日志位置, 级别, 键) 为每种组合登记一个 callsite，登记后常驻内存，数量与日志语句数相当。
+//
+// 进程内使用 LOGGER (与 btcmtools::LOGGER 用法相同)；依赖库应以 `slogbridge::logger()` 构建自己的 LOGGER。
+
+use slog::{ Drain, Key, Level, OwnedKVList, Record, Serializer, KV };
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{ LazyLock, Mutex, OnceLock, PoisonError };
+use tracing::callsite::{ Callsite, Identifier };
+use tracing::field::{ Field, FieldSet, Value };
+use tracing::level_filters::LevelFilter;
+use tracing::metadata::{ Kind, Metadata };
+use tracing::subscriber::Interest;
+use tracing::{ dispatcher, Event };
+
+/// 进程内共用的 slog Logger
+pub static LOGGER: LazyLock<slog::Logger> = LazyLock::new(logger);
+
+// 单条记录转发的字段数上限 (含 message)，ValueSet 需要定长数组，超出的键值对被丢弃
+const MAX_FIELDS: usize = 32;
+
+/// 把 slog 记录转发到 tracing 的 Drain
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingDrain;
+
+impl Drain for TracingDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        let level = match record.level() {
+            Level::Critical | Level::Error => tracing::Level::ERROR,
+            Level::Warning => tracing::Level::WARN,
+            Level::Info => tracing::Level::INFO,
+            Level::Debug => tracing::Level::DEBUG,
+            Level::Trace => tracing::Level::TRACE,
+        };
+        if level > LevelFilter::current() {
+            return Ok(());
+        }
+
+        // 先收集记录自身的键值对，再收集 Logger 上绑定的；同名时保留前者，与 slog 的覆盖规则一致。
+        // slog 按声明的逆序输出，每组收集后再反转回声明顺序
+        let mut kv = KvCollector::default();
+        let _ = record.kv().serialize(record, &mut kv);
+        let own = kv.0.len();
+        kv.0.reverse();
+        let _ = values.serialize(record, &mut kv);
+        kv.0[own..].reverse();
+
+        let keys: Vec<Key> = kv.0.iter().map(|(key, _)| *key).collect();
+        let metadata = callsite(record, level, keys);
+        dispatcher::get_default(|dispatch| {
+            if !dispatch.enabled(metadata) {
+                return;
+            }
+            let fields: Vec<Field> = metadata.fields().iter().collect();
+            let message = *record.msg();
+            let mut values: [(&Field, Option<&dyn Value>); MAX_FIELDS] = [(&fields[0], None); MAX_FIELDS];
+            values[0].1 = Some(&message);
+            for (i, (_, value)) in kv.0.iter().enumerate() {
+                values[i + 1] = (&fields[i + 1], Some(value.as_value()));
+            }
+            dispatch.event(&Event::new(metadata, &metadata.fields().value_set(&values)));
+        });
+        Ok(())
+    }
+}
+
+/// 以 TracingDrain 为根的 slog Logger
+pub fn logger() -> slog::Logger {
+    slog::Logger::root(TracingDrain, slog::o!())
+}
+
+// slog 记录对应的 tracing callsite
+struct SlogCallsite {
+    metadata: OnceLock<Metadata<'static>>,
+}
+
+impl Callsite for SlogCallsite {
+    fn set_interest(&self, _interest: Interest) {}
+
+    fn metadata(&self) -> &Metadata<'_> {
+        self.metadata.get().expect("callsite metadata is set before registration")
+    }
+}
+
+type CallsiteKey = (&'static str, &'static str, u32, tracing::Level, Vec<Key>);
+
+static CALLSITES: LazyLock<Mutex<HashMap<CallsiteKey, &'static SlogCallsite>>> = LazyLock::new(Default::default);
+
+// 取出或登记 callsite，字段依次为 message 和各个键
+fn callsite(record: &Record, level: tracing::Level, keys: Vec<Key>) -> &'static Metadata<'static> {
+    let mut callsites = CALLSITES.lock().unwrap_or_else(PoisonError::into_inner);
+    let key = (record.module(), record.file(), record.line(), level, keys);
+    if let Some(callsite) = callsites.get(&key) {
+        return callsite.metadata.get().expect("registered callsite has metadata");
+    }
+
+    let callsite: &'static SlogCallsite = Box::leak(Box::new(SlogCallsite { metadata: OnceLock::new() }));
+    let names: Vec<&'static str> = std::iter::once("message").chain(key.4.iter().copied()).collect();
+    let fields = FieldSet::new(Box::leak(names.into_boxed_slice()), Identifier(callsite));
+    let name: &'static str = Box::leak(format!("event {}:{}", record.file(), record.line()).into_boxed_str());
+    let metadata = Metadata::new(name, record.module(), level, Some(record.file()), Some(record.line()), Some(record.module()), fields, Kind::EVENT);
+    let _ = callsite.metadata.set(metadata);
+    tracing::callsite::register(callsite);
+    callsites.insert(key, callsite);
+    callsite.metadata.get().expect("callsite metadata was just set")
+}
+
+// 保留类型的键值
+enum KvValue {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Str(String),
+}
+
+impl KvValue {
+    fn as_value(&self) -> &dyn Value {
+        match self {
+            KvValue::Bool(value) => value,
+            KvValue::I64(value) => value,
+            KvValue::U64(value) => value,
+            KvValue::F64(value) => value,
+            KvValue::Str(value) => value,
+        }
+    }
+}
+
+// 按出现顺序收集键值对，跳过重复的键和与 message 同名的键
+#[derive(Default)]
+struct KvCollector(Vec<(Key, KvValue)>);
+
+impl KvCollector {
+    fn push(&mut self, key: Key, value: KvValue) -> slog::Result {
+        if self.0.len() + 1 < MAX_FIELDS && key != "message" && !self.0.iter().any(|(existing, _)| *existing == key) {
+            self.0.push((key, value));
+        }
+        Ok(())
+    }
+}
+
+impl Serializer for KvCollector {
+    fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
+        self.push(key, KvValue::Bool(val))
+    }
+
+    fn emit_i32(&mut self, key: Key, val: i32) -> slog::Result {
+        self.push(key, KvValue::I64(val.into()))
+    }
+
+    fn emit_i64(&mut self, key: Key, val: i64) -> slog::Result {
+        self.push(key, KvValue::I64(val))
+    }
+
+    fn emit_isize(&mut self, key: Key, val: isize) -> slog::Result {
+        self.push(key, KvValue::I64(val as i64))
+    }
+
+    fn emit_u32(&mut self, key: Key, val: u32) -> slog::Result {
+        self.push(key, KvValue::U64(val.into()))
+    }
+
+    fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
+        self.push(key, KvValue::U64(val))
+    }
+
+    fn emit_usize(&mut self, key: Key, val: usize) -> slog::Result {
+        self.push(key, KvValue::U64(val as u64))
+    }
+
+    fn emit_f64(&mut self, key: Key, val: f64) -> slog::Result {
+        self.push(key, KvValue::F64(val))
+    }
+
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        self.push(key, KvValue::Str(val.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tracing::field::Visit;
+    use tracing::span::{ Attributes, Id, Record as SpanRecord };
+    use tracing::Subscriber;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Captured {
+        level: tracing::Level,
+        target: String,
+        fields: Vec<(String, String)>,
+    }
+
+    // 记录收到的事件，充当 tracing subscriber
+    #[derive(Default, Clone)]
+    struct Capture(Arc<Mutex<Vec<Captured>>>);
+
+    struct FieldVisitor(Vec<(String, String)>);
+
+    impl Visit for FieldVisitor {
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            self.0.push((field.name().to_string(), format!("u64:{}", value)));
+        }
+
+        fn record_bool(&mut self, field: &Field, value: bool) {
+            self.0.push((field.name().to_string(), format!("bool:{}", value)));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.push((field.name().to_string(), value.to_string()));
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl Subscriber for Capture {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            *metadata.level() <= tracing::Level::INFO
+        }
+
+        fn new_span(&self, _span: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &Id, _values: &SpanRecord<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut visitor = FieldVisitor(Vec::new());
+            event.record(&mut visitor);
+            let metadata = event.metadata();
+            self.0.lock().unwrap().push(Captured { level: *metadata.level(), target: metadata.target().to_string(), fields: visitor.0 });
+        }
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn key_values_become_structured_fields() {
+        let capture = Capture::default();
+        tracing::subscriber::with_default(capture.clone(), || {
+            let log = logger().new(slog::o!("service" => "imserver", "peer" => "bound"));
+            slog::info!(log, "session {} opened", 7; "peer" => "10.0.0.1", "sessions" => 3usize, "tls" => true);
+            slog::warn!(log, "plain");
+            slog::debug!(log, "filtered out");
+        });
+
+        let events = capture.0.lock().unwrap().clone();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].level, tracing::Level::INFO);
+        assert_eq!(events[0].target, module_path!());
+        assert_eq!(
+            events[0].fields,
+            vec![
+                pair("message", "session 7 opened"),
+                pair("peer", "10.0.0.1"),
+                pair("sessions", "u64:3"),
+                pair("tls", "bool:true"),
+                pair("service", "imserver")
+            ]
+        );
+        assert_eq!(events[1].level, tracing::Level::WARN);
+        assert_eq!(events[1].fields, vec![pair("message", "plain"), pair("service", "imserver"), pair("peer", "bound")]);
+    }
+
+    #[test]
+    fn repeated_records_reuse_their_callsite() {
+        let capture = Capture::default();
+        tracing::subscriber::with_default(capture.clone(), || {
+            for i in 0..3u64 {
+                slog::info!(LOGGER, "tick"; "n" => i);
+            }
+        });
+        let events = capture.0.lock().unwrap().clone();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2].fields, vec![pair("message", "tick"), pair("n", "u64:2")]);
+
+        let line_callsites = CALLSITES.lock().unwrap().keys().filter(|key| key.4 == vec!["n"]).count();
+        assert_eq!(line_callsites, 1);
+    }
+}
@@ -0,0 +1,354 @@
+// tokio 运行时卡顿检测
+//
+// 独立的 OS 线程每隔 interval 向运行时投递与工作线程数相同的探测任务，记录从投递到开始执行的调度延迟。
+// tokio 不能把任务指定到某个工作线程，探测任务经全局队列由空闲的工作线程领取；
+// 有任务长时间占住工作线程 (同步 IO、CPU 密集计算、阻塞锁) 时，探测任务排队，延迟随之上升。
+// 延迟超过阈值时告警；启用 sample_stacks 时用信号抓取各工作线程的调用栈，把没有停在 park 中的线程的调用栈随告警一起输出。
+// 检测线程本身不在运行时上，运行时完全卡住时也能告警。
+//
+// 抓栈的信号处理函数调用 backtrace::trace_unsynchronized，其中的栈回溯 (_Unwind_Backtrace) 会经
+// dl_iterate_phdr 获取动态链接器的锁，这不是 async-signal-safe 的：目标线程被打断时恰好持有该锁
+// (正在 dlopen、加载库或另一次栈回溯) 就会死锁。因此 sample_stacks 默认关闭，只在排查卡顿时临时开启。
+//
+// 每次抓栈分配递增的序号并记录目标线程，处理函数只为与当前序号和目标线程匹配的请求写入一次，
+// 超时后迟到的信号 (包括排队的实时信号) 不会覆盖之后的结果；上一次的处理函数仍在写入时跳过本次抓栈。
+
+use crate::alerting::{ self, AlertSeverity };
+use crate::config::StallSection;
+use crate::metrics;
+use ::metrics::{ counter, histogram };
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+use std::io;
+use std::sync::atomic::{ AtomicU64, AtomicUsize, Ordering };
+use std::sync::mpsc::{ self, RecvTimeoutError };
+use std::sync::{ Mutex, PoisonError };
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+use tokio::runtime::Handle;
+use tracing::warn;
+
+// 卡顿期间等待剩余探测任务的轮询间隔
+const STALL_POLL: Duration = Duration::from_millis(50);
+// 等待目标线程在信号处理函数中完成抓栈的时间
+const SAMPLE_TIMEOUT: Duration = Duration::from_millis(100);
+// 单个调用栈最多记录的帧数
+const MAX_FRAMES: usize = 128;
+// 卡顿告警的去重键
+const STALL_ALERT: &str = "runtime-stall";
+
+/// 卡顿检测状态
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StallStatus {
+    /// 是否正处于卡顿中 (有探测任务超过阈值仍未执行)
+    pub stalled: bool,
+    /// 最近一轮探测的最大调度延迟，单位毫秒
+    pub last_max_delay_ms: f64,
+    /// 累计卡顿次数
+    pub stalls: u64,
+    /// 最近一次卡顿的时间 (Unix 秒)
+    pub last_stall: Option<u64>,
+}
+
+static STATUS: Mutex<StallStatus> = Mutex::new(StallStatus {
+    stalled: false,
+    last_max_delay_ms: 0.0,
+    stalls: 0,
+    last_stall: None,
+});
+
+// 运行过探测任务的工作线程，pthread_t -> 线程名
+static WORKERS: Mutex<BTreeMap<libc::pthread_t, String>> = Mutex::new(BTreeMap::new());
+
+// 信号处理函数写入的调用栈，同一时间只有检测线程发起一次抓栈
+static FRAMES: [AtomicUsize; MAX_FRAMES] = [const { AtomicUsize::new(0) }; MAX_FRAMES];
+static FRAME_COUNT: AtomicUsize = AtomicUsize::new(0);
+// 当前抓栈请求的序号和目标线程 (Linux 上 pthread_t 为 u64)
+static REQUEST_SEQ: AtomicU64 = AtomicU64::new(0);
+static REQUEST_TARGET: AtomicU64 = AtomicU64::new(0);
+// 已被处理函数认领的最大序号，每个序号只认领一次
+static CLAIMED_SEQ: AtomicU64 = AtomicU64::new(0);
+// 已写完调用栈的序号
+static SAMPLED_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 当前的卡顿检测状态
+pub fn status() -> StallStatus {
+    STATUS.lock().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+/// 启动卡顿检测线程，需在 tokio 运行时中调用
+pub fn spawn_stall_detector(config: StallSection) -> io::Result<()> {
+    if !config.enable {
+        return Ok(());
+    }
+    if config.sample_stacks {
+        install_sample_handler()?;
+    }
+    let handle = Handle::current();
+    std::thread::Builder
+        ::new()
+        .name("bitcomm-stall".to_string())
+        .spawn(move || detector_loop(handle, config))?;
+    Ok(())
+}
+
+fn detector_loop(handle: Handle, config: StallSection) {
+    let interval = Duration::from_millis(config.interval_ms);
+    let threshold = Duration::from_millis(config.threshold_ms);
+    loop {
+        std::thread::sleep(interval);
+        let workers = handle.metrics().num_workers();
+        let (tx, rx) = mpsc::channel();
+        let sent = Instant::now();
+        for _ in 0..workers {
+            let tx = tx.clone();
+            handle.spawn(async move {
+                register_worker();
+                let _ = tx.send(sent.elapsed());
+            });
+        }
+        drop(tx);
+
+        let deadline = sent + threshold;
+        let mut max_delay = Duration::ZERO;
+        let mut received = 0;
+        let mut stalled = false;
+        // 等齐本轮所有探测任务后才开始下一轮，卡顿期间不会堆积探测任务
+        while received < workers {
+            let timeout = if stalled { STALL_POLL } else { deadline.saturating_duration_since(Instant::now()) };
+            match rx.recv_timeout(timeout) {
+                Ok(delay) => {
+                    received += 1;
+                    max_delay = max_delay.max(delay);
+                    histogram!(metrics::RUNTIME_SCHEDULING_DELAY).record(delay.as_secs_f64());
+                }
+                Err(RecvTimeoutError::Timeout) if !stalled => {
+                    stalled = true;
+                    report_stall(threshold, workers - received, config.sample_stacks);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                // 运行时已关闭
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        let mut status = STATUS.lock().unwrap_or_else(PoisonError::into_inner);
+        status.stalled = false;
+        status.last_max_delay_ms = max_delay.as_secs_f64() * 1000.0;
+        if stalled {
+            warn!(delay_ms = status.last_max_delay_ms, "tokio runtime recovered from stall");
+            alerting::resolve(STALL_ALERT, format!("max scheduling delay {:.1}ms", status.last_max_delay_ms));
+        }
+    }
+}
+
+fn register_worker() {
+    let thread = std::thread::current();
+    let name = thread.name().unwrap_or("unnamed").to_string();
+    // SAFETY: pthread_self 总是成功
+    let id = unsafe { libc::pthread_self() };
+    WORKERS.lock().unwrap_or_else(PoisonError::into_inner).entry(id).or_insert(name);
+}
+
+// 卡顿仍在进行时抓取调用栈，此时占住工作线程的任务还在栈上
+fn report_stall(threshold: Duration, pending: usize, sample_stacks: bool) {
+    counter!(metrics::RUNTIME_STALLS).increment(1);
+    {
+        let mut status = STATUS.lock().unwrap_or_else(PoisonError::into_inner);
+        status.stalled = true;
+        status.stalls += 1;
+        status.last_stall = Some(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs());
+    }
+
+    let mut stacks = String::new();
+    let workers: Vec<(libc::pthread_t, String)> = if sample_stacks {
+        WORKERS.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(id, name)| (*id, name.clone()))
+            .collect()
+    } else {
+        stacks.push_str("\nstack sampling disabled ([stall] sample_stacks = false)");
+        Vec::new()
+    };
+    for (id, name) in workers {
+        let Some(frames) = sample_stack(id) else {
+            continue;
+        };
+        // 空闲的工作线程停在 park 中，与卡顿无关
+        if frames.iter().any(|frame| frame.contains("tokio::runtime") && frame.contains("park")) {
+            continue;
+        }
+        stacks.push_str(&format!("\nworker {} ({:#x}):\n", name, id));
+        for frame in frames {
+            stacks.push_str("    ");
+            stacks.push_str(&frame);
+            stacks.push('\n');
+        }
+    }
+    if stacks.is_empty() {
+        stacks.push_str("\nno busy worker stack captured");
+    }
+    warn!(
+        threshold_ms = threshold.as_millis() as u64,
+        pending_probes = pending,
+        "tokio runtime stalled: probe tasks not scheduled within threshold{}",
+        stacks
+    );
+    alerting::fire(
+        STALL_ALERT,
+        AlertSeverity::Warning,
+        None,
+        "tokio runtime stalled",
+        format!("{} probe tasks not scheduled within {}ms{}", pending, threshold.as_millis(), stacks)
+    );
+}
+
+fn sample_signal() -> libc::c_int {
+    libc::SIGRTMIN() + 1
+}
+
+fn install_sample_handler() -> io::Result<()> {
+    // SAFETY: 以零值初始化 sigaction 后填入处理函数，sa_mask 由 sigemptyset 初始化
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_sample_signal as *const () as usize;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(sample_signal(), &action, std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// 在目标线程上执行：只遍历栈帧记录指令地址，不分配内存，符号解析留给检测线程。
+// 栈回溯本身可能获取动态链接器的锁，见文件开头的说明
+extern "C" fn on_sample_signal(_signal: libc::c_int) {
+    let seq = REQUEST_SEQ.load(Ordering::Acquire);
+    // SAFETY: pthread_self 总是成功
+    let current = unsafe { libc::pthread_self() };
+    // 不是发给本线程的当前请求 (迟到的信号)，或该序号已被认领
+    if current != REQUEST_TARGET.load(Ordering::Acquire) || CLAIMED_SEQ.fetch_max(seq, Ordering::AcqRel) >= seq {
+        return;
+    }
+
+    let mut count = 0;
+    // SAFETY: 每个序号只有一个处理函数认领，检测线程在上一次认领写完之前不会发起新的请求
+    unsafe {
+        backtrace::trace_unsynchronized(|frame| {
+            FRAMES[count].store(frame.ip() as usize, Ordering::Relaxed);
+            count += 1;
+            count < MAX_FRAMES
+        });
+    }
+    FRAME_COUNT.store(count, Ordering::Relaxed);
+    SAMPLED_SEQ.store(seq, Ordering::Release);
+}
+
+// 向指定线程发送信号抓取调用栈并解析符号
+fn sample_stack(thread: libc::pthread_t) -> Option<Vec<String>> {
+    // 上一次超时的请求已被认领但还没写完，FRAMES 仍在被写入
+    if CLAIMED_SEQ.load(Ordering::Acquire) != SAMPLED_SEQ.load(Ordering::Acquire) {
+        return None;
+    }
+    let seq = REQUEST_SEQ.load(Ordering::Relaxed) + 1;
+    REQUEST_TARGET.store(thread, Ordering::Release);
+    REQUEST_SEQ.store(seq, Ordering::Release);
+    // SAFETY: thread 来自运行过探测任务的工作线程；线程已退出时 pthread_kill 返回错误
+    if unsafe { libc::pthread_kill(thread, sample_signal()) } != 0 {
+        return None;
+    }
+    let start = Instant::now();
+    while SAMPLED_SEQ.load(Ordering::Acquire) != seq {
+        if start.elapsed() > SAMPLE_TIMEOUT {
+            return None;
+        }
+        std::thread::yield_now();
+    }
+
+    let count = FRAME_COUNT.load(Ordering::Relaxed);
+    let mut frames = Vec::with_capacity(count);
+    for slot in FRAMES.iter().take(count) {
+        let ip = slot.load(Ordering::Relaxed) as *mut c_void;
+        let mut resolved = false;
+        backtrace::resolve(ip, |symbol| {
+            resolved = true;
+            let name = symbol.name().map(|name| format!("{:#}", name)).unwrap_or_else(|| format!("{:?}", ip));
+            match (symbol.filename(), symbol.lineno()) {
+                (Some(file), Some(line)) => frames.push(format!("{} at {}:{}", name, file.display(), line)),
+                _ => frames.push(name),
+            }
+        });
+        if !resolved {
+            frames.push(format!("{:?}", ip));
+        }
+    }
+    // 去掉信号处理函数自身及其后的信号跳板帧
+    let skip = frames
+        .iter()
+        .position(|frame| frame.contains("on_sample_signal"))
+        .map(|index| index + 2)
+        .unwrap_or(0);
+    Some(frames.split_off(skip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    #[inline(never)]
+    fn spin_until(spinning: &AtomicBool, stop: &AtomicBool) {
+        spinning.store(true, Ordering::Release);
+        while !stop.load(Ordering::Relaxed) {
+            std::hint::spin_loop();
+        }
+    }
+
+    // 抓栈的全局状态在测试之间共享，全部放在一个测试中顺序执行
+    #[test]
+    fn samples_are_matched_by_sequence_and_target() {
+        install_sample_handler().unwrap();
+        let spinning = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+        let spinner = {
+            let (spinning, stop) = (spinning.clone(), stop.clone());
+            std::thread::spawn(move || {
+                // SAFETY: pthread_self 总是成功
+                tx.send(unsafe { libc::pthread_self() }).unwrap();
+                spin_until(&spinning, &stop);
+            })
+        };
+        let target = rx.recv().unwrap();
+        // 等目标线程进入 spin_until 后再抓栈
+        while !spinning.load(Ordering::Acquire) {
+            std::thread::yield_now();
+        }
+
+        let frames = sample_stack(target).expect("stack sampled");
+        assert!(frames.iter().any(|frame| frame.contains("spin_until")), "{:#?}", frames);
+        let seq = SAMPLED_SEQ.load(Ordering::Acquire);
+        assert_eq!(seq, REQUEST_SEQ.load(Ordering::Acquire));
+
+        // 迟到的信号：在非目标线程上执行，或序号已被认领，都不会写入
+        on_sample_signal(sample_signal());
+        assert_eq!(SAMPLED_SEQ.load(Ordering::Acquire), seq);
+        // SAFETY: pthread_self 总是成功
+        REQUEST_TARGET.store(unsafe { libc::pthread_self() }, Ordering::Release);
+        on_sample_signal(sample_signal());
+        assert_eq!(SAMPLED_SEQ.load(Ordering::Acquire), seq);
+
+        // 认领后尚未写完时跳过新的请求
+        CLAIMED_SEQ.store(seq + 1, Ordering::Release);
+        assert!(sample_stack(target).is_none());
+        SAMPLED_SEQ.store(seq + 1, Ordering::Release);
+        REQUEST_SEQ.store(seq + 1, Ordering::Release);
+        assert!(sample_stack(target).is_some());
+
+        stop.store(true, Ordering::Relaxed);
+        spinner.join().unwrap();
+    }
+}
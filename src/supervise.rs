@@ -0,0 +1,184 @@
+// 外部监管模式 (`bitcomm supervise`)
+//
+// 与 supervisor.rs (进程内各服务的状态与重启) 不同，这里由一个很小的父进程以子进程方式运行 `bitcomm start`：
+// - 子进程异常退出 (非零退出码或被信号终止) 时按指数退避重启，子进程持续运行 stable_secs 秒后退避时间复位；
+// - 父进程收到的信号转发给子进程，SIGINT/SIGTERM 转发后不再重启，子进程退出后父进程随之退出；
+// - 子进程被信号终止时 panic hook 不会执行，由父进程补写崩溃报告，并按 [crash] max_reports 清理旧报告；
+// - PID 文件记录父进程，`bitcomm stop` 照常使用；子进程通过 BITCOMM_SUPERVISED 得知自己受监管，不再写 PID 文件。
+
+use crate::config::BitcommConfig;
+use crate::{ crashreport, supervisor };
+use std::error::Error;
+use std::os::unix::process::{ CommandExt, ExitStatusExt };
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::{ Duration, Instant };
+use tokio::process::{ Child, Command };
+use tokio::signal::unix::{ signal, SignalKind };
+use tokio::sync::mpsc;
+use tracing::{ error, info, warn };
+
+/// 受监管的子进程带有该环境变量
+pub const SUPERVISED_ENV: &str = "BITCOMM_SUPERVISED";
+
+// 转发给子进程的信号
+const FORWARDED: [libc::c_int; 6] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGUSR1, libc::SIGUSR2, libc::SIGQUIT];
+
+/// 当前进程是否由 `bitcomm supervise` 启动
+pub fn is_supervised() -> bool {
+    std::env::var_os(SUPERVISED_ENV).is_some()
+}
+
+/// 运行监管循环，直到子进程正常退出或收到 SIGINT/SIGTERM
+pub async fn run(config_path: &Path, no_banner: bool, config: &BitcommConfig) -> Result<(), Box<dyn Error>> {
+    let mut signals = forward_signals()?;
+    btcmtools::pid::save_pid();
+    let result = supervise(config_path, no_banner, config, &mut signals).await;
+    btcmtools::pid::dele_pid();
+    result
+}
+
+async fn supervise(
+    config_path: &Path,
+    no_banner: bool,
+    config: &BitcommConfig,
+    signals: &mut mpsc::UnboundedReceiver<libc::c_int>
+) -> Result<(), Box<dyn Error>> {
+    let initial = Duration::from_millis(config.supervise.backoff_initial_ms);
+    let max = Duration::from_secs(config.supervise.backoff_max_secs).max(initial);
+    let stable = Duration::from_secs(config.supervise.stable_secs);
+    let mut backoff = initial;
+    let mut restarts: u64 = 0;
+
+    loop {
+        let started = Instant::now();
+        let mut child = spawn_child(config_path, no_banner)?;
+        let pid = child.id().unwrap_or_default();
+        info!(pid, restarts, "server process started");
+
+        let mut stopping = false;
+        let status = loop {
+            tokio::select! {
+                status = child.wait() => break status?,
+                Some(signum) = signals.recv() => {
+                    stopping |= is_stop_signal(signum);
+                    // SAFETY: 只向自己启动、尚未回收的子进程发送信号
+                    if unsafe { libc::kill(pid as libc::pid_t, signum) } != 0 {
+                        warn!(pid, signal = signal_name(signum), "failed to forward signal: {}", std::io::Error::last_os_error());
+                    }
+                }
+            }
+        };
+
+        if stopping || status.success() {
+            info!(pid, %status, "server process exited");
+            return Ok(());
+        }
+        error!(pid, %status, uptime_secs = started.elapsed().as_secs(), "server process exited abnormally");
+        record_exit(config, pid, status);
+
+        if started.elapsed() >= stable {
+            backoff = initial;
+        }
+        warn!(pid, delay_ms = backoff.as_millis() as u64, "restarting server process");
+        let delay = tokio::time::sleep(backoff);
+        tokio::pin!(delay);
+        loop {
+            tokio::select! {
+                _ = &mut delay => break,
+                Some(signum) = signals.recv() => {
+                    if is_stop_signal(signum) {
+                        info!(signal = signal_name(signum), "stopping during restart backoff");
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        backoff = (backoff * 2).min(max);
+        restarts += 1;
+    }
+}
+
+// 以相同的配置启动 `bitcomm start`
+fn spawn_child(config_path: &Path, no_banner: bool) -> Result<Child, Box<dyn Error>> {
+    let program = std::env::current_exe()?;
+    let mut command = std::process::Command::new(&program);
+    command.arg("-c").arg(config_path);
+    if no_banner {
+        command.arg("--no-banner");
+    }
+    command.arg("start").env(SUPERVISED_ENV, "1");
+    // 单独的进程组：终端的 Ctrl-C 只发给父进程，由父进程转发，子进程不会收到两次
+    command.process_group(0);
+    // SAFETY: pre_exec 中只调用 async-signal-safe 的 prctl；父进程被 SIGKILL 时子进程随之收到 SIGTERM
+    unsafe {
+        command.pre_exec(|| {
+            if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Command::from(command)
+        .spawn()
+        .map_err(|e| format!("failed to start {}: {}", program.display(), e).into())
+}
+
+// 子进程被信号终止时补写崩溃报告，然后清理旧报告
+fn record_exit(config: &BitcommConfig, pid: u32, status: ExitStatus) {
+    if let Some(signum) = status.signal() {
+        let mut message = format!("terminated by {}", signal_name(signum));
+        if status.core_dumped() {
+            message.push_str(" (core dumped)");
+        }
+        match crashreport::write_exit_report(config, pid, &message) {
+            Ok(Some(path)) => warn!(pid, path = %path.display(), "crash report written for server process"),
+            Ok(None) => {}
+            Err(e) => error!(pid, "failed to write crash report: {}", e),
+        }
+    }
+    let removed = crashreport::rotate(&config.crash);
+    if removed > 0 {
+        info!(removed, max_reports = config.crash.max_reports, "old crash reports removed");
+    }
+}
+
+// 父进程收到的信号通过通道交给监管循环
+fn forward_signals() -> Result<mpsc::UnboundedReceiver<libc::c_int>, Box<dyn Error>> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    for signum in FORWARDED {
+        let mut stream = signal(SignalKind::from_raw(signum))?;
+        let sender = sender.clone();
+        supervisor::spawn_named("supervise/signal", async move {
+            while stream.recv().await.is_some() {
+                if sender.send(signum).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    Ok(receiver)
+}
+
+fn is_stop_signal(signum: libc::c_int) -> bool {
+    signum == libc::SIGINT || signum == libc::SIGTERM
+}
+
+fn signal_name(signum: libc::c_int) -> String {
+    let name = match signum {
+        libc::SIGINT => "SIGINT",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGILL => "SIGILL",
+        libc::SIGFPE => "SIGFPE",
+        _ => return format!("signal {}", signum),
+    };
+    name.to_string()
+}
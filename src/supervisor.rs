@@ -0,0 +1,360 @@
+// 服务运行状态登记
+//
+// bin/bitcomm.rs 中每个服务任务持有一个 ServiceGuard，
+// 健康检查、指标和控制 socket 都从这里读取各服务的状态与心跳。
+// 心跳由 ServiceGuard 启动的独立任务发布，不依赖服务主体是否被轮询；服务是否在推进由进度计数判断：
+// 服务用 start_work 登记进行中的工作 (请求、消息)，完成时计一次进度，看门狗据此发现活着但没有进展的服务；
+// 主体在依赖库中的服务 (imserver / mqserver) 改由依赖库增减 bitcomm_service_in_flight 并累加进度计数。
+// 用 run_restartable 包裹的服务可以通过 restart_service 原地重启。
+
+use crate::alerting::{ self, AlertSeverity };
+use crate::journal::{ self, EventKind };
+use crate::metrics;
+use ::metrics::counter;
+use axum::extract::{ Request, State };
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::{ Deserialize, Serialize };
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{ info, warn };
+
+/// 服务心跳上报间隔
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+// 接受连接出错 (例如文件描述符耗尽) 后等待多久再继续接受
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// 服务状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceState {
+    /// 正在运行
+    Running,
+    /// 已正常退出
+    Stopped,
+    /// 异常退出 (panic)
+    Failed,
+}
+
+/// 单个服务的状态快照
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    pub service: String,
+    pub state: ServiceState,
+    /// 进入当前状态的时间 (Unix 秒)
+    pub since: u64,
+    /// 距上次心跳的秒数，从未上报心跳时为 None
+    pub heartbeat_age_secs: Option<f64>,
+    /// 进行中的工作数
+    pub in_flight: usize,
+}
+
+struct ServiceEntry {
+    state: ServiceState,
+    since: SystemTime,
+    last_heartbeat: Option<Instant>,
+    in_flight: usize,
+}
+
+static SERVICES: Mutex<BTreeMap<&'static str, ServiceEntry>> = Mutex::new(BTreeMap::new());
+
+// 可重启服务的重启通知
+static RESTARTS: Mutex<BTreeMap<&'static str, Arc<Notify>>> = Mutex::new(BTreeMap::new());
+
+// 是否正在排空
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+// 是否因资源告警拒绝新连接
+static SHEDDING: AtomicBool = AtomicBool::new(false);
+
+tokio::task_local! {
+    // 当前任务所属的服务，供 panic hook 判断是哪个服务崩溃
+    static CURRENT_SERVICE: &'static str;
+}
+
+/// 以指定任务名启动 tokio 任务，任务名按 `服务/用途[/对象]` 命名，例如 `imserver/conn/<peer>`；
+/// 以 console feature 构建时任务名会显示在 tokio-console 中，否则等同于 tokio::spawn
+#[track_caller]
+pub fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+    where F: Future + Send + 'static, F::Output: Send + 'static
+{
+    #[cfg(all(feature = "console", tokio_unstable))]
+    {
+        tokio::task::Builder::new().name(name).spawn(future).expect("failed to spawn task")
+    }
+    #[cfg(not(all(feature = "console", tokio_unstable)))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
+
+/// 当前任务所属的服务，不在 run_restartable 的任务中时为 None
+pub fn current_service() -> Option<&'static str> {
+    CURRENT_SERVICE.try_with(|service| *service).ok()
+}
+
+fn set_state(service: &'static str, state: ServiceState) {
+    let mut services = SERVICES.lock().unwrap();
+    let entry = services
+        .entry(service)
+        .or_insert(ServiceEntry { state, since: SystemTime::now(), last_heartbeat: None, in_flight: 0 });
+    entry.state = state;
+    entry.since = SystemTime::now();
+}
+
+/// 上报服务心跳
+pub fn heartbeat(service: &'static str) {
+    if let Some(entry) = SERVICES.lock().unwrap().get_mut(service) {
+        entry.last_heartbeat = Some(Instant::now());
+    }
+}
+
+/// 上报服务完成了 amount 个单位的工作 (处理的消息、请求、连接等)
+pub fn record_progress(service: &'static str, amount: u64) {
+    counter!(metrics::SERVICE_PROGRESS, "service" => service).increment(amount);
+}
+
+/// 服务开始处理一个工作单位，返回的 guard 析构时结束并计一次进度；
+/// 看门狗只在服务有进行中的工作时检查进度，空闲的服务不会因为没有进度被判定为卡住
+pub fn start_work(service: &'static str) -> WorkGuard {
+    if let Some(entry) = SERVICES.lock().unwrap().get_mut(service) {
+        entry.in_flight += 1;
+    }
+    WorkGuard { service }
+}
+
+/// 一个进行中的工作单位，见 start_work
+#[must_use = "the work ends when the guard is dropped"]
+pub struct WorkGuard {
+    service: &'static str,
+}
+
+impl Drop for WorkGuard {
+    fn drop(&mut self) {
+        if let Some(entry) = SERVICES.lock().unwrap().get_mut(self.service) {
+            entry.in_flight = entry.in_flight.saturating_sub(1);
+        }
+        record_progress(self.service, 1);
+    }
+}
+
+/// axum 中间件，把每个请求作为服务的一个工作单位，服务名作为 state 传入：
+/// `middleware::from_fn_with_state("opsserver", supervisor::track_work)`
+pub async fn track_work(State(service): State<&'static str>, req: Request, next: Next) -> Response {
+    let _work = start_work(service);
+    next.run(req).await
+}
+
+// 周期性上报心跳，由 ServiceGuard 在独立的任务中运行
+async fn heartbeat_loop(service: &'static str, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        heartbeat(service);
+    }
+}
+
+/// 查询单个服务的状态
+pub fn service_status(service: &str) -> Option<ServiceStatus> {
+    SERVICES.lock().unwrap().get_key_value(service).map(|(name, entry)| status_of(name, entry))
+}
+
+/// 所有已登记服务的状态
+pub fn services() -> Vec<ServiceStatus> {
+    SERVICES.lock()
+        .unwrap()
+        .iter()
+        .map(|(name, entry)| status_of(name, entry))
+        .collect()
+}
+
+fn status_of(service: &'static str, entry: &ServiceEntry) -> ServiceStatus {
+    ServiceStatus {
+        service: service.to_string(),
+        state: entry.state,
+        since: entry.since.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        heartbeat_age_secs: entry.last_heartbeat.map(|beat| beat.elapsed().as_secs_f64()),
+        in_flight: entry.in_flight,
+    }
+}
+
+/// 服务运行期间持有，登记为 running 并把 bitcomm_service_up 置为 1，在独立的任务中每 HEARTBEAT_INTERVAL 上报一次心跳；
+/// 析构时停止心跳并登记为 stopped，因 panic 析构时登记为 failed，状态变化同时记入事件日志
+pub struct ServiceGuard {
+    service: &'static str,
+    heartbeat: Option<JoinHandle<()>>,
+}
+
+impl ServiceGuard {
+    pub fn new(service: &'static str) -> ServiceGuard {
+        set_state(service, ServiceState::Running);
+        metrics::service_up(service, true);
+        journal::record(EventKind::Started, Some(service), "running");
+        alerting::resolve(failed_key(service), "service running again");
+        // 不在 tokio 运行时中 (例如同步的测试) 时不上报心跳
+        let heartbeat = tokio::runtime::Handle::try_current()
+            .ok()
+            .map(|_| spawn_named(&format!("{}/heartbeat", service), heartbeat_loop(service, HEARTBEAT_INTERVAL)));
+        ServiceGuard { service, heartbeat }
+    }
+}
+
+impl Drop for ServiceGuard {
+    fn drop(&mut self) {
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.abort();
+        }
+        let (state, kind) = if std::thread::panicking() {
+            (ServiceState::Failed, EventKind::Failed)
+        } else {
+            (ServiceState::Stopped, EventKind::Stopped)
+        };
+        set_state(self.service, state);
+        metrics::service_up(self.service, false);
+        journal::record(kind, Some(self.service), format!("{:?}", state).to_lowercase());
+        if state == ServiceState::Failed {
+            alerting::fire(
+                failed_key(self.service),
+                AlertSeverity::Critical,
+                Some(self.service),
+                format!("service {} failed", self.service),
+                "the service panicked, see the crash report"
+            );
+        }
+    }
+}
+
+fn failed_key(service: &str) -> String {
+    format!("service-failed:{}", service)
+}
+
+/// 反复运行 run 返回的服务主体：收到 restart_service 请求时丢弃正在运行的主体并重新调用 run，
+/// 服务主体自行返回时结束
+pub async fn run_restartable<F, Fut>(service: &'static str, mut run: F)
+    where F: FnMut() -> Fut, Fut: Future<Output = ()>
+{
+    let restart = Arc::clone(RESTARTS.lock().unwrap().entry(service).or_default());
+    CURRENT_SERVICE.scope(service, async move {
+        loop {
+            tokio::select! {
+                _ = run() => break,
+                _ = restart.notified() => {
+                    metrics::record_service_restart(service);
+                    journal::record(EventKind::Restarted, Some(service), "restart requested");
+                    info!(service, "service restarting");
+                }
+            }
+        }
+    }).await
+}
+
+/// 请求重启一个正在运行的服务
+pub fn restart_service(service: &str) -> Result<(), String> {
+    let restart = RESTARTS.lock().unwrap().get(service).cloned();
+    let Some(restart) = restart else {
+        return Err(format!("service {} cannot be restarted", service));
+    };
+    match service_status(service) {
+        Some(status) if status.state == ServiceState::Running => {
+            restart.notify_one();
+            Ok(())
+        }
+        _ => Err(format!("service {} is not running", service)),
+    }
+}
+
+/// 开始排空：就绪检查随即失败，负载均衡不再分配新的流量，已有连接不受影响。
+/// 返回之前是否已经在排空
+pub fn begin_drain() -> bool {
+    let already = DRAINING.swap(true, Ordering::SeqCst);
+    if !already {
+        journal::record(EventKind::DrainBegan, None, "readiness will report not ready");
+        warn!("drain requested, readiness will report not ready");
+    }
+    already
+}
+
+/// 是否正在排空
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::SeqCst)
+}
+
+/// 开始或停止拒绝新连接：就绪检查随之失败，接入服务应在 is_shedding 为 true 时拒绝新连接，
+/// 已有连接不受影响。返回之前的状态
+pub fn set_shedding(shedding: bool) -> bool {
+    let previous = SHEDDING.swap(shedding, Ordering::SeqCst);
+    if previous != shedding {
+        if shedding {
+            warn!("shedding new connections");
+        } else {
+            info!("no longer shedding new connections");
+        }
+    }
+    previous
+}
+
+/// 是否正在拒绝新连接
+pub fn is_shedding() -> bool {
+    SHEDDING.load(Ordering::SeqCst)
+}
+
+/// IM 接入路径在接受新连接时调用：正在排空或拒绝新连接时计入
+/// bitcomm_im_connections_total{result="shed"} 并返回 false，调用方应立即关闭该连接。
+/// imtcp 和 imws 在这里检查；btcmnetwork 的 QUIC 接入不依赖 bitcomm，无法调用，新 QUIC 连接不受降载限制
+pub fn admit_connection(transport: &'static str) -> bool {
+    if is_draining() || is_shedding() {
+        counter!(metrics::IM_CONNECTIONS_TOTAL, "transport" => transport, "result" => "shed").increment(1);
+        return false;
+    }
+    true
+}
+
+/// 监听循环在 accept 出错时调用，返回后继续接受连接：单个连接的错误 (对端在握手前断开) 直接忽略，
+/// 其他错误 (EMFILE 等) 记录日志并等待一秒，与 axum::serve 相同，暂时性错误不会让服务退出
+pub async fn accept_error(service: &str, error: io::Error) {
+    if matches!(error.kind(), io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset) {
+        return;
+    }
+    warn!(service, "failed to accept connection, retrying in {:?}: {}", ACCEPT_ERROR_BACKOFF, error);
+    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::metrics::tests::local_recorder;
+
+    /// 修改或依赖拒绝新连接状态的测试持有该锁，避免并行的测试互相影响
+    pub(crate) static ADMISSION: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    #[tokio::test]
+    async fn connections_are_refused_while_shedding() {
+        let _admission = ADMISSION.lock().await;
+        let (recorder, _) = local_recorder();
+        let _recorder = ::metrics::set_default_local_recorder(&recorder);
+
+        assert!(admit_connection("test"));
+        assert!(!set_shedding(true));
+        assert!(!admit_connection("test"));
+        assert!(set_shedding(false));
+        assert!(admit_connection("test"));
+    }
+
+    #[tokio::test]
+    async fn accept_errors_back_off_unless_the_connection_was_aborted() {
+        let start = Instant::now();
+        accept_error("test", io::ErrorKind::ConnectionAborted.into()).await;
+        assert!(start.elapsed() < ACCEPT_ERROR_BACKOFF);
+        // EMFILE
+        accept_error("test", io::Error::from_raw_os_error(24)).await;
+        assert!(start.elapsed() >= ACCEPT_ERROR_BACKOFF);
+    }
+}
@@ -0,0 +1,425 @@
+// systemd 集成
+//
+// sd_notify：在 systemd (Type=notify) 下运行时向 NOTIFY_SOCKET 发送
+// - READY=1：启用的服务全部进入 running 后发送一次，此前 STATUS= 显示仍在等待的服务；
+// - STATUS=：每 [systemd] status_interval_secs 秒更新一行运行摘要，`systemctl status` 中可见；
+// - WATCHDOG=1：单元设置了 WatchdogSec= 时按其一半的间隔发送，只在存活检查通过且看门狗没有判定卡住的服务时发送，
+//   停止发送后由 systemd 重启进程；
+// - STOPPING=1：收到 SIGINT/SIGTERM 时发送。
+// NOTIFY_SOCKET 可以是文件路径或以 @ 开头的抽象地址，本地调试时用任意 unix datagram socket 接收即可。
+// `bitcomm supervise` 下通知由子进程发出，单元需要设置 NotifyAccess=all。
+//
+// socket 激活：启动时读取 LISTEN_PID/LISTEN_FDS/LISTEN_FDNAMES 登记 systemd 传入的 socket，
+// 服务按 FileDescriptorName= (与服务名相同，例如 opsserver) 或监听地址取得 socket 的副本，服务重启时复用同一个 socket。
+// 传入的 socket 和环境变量都保持原样，看门狗重新执行进程后仍能取得。
+// 目前 ops、健康检查、控制 socket、IM 的 TCP+TLS 备用传输 (imtcpserver) 和 WebSocket 网关 (imwsserver，单独监听 [webserver.websocket] 的端口) 使用激活的 socket。
+// IM (UDP) 与 Web (TCP) 尚不支持 socket 激活：imserver::start_instant_message_server / webserver::star_webserver
+// 不接受外部 socket，由 btcmnetwork / btcmweb 自行绑定端口。imquic::endpoint 已用 activated_udp_socket("imserver", …)
+// 准备好 QUIC 端点，等 btcmnetwork 提供接受端点的入口后接入；Web 服务器需要 btcmweb 提供接受监听 socket 的入口。
+// 在此之前名为 imserver / webserver 的 socket 不会被使用，就绪时仍未被取用的 socket 会输出告警，单元中不应为它们配置 socket。
+
+use crate::config::BitcommConfig;
+use crate::health::{ self, HealthStatus };
+use crate::supervisor::{ self, ServiceState };
+use crate::{ stall, supervise, watchdog };
+use std::ffi::{ OsStr, OsString };
+use std::io;
+use std::net::{ SocketAddr, TcpListener, UdpSocket };
+use std::os::fd::{ AsRawFd, FromRawFd, OwnedFd, RawFd };
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{ self, UnixDatagram, UnixListener };
+use std::path::Path;
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
+use tracing::{ debug, info, warn };
+
+// sd_listen_fds 约定传入的第一个 fd
+const LISTEN_FDS_START: RawFd = 3;
+
+// 没有设置 FileDescriptorName= 时 systemd 使用的名称
+const UNNAMED: &str = "unknown";
+
+// 等待服务就绪时的轮询间隔
+const READY_POLL: Duration = Duration::from_millis(500);
+
+struct ActivatedSocket {
+    name: String,
+    fd: OwnedFd,
+    // 取用该 socket 的服务
+    used_by: Option<String>,
+}
+
+static ACTIVATED: Mutex<Vec<ActivatedSocket>> = Mutex::new(Vec::new());
+
+// 启用通知时 init 读取的 NOTIFY_SOCKET，为 None 时不发送通知
+static NOTIFY_SOCKET: Mutex<Option<OsString>> = Mutex::new(None);
+
+/// 登记 socket 激活传入的 socket；在 systemd 下时启动 READY/STATUS/WATCHDOG 通知任务。
+/// 需要在各服务启动之前调用
+pub fn init(config: &BitcommConfig) -> io::Result<()> {
+    let count = register_listen_fds()?;
+    if count > 0 {
+        let names: Vec<String> = ACTIVATED.lock().unwrap().iter().map(|socket| socket.name.clone()).collect();
+        info!(count, names = %names.join(","), "received sockets from systemd");
+    }
+
+    let Some(notify_socket) = std::env::var_os("NOTIFY_SOCKET").filter(|_| config.systemd.enable) else {
+        return Ok(());
+    };
+    *NOTIFY_SOCKET.lock().unwrap() = Some(notify_socket);
+    supervisor::spawn_named("systemd/notify", run_notifier(Arc::new(config.clone()), watchdog_interval()));
+    Ok(())
+}
+
+/// 向 NOTIFY_SOCKET 发送一条通知 (换行分隔的 KEY=VALUE)；没有 NOTIFY_SOCKET 时返回 Ok(false)
+pub fn notify(state: &str) -> io::Result<bool> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    notify_to(&path, state)?;
+    Ok(true)
+}
+
+// path 为文件路径或以 @ 开头的抽象地址
+fn notify_to(path: &OsStr, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => socket.send_to_addr(state.as_bytes(), &net::SocketAddr::from_abstract_name(name)?)?,
+        None => socket.send_to(state.as_bytes(), Path::new(path))?,
+    };
+    Ok(())
+}
+
+/// 通知 systemd 进程正在停止
+pub fn notify_stopping() {
+    send("STOPPING=1\nSTATUS=stopping");
+}
+
+// 启用通知时发送，失败只记录日志
+fn send(state: &str) {
+    let Some(path) = NOTIFY_SOCKET.lock().unwrap().clone() else {
+        return;
+    };
+    if let Err(e) = notify_to(&path, state) {
+        warn!("failed to notify systemd: {}", e);
+    }
+}
+
+/// 取得激活的 TCP 监听 socket：名称相同，或未命名且监听地址相同
+pub fn activated_tcp_listener(name: &str, addr: SocketAddr) -> Option<TcpListener> {
+    let fd = find_socket(name, libc::SOCK_STREAM, |fd| inet_local_addr(fd) == Some(addr))?;
+    let listener = TcpListener::from(fd);
+    listener.set_nonblocking(true).ok()?;
+    Some(listener)
+}
+
+/// 取得激活的 UDP socket：名称相同，或未命名且绑定地址相同
+pub fn activated_udp_socket(name: &str, addr: SocketAddr) -> Option<UdpSocket> {
+    let fd = find_socket(name, libc::SOCK_DGRAM, |fd| inet_local_addr(fd) == Some(addr))?;
+    let socket = UdpSocket::from(fd);
+    socket.set_nonblocking(true).ok()?;
+    Some(socket)
+}
+
+/// 取得激活的 unix 监听 socket：名称相同，或未命名且路径相同
+pub fn activated_unix_listener(name: &str, path: &Path) -> Option<UnixListener> {
+    let fd = find_socket(name, libc::SOCK_STREAM, |fd| {
+        let listener = UnixListener::from(fd);
+        listener.local_addr().ok().and_then(|addr| addr.as_pathname().map(|local| local == path)).unwrap_or(false)
+    })?;
+    let listener = UnixListener::from(fd);
+    listener.set_nonblocking(true).ok()?;
+    Some(listener)
+}
+
+/// 服务是否在使用激活的 socket；这类 socket 的文件由 systemd 管理，服务退出时不应删除
+pub fn uses_activated_socket(service: &str) -> bool {
+    ACTIVATED.lock().unwrap().iter().any(|socket| socket.used_by.as_deref() == Some(service))
+}
+
+// 按 sd_listen_fds 约定登记传入的 socket，返回个数。
+// `bitcomm supervise` 的子进程继承父进程收到的 socket，LISTEN_PID 是父进程
+fn register_listen_fds() -> io::Result<usize> {
+    let Some(pid) = env_number("LISTEN_PID") else {
+        return Ok(0);
+    };
+    if !is_own_pid(pid) {
+        return Ok(0);
+    }
+    let count = env_number("LISTEN_FDS").unwrap_or(0) as RawFd;
+    let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let names: Vec<&str> = names.split(':').collect();
+
+    let mut activated = ACTIVATED.lock().unwrap();
+    activated.clear();
+    for index in 0..count {
+        let raw = LISTEN_FDS_START + index;
+        if socket_type(raw).is_none() {
+            return Err(io::Error::other(format!("LISTEN_FDS={} but fd {} is not a socket", count, raw)));
+        }
+        // SAFETY: systemd 传入的 fd 归本进程所有，只在这里接管一次
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+        let name = names.get(index as usize).filter(|name| !name.is_empty()).unwrap_or(&UNNAMED);
+        activated.push(ActivatedSocket { name: name.to_string(), fd, used_by: None });
+    }
+    Ok(activated.len())
+}
+
+// 先按名称找，再按地址找未命名的 socket；返回 fd 的副本，登记的 fd 保留给服务重启时使用
+fn find_socket(name: &str, kind: libc::c_int, matches: impl Fn(OwnedFd) -> bool) -> Option<OwnedFd> {
+    let mut activated = ACTIVATED.lock().unwrap();
+    let by_name = activated.iter().position(|socket| socket.name == name && socket_type(socket.fd.as_raw_fd()) == Some(kind));
+    let index = by_name.or_else(|| {
+        activated.iter().position(|socket| {
+            socket.name == UNNAMED && socket_type(socket.fd.as_raw_fd()) == Some(kind) && socket.fd.try_clone().map(&matches).unwrap_or(false)
+        })
+    })?;
+    let socket = &mut activated[index];
+    match socket.fd.try_clone() {
+        Ok(fd) => {
+            if socket.used_by.is_none() {
+                info!(service = name, fd = socket.fd.as_raw_fd(), "using socket from systemd");
+            }
+            socket.used_by = Some(name.to_string());
+            Some(fd)
+        }
+        Err(e) => {
+            warn!(service = name, "failed to duplicate socket from systemd: {}", e);
+            None
+        }
+    }
+}
+
+// 不是 socket 时返回 None
+fn socket_type(fd: RawFd) -> Option<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: getsockopt 只写入传入的 int
+    let result = unsafe {
+        libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_TYPE, (&mut value as *mut libc::c_int).cast(), &mut len)
+    };
+    (result == 0).then_some(value)
+}
+
+// getsockname 对 TCP 和 UDP socket 相同，借用 UdpSocket 读取 IPv4/IPv6 本地地址
+fn inet_local_addr(fd: OwnedFd) -> Option<SocketAddr> {
+    UdpSocket::from(fd).local_addr().ok()
+}
+
+fn env_number(key: &str) -> Option<u32> {
+    std::env::var(key).ok().and_then(|value| value.trim().parse().ok())
+}
+
+fn is_own_pid(pid: u32) -> bool {
+    // SAFETY: getppid 总是成功
+    pid == std::process::id() || (supervise::is_supervised() && pid == (unsafe { libc::getppid() } as u32))
+}
+
+// WatchdogSec= 对应的喂狗间隔 (一半)，WATCHDOG_PID 指向其他进程时不喂狗
+fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.trim().parse().ok()?;
+    if let Some(pid) = env_number("WATCHDOG_PID") {
+        if !is_own_pid(pid) {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+// 按配置应当运行的服务
+fn expected_services(config: &BitcommConfig) -> Vec<&'static str> {
+    let mut services = vec!["mqserver", "imserver", "webserver", "wdserver"];
+    if config.imserver.tcp_fallback.enable {
+        services.push("imtcpserver");
+    }
+    if config.webserver.websocket.enable {
+        services.push("imwsserver");
+    }
+    if config.opsserver.enable {
+        services.push("opsserver");
+    }
+    if config.health.enable {
+        services.push("hcserver");
+    }
+    if config.control.enable {
+        services.push("ctlserver");
+    }
+    services
+}
+
+// watchdog 为喂狗间隔，单元没有设置 WatchdogSec= 时为 None
+async fn run_notifier(config: Arc<BitcommConfig>, watchdog: Option<Duration>) {
+    let expected = expected_services(&config);
+
+    // 等待所有服务进入 running
+    let mut waiting_status = String::new();
+    loop {
+        let waiting: Vec<&str> = expected
+            .iter()
+            .copied()
+            .filter(|service| supervisor::service_status(service).map(|status| status.state) != Some(ServiceState::Running))
+            .collect();
+        if waiting.is_empty() {
+            break;
+        }
+        let status = format!("starting, waiting for {}", waiting.join(", "));
+        if status != waiting_status {
+            send(&format!("STATUS={}", status));
+            waiting_status = status;
+        }
+        tokio::time::sleep(READY_POLL).await;
+    }
+    send(&format!("READY=1\nSTATUS={}", summary(&expected)));
+    info!("notified systemd: ready");
+    warn_unused_sockets();
+
+    if let Some(interval) = watchdog {
+        info!(interval_ms = interval.as_millis() as u64, "systemd watchdog enabled");
+        supervisor::spawn_named("systemd/watchdog", run_watchdog_pings(config.clone(), interval));
+    }
+
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.systemd.status_interval_secs));
+    loop {
+        ticker.tick().await;
+        send(&format!("STATUS={}", summary(&expected)));
+    }
+}
+
+// 只在进程存活且没有卡住的服务时喂狗，否则由 systemd 在 WatchdogSec= 后重启进程
+async fn run_watchdog_pings(config: Arc<BitcommConfig>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    let mut withheld = false;
+    loop {
+        ticker.tick().await;
+        let live = health::liveness(&config.health);
+        let stuck = watchdog::stuck_services();
+        if live.status == HealthStatus::Ok && stuck == 0 {
+            if withheld {
+                info!("resuming systemd watchdog pings");
+                withheld = false;
+            }
+            send("WATCHDOG=1");
+        } else if !withheld {
+            let failing: Vec<&str> = live.components
+                .iter()
+                .filter(|(_, component)| component.status != HealthStatus::Ok)
+                .map(|(name, _)| name.as_str())
+                .collect();
+            warn!(stuck, failing = %failing.join(","), "withholding systemd watchdog pings");
+            withheld = true;
+        } else {
+            debug!(stuck, "systemd watchdog ping withheld");
+        }
+    }
+}
+
+// STATUS= 摘要，例如 "7/7 services running, shedding"
+fn summary(expected: &[&str]) -> String {
+    let running = expected
+        .iter()
+        .filter(|service| supervisor::service_status(service).map(|status| status.state) == Some(ServiceState::Running))
+        .count();
+    let mut parts = vec![format!("{}/{} services running", running, expected.len())];
+    let stuck = watchdog::stuck_services();
+    if stuck > 0 {
+        parts.push(format!("{} stuck", stuck));
+    }
+    if stall::status().stalled {
+        parts.push("runtime stalled".to_string());
+    }
+    if supervisor::is_shedding() {
+        parts.push("shedding".to_string());
+    }
+    if supervisor::is_draining() {
+        parts.push("draining".to_string());
+    }
+    parts.join(", ")
+}
+
+fn warn_unused_sockets() {
+    for socket in ACTIVATED.lock().unwrap().iter().filter(|socket| socket.used_by.is_none()) {
+        warn!(
+            name = %socket.name,
+            fd = socket.fd.as_raw_fd(),
+            "socket from systemd is not used by any service (imserver/webserver bind their own sockets)"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supervisor::ServiceGuard;
+
+    // 读取一条通知，超时返回 None
+    fn receive(socket: &UnixDatagram) -> Option<String> {
+        let mut buffer = [0u8; 1024];
+        let len = socket.recv(&mut buffer).ok()?;
+        Some(String::from_utf8_lossy(&buffer[..len]).into_owned())
+    }
+
+    // 读取通知直到 expected 中的每一条都收到过，返回期间收到的所有通知
+    fn receive_all(socket: &UnixDatagram, expected: &[&str]) -> Vec<String> {
+        let mut messages = Vec::new();
+        while !expected.iter().all(|expected| messages.iter().any(|message| message == expected)) {
+            match receive(socket) {
+                Some(message) => messages.push(message),
+                None => panic!("{:?} not all received, got {:?}", expected, messages),
+            }
+        }
+        messages
+    }
+
+    // 通知地址和喂狗间隔直接传入，不修改进程的环境变量
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn notifications_reach_the_notify_socket() {
+        let dir = std::env::temp_dir().join(format!("bitcomm-notify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let stand_in = UnixDatagram::bind(&path).unwrap();
+        stand_in.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        *NOTIFY_SOCKET.lock().unwrap() = Some(path.clone().into_os_string());
+
+        let mut config = BitcommConfig::default();
+        config.opsserver.enable = false;
+        config.health.enable = false;
+        config.control.enable = false;
+        // WatchdogSec=200ms，每 100ms 喂狗一次
+        let notifier = supervisor::spawn_named("systemd/notify", run_notifier(Arc::new(config), Some(Duration::from_millis(100))));
+
+        assert_eq!(receive(&stand_in).unwrap(), "STATUS=starting, waiting for mqserver, imserver, webserver, wdserver");
+        let guards: Vec<ServiceGuard> = ["mqserver", "imserver", "webserver", "wdserver"].into_iter().map(ServiceGuard::new).collect();
+        supervisor::heartbeat("wdserver");
+
+        let messages = receive_all(&stand_in, &["READY=1\nSTATUS=4/4 services running", "STATUS=4/4 services running", "WATCHDOG=1"]);
+        assert!(!messages.iter().any(|message| message.starts_with("STATUS=starting, waiting for mqserver")), "{:?}", messages);
+
+        notify_stopping();
+        receive_all(&stand_in, &["STOPPING=1\nSTATUS=stopping"]);
+        notifier.abort();
+        drop(guards);
+        *NOTIFY_SOCKET.lock().unwrap() = None;
+
+        // 抽象地址
+        let name = format!("bitcomm-notify-{}", std::process::id());
+        let abstract_stand_in = UnixDatagram::bind_addr(&net::SocketAddr::from_abstract_name(name.as_bytes()).unwrap()).unwrap();
+        abstract_stand_in.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        notify_to(OsStr::new(&format!("@{}", name)), "STATUS=abstract").unwrap();
+        assert_eq!(receive(&abstract_stand_in).unwrap(), "STATUS=abstract");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expected_services_follow_config() {
+        let mut config = BitcommConfig::default();
+        assert_eq!(expected_services(&config), vec!["mqserver", "imserver", "webserver", "wdserver", "opsserver", "hcserver", "ctlserver"]);
+        config.imserver.tcp_fallback.enable = true;
+        config.opsserver.enable = false;
+        config.health.enable = false;
+        config.control.enable = false;
+        config.webserver.websocket.enable = true;
+        assert_eq!(expected_services(&config), vec!["mqserver", "imserver", "webserver", "wdserver", "imtcpserver", "imwsserver"]);
+    }
+}
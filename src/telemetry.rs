@@ -0,0 +1,412 @@
+// 日志与链路追踪初始化
+//
+// 所有 tracing layer 都在这里组装，bin/bitcomm.rs 启动时调用一次 init_tracing。
+// 日志过滤器只作用于日志 sink、最近日志缓冲和 OTLP 导出；
+// 启用 console feature 时，tokio-console 的 layer 不经过该过滤器，以便收到 tokio 的 trace 级数据。
+//
+// web 请求的 span 由 opsserver 的 TraceLayer 产生；IM 连接与消息、MQ 发布与消费的 span 由这里的函数创建，
+// btcmnetwork 处理 QUIC 上的消息和 NATS 收发时也应使用它们，字段遵循 OpenTelemetry messaging 语义约定。
+// MQ 的链路以 W3C trace context (traceparent / tracestate) 放在 NATS 消息头中传递，消费方的 span 以发布方为父；
+// 每次创建 MQ span 同时计一次 mqserver 的进度 (见 watchdog.rs)，并计入 bitcomm_nats_published_total /
+// bitcomm_nats_consumed_total；消费的是 JetStream 消息时，从其 ack subject 取出 pending 写入 bitcomm_nats_consumer_lag。
+
+use crate::config::{ BitcommConfig, OtlpProtocol, OtlpSection };
+#[cfg(feature = "console")]
+use crate::config::ConsoleSection;
+use crate::journal::{ self, EventKind };
+use crate::{ logring, logsink, metrics, supervisor };
+use ::metrics::{ counter, gauge };
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{ self as sdktrace, BatchConfig, Sampler, Tracer };
+use opentelemetry_sdk::{ runtime, Resource };
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::{ Mutex, OnceLock };
+use std::time::Duration;
+use tokio::signal;
+use tracing::{ debug_span, field, info, info_span, warn, Span };
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::{ Layer, SubscriberExt };
+use tracing_subscriber::{ reload, util::SubscriberInitExt, EnvFilter, Registry };
+
+// 文件 sink 后台写线程的 guard，进程退出前释放以刷新缓冲
+static LOG_GUARDS: Mutex<Vec<WorkerGuard>> = Mutex::new(Vec::new());
+
+// NATS 收发所在的服务
+const MQ_SERVICE: &str = "mqserver";
+
+// 可在运行时替换的日志过滤器
+type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+static LOG_FILTER: OnceLock<LogFilter> = OnceLock::new();
+
+struct LogFilter {
+    handle: FilterHandle,
+    state: Mutex<LogFilterState>,
+}
+
+struct LogFilterState {
+    // 当前生效的过滤规则
+    directives: String,
+    // 每次修改递增，用于判断到期回退时规则是否已被再次修改
+    generation: u64,
+    // SIGUSR2 切换到 debug 之前的规则
+    before_debug: Option<String>,
+}
+
+/// 初始化全局 tracing subscriber
+pub fn init_tracing(config: &BitcommConfig) -> Result<(), Box<dyn Error>> {
+    let mut sinks = Vec::with_capacity(config.log.sinks.len());
+    for sink in &config.log.sinks {
+        let (layer, guard) = logsink::sink_layer(sink)?;
+        sinks.push(layer);
+        LOG_GUARDS.lock().unwrap().extend(guard);
+    }
+    sinks.push(logring::ring_layer(config.log.recent_lines));
+
+    // OTLP 导出为可选 layer
+    if config.otlp.enable {
+        sinks.push(tracing_opentelemetry::layer().with_tracer(otlp_tracer(&config.otlp)?).boxed());
+    }
+
+    // RUST_LOG 优先于配置文件中的 [log] filter
+    let directives = std::env::var("RUST_LOG").unwrap_or_else(|_| config.log.filter.clone());
+    let (filter, handle) = reload::Layer::new(EnvFilter::try_new(&directives)?);
+
+    let registry = tracing_subscriber::registry().with(sinks.with_filter(filter));
+    #[cfg(feature = "console")]
+    let registry = registry.with(console_layer(&config.console));
+    registry.init();
+
+    let state = LogFilterState { directives, generation: 0, before_debug: None };
+    let _ = LOG_FILTER.set(LogFilter { handle, state: Mutex::new(state) });
+    Ok(())
+}
+
+/// 当前生效的日志过滤规则
+pub fn log_filter() -> Option<String> {
+    LOG_FILTER.get().map(|filter| filter.state.lock().unwrap().directives.clone())
+}
+
+/// 运行时替换日志过滤规则，返回替换前的规则
+///
+/// 指定 ttl 时，到期后自动回退到替换前的规则；期间如果规则再次被修改则不回退。
+pub fn set_log_filter(directives: &str, ttl: Option<Duration>) -> Result<String, Box<dyn Error>> {
+    let filter = LOG_FILTER.get().ok_or("tracing is not initialized")?;
+    let new_filter = EnvFilter::try_new(directives)?;
+
+    let (previous, generation) = {
+        let mut state = filter.state.lock().unwrap();
+        filter.handle.reload(new_filter)?;
+        let previous = std::mem::replace(&mut state.directives, directives.to_string());
+        state.generation += 1;
+        (previous, state.generation)
+    };
+    info!(previous = %previous, current = %directives, ttl_secs = ttl.map(|ttl| ttl.as_secs()), "log filter changed");
+    journal::record(EventKind::ConfigReloaded, None, format!("log filter {} -> {}", previous, directives));
+
+    if let Some(ttl) = ttl {
+        let revert_to = previous.clone();
+        supervisor::spawn_named("telemetry/log-filter-ttl", async move {
+            tokio::time::sleep(ttl).await;
+            let unchanged = filter.state.lock().unwrap().generation == generation;
+            if unchanged {
+                if let Err(e) = set_log_filter(&revert_to, None) {
+                    warn!("failed to revert log filter: {}", e);
+                }
+            }
+        });
+    }
+    Ok(previous)
+}
+
+/// 在 debug 与之前的规则之间切换，返回切换后的规则
+pub fn toggle_debug_filter() -> Result<String, Box<dyn Error>> {
+    let filter = LOG_FILTER.get().ok_or("tracing is not initialized")?;
+    let restore = filter.state.lock().unwrap().before_debug.take();
+    match restore {
+        Some(directives) => {
+            set_log_filter(&directives, None)?;
+            Ok(directives)
+        }
+        None => {
+            let previous = set_log_filter("debug", None)?;
+            filter.state.lock().unwrap().before_debug = Some(previous);
+            Ok("debug".to_string())
+        }
+    }
+}
+
+/// 监听 SIGUSR2，每收到一次在 debug 与原有规则之间切换
+pub fn spawn_debug_toggle() -> Result<tokio::task::JoinHandle<()>, Box<dyn Error>> {
+    let mut sig_usr2 = signal::unix::signal(signal::unix::SignalKind::user_defined2())?;
+    Ok(
+        supervisor::spawn_named("telemetry/sigusr2", async move {
+            while sig_usr2.recv().await.is_some() {
+                journal::record(EventKind::SignalReceived, None, "SIGUSR2");
+                match toggle_debug_filter() {
+                    Ok(directives) => info!("Received SIGUSR2, log filter is now {}", directives),
+                    Err(e) => warn!("Received SIGUSR2, failed to toggle log filter: {}", e),
+                }
+            }
+        })
+    )
+}
+
+/// 退出前刷新尚未导出的 span 和尚未写入文件的日志
+pub fn shutdown_tracing() {
+    opentelemetry::global::shutdown_tracer_provider();
+    LOG_GUARDS.lock().unwrap().clear();
+}
+
+/// 一条 IM 连接的 span，转接或处理该连接的任务在其中运行
+pub fn im_connection_span(transport: &'static str, peer: Option<SocketAddr>) -> Span {
+    let span = info_span!("im.connection", otel.kind = "server", im.transport = transport, net.peer.addr = field::Empty);
+    if let Some(peer) = peer {
+        span.record("net.peer.addr", peer.to_string());
+    }
+    span
+}
+
+/// 处理一条 IM 消息的 span，direction 为 "in" (客户端发来) 或 "out" (发往客户端)
+///
+/// 每条消息一个 span，使用 debug 级别，[log] filter 打开 debug 时才会导出。
+pub fn im_message_span(transport: &'static str, direction: &'static str, size: usize) -> Span {
+    debug_span!("im.message", im.transport = transport, im.direction = direction, messaging.message.body.size = size)
+}
+
+/// 向 NATS subject 发布消息的 span，随后以 trace_headers 取出要放进消息头的 trace context
+pub fn mq_publish_span(subject: &str) -> Span {
+    supervisor::record_progress(MQ_SERVICE, 1);
+    counter!(metrics::MQ_PUBLISHED).increment(1);
+    info_span!(
+        "mq.publish",
+        otel.kind = "producer",
+        messaging.system = "nats",
+        messaging.operation = "publish",
+        messaging.destination.name = subject
+    )
+}
+
+/// 从 NATS subject 消费一条消息的 span，headers 中带有 trace context 时以发布方的 span 为父；
+/// reply 为消息的回复 subject，JetStream 消息的 ack subject 中带有消费者尚未消费的消息数
+pub fn mq_consume_span<'a>(subject: &str, reply: Option<&str>, headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> Span {
+    supervisor::record_progress(MQ_SERVICE, 1);
+    counter!(metrics::MQ_CONSUMED).increment(1);
+    if let Some((stream, consumer, pending)) = reply.and_then(jetstream_pending) {
+        gauge!(metrics::MQ_LAG, "stream" => stream.to_string(), "consumer" => consumer.to_string()).set(pending as f64);
+    }
+    let span = info_span!(
+        "mq.consume",
+        otel.kind = "consumer",
+        messaging.system = "nats",
+        messaging.operation = "receive",
+        messaging.destination.name = subject
+    );
+    let carrier: HashMap<String, String> = headers.into_iter().map(|(key, value)| (key.to_ascii_lowercase(), value.to_string())).collect();
+    let parent = TraceContextPropagator::new().extract(&carrier);
+    span.set_parent(parent);
+    span
+}
+
+// JetStream ack subject 中的 stream、consumer 和 pending：
+// $JS.ACK.<stream>.<consumer>.<delivered>.<stream seq>.<consumer seq>.<timestamp>.<pending>，
+// 或带 domain 和账号的 $JS.ACK.<domain>.<account>.<stream>.<consumer>.<delivered>.<stream seq>.<consumer seq>.<timestamp>.<pending>.<token>
+fn jetstream_pending(reply: &str) -> Option<(&str, &str, u64)> {
+    let tokens: Vec<&str> = reply.split('.').collect();
+    let (stream, consumer, pending) = match tokens[..] {
+        ["$JS", "ACK", stream, consumer, _, _, _, _, pending] => (stream, consumer, pending),
+        ["$JS", "ACK", _, _, stream, consumer, _, _, _, _, pending, _] => (stream, consumer, pending),
+        _ => return None,
+    };
+    Some((stream, consumer, pending.parse().ok()?))
+}
+
+/// span 的 W3C trace context，作为消息头随 NATS 消息发送；未启用 OTLP 导出时为空
+pub fn trace_headers(span: &Span) -> Vec<(String, String)> {
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&span.context(), &mut carrier);
+    let mut headers: Vec<(String, String)> = carrier.into_iter().collect();
+    headers.sort();
+    headers
+}
+
+/// tokio-console 数据采集 layer，在后台线程中监听 [console] 地址
+#[cfg(feature = "console")]
+fn console_layer<S>(console: &ConsoleSection) -> impl Layer<S>
+    where S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>
+{
+    console_subscriber::ConsoleLayer::builder().server_addr(SocketAddr::new(console.ip, console.port)).spawn()
+}
+
+/// 按配置构建 OTLP 批量导出的 Tracer
+fn otlp_tracer(otlp: &OtlpSection) -> Result<Tracer, Box<dyn Error>> {
+    let mut attributes = vec![KeyValue::new("service.name", otlp.service_name.clone())];
+    attributes.push(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")));
+    attributes.push(KeyValue::new("process.pid", std::process::id() as i64));
+    for (key, value) in &otlp.resource {
+        attributes.push(KeyValue::new(key.clone(), value.clone()));
+    }
+
+    let trace_config = sdktrace::config()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(otlp.sampling_ratio))))
+        .with_resource(Resource::new(attributes));
+
+    let batch_config = BatchConfig::default()
+        .with_max_queue_size(otlp.max_queue_size)
+        .with_max_export_batch_size(otlp.max_export_batch_size)
+        .with_scheduled_delay(Duration::from_millis(otlp.scheduled_delay_ms))
+        .with_max_export_timeout(Duration::from_secs(otlp.timeout));
+
+    let pipeline = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_trace_config(trace_config)
+        .with_batch_config(batch_config);
+
+    let timeout = Duration::from_secs(otlp.timeout);
+    let tracer = match otlp.protocol {
+        OtlpProtocol::Grpc => {
+            let exporter = opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(otlp.endpoint.clone())
+                .with_timeout(timeout);
+            pipeline.with_exporter(exporter).install_batch(runtime::Tokio)?
+        }
+        OtlpProtocol::Http => {
+            let exporter = opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(otlp.endpoint.clone())
+                .with_timeout(timeout);
+            pipeline.with_exporter(exporter).install_batch(runtime::Tokio)?
+        }
+    };
+    Ok(tracer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Bytes;
+    use axum::extract::State;
+    use axum::routing::post;
+    use axum::Router;
+    use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::any_value::Value as AnyValue;
+    use opentelemetry_proto::tonic::common::v1::KeyValue as ProtoKeyValue;
+    use opentelemetry_proto::tonic::trace::v1::Span as ProtoSpan;
+    use prost::Message;
+    use std::sync::Arc;
+
+    type Received = Arc<Mutex<Vec<ExportTraceServiceRequest>>>;
+
+    // OTLP/HTTP collector 的替身，记录收到的导出请求
+    async fn collector() -> (SocketAddr, Received) {
+        let received = Received::default();
+        let app = Router::new()
+            .route(
+                "/v1/traces",
+                post(|State(received): State<Received>, body: Bytes| async move {
+                    received.lock().unwrap().push(ExportTraceServiceRequest::decode(body).unwrap());
+                })
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (addr, received)
+    }
+
+    fn attribute<'a>(attributes: &'a [ProtoKeyValue], key: &str) -> Option<&'a AnyValue> {
+        attributes.iter().find(|kv| kv.key == key).and_then(|kv| kv.value.as_ref()).and_then(|value| value.value.as_ref())
+    }
+
+    fn string_attribute(attributes: &[ProtoKeyValue], key: &str) -> Option<String> {
+        match attribute(attributes, key) {
+            Some(AnyValue::StringValue(value)) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn spans_are_exported_with_mq_trace_context() {
+        let (addr, received) = collector().await;
+        let otlp = OtlpSection {
+            enable: true,
+            protocol: OtlpProtocol::Http,
+            endpoint: format!("http://{}", addr),
+            service_name: "bitcomm-test".to_string(),
+            resource: [("deployment.environment".to_string(), "test".to_string())].into(),
+            scheduled_delay_ms: 50,
+            ..OtlpSection::default()
+        };
+        let tracer = otlp_tracer(&otlp).unwrap();
+        let provider = tracer.provider().unwrap();
+        let subscriber = Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+        let headers = tracing::subscriber::with_default(subscriber, || {
+            let publish = mq_publish_span("im.msg.alice");
+            let headers = trace_headers(&publish);
+            drop(publish);
+
+            let consume = mq_consume_span("im.msg.alice", None, headers.iter().map(|(key, value)| (key.as_str(), value.as_str())));
+            consume.in_scope(|| {
+                let _connection = im_connection_span("websocket", Some(([127, 0, 0, 1], 40000).into())).entered();
+                let _message = im_message_span("websocket", "in", 5).entered();
+            });
+            headers
+        });
+        assert_eq!(headers.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>(), vec!["traceparent", "tracestate"]);
+
+        tokio::task::spawn_blocking(move || provider.force_flush()).await.unwrap();
+        let mut spans: Vec<(Vec<ProtoKeyValue>, ProtoSpan)> = Vec::new();
+        for _ in 0..100 {
+            spans = received
+                .lock()
+                .unwrap()
+                .iter()
+                .flat_map(|request| request.resource_spans.iter())
+                .flat_map(|resource_spans| {
+                    let resource = resource_spans.resource.clone().unwrap_or_default().attributes;
+                    resource_spans.scope_spans.iter().flat_map(move |scope| scope.spans.iter().map({
+                        let resource = resource.clone();
+                        move |span| (resource.clone(), span.clone())
+                    }))
+                })
+                .collect();
+            if spans.len() >= 4 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let find = |name: &str| spans.iter().find(|(_, span)| span.name == name).unwrap_or_else(|| panic!("no {} span in {:?}", name, spans));
+        let (resource, publish) = find("mq.publish");
+        let (_, consume) = find("mq.consume");
+        let (_, connection) = find("im.connection");
+        assert_eq!(string_attribute(resource, "service.name").as_deref(), Some("bitcomm-test"));
+        assert_eq!(string_attribute(resource, "deployment.environment").as_deref(), Some("test"));
+        assert_eq!(string_attribute(&publish.attributes, "messaging.system").as_deref(), Some("nats"));
+        assert_eq!(string_attribute(&publish.attributes, "messaging.destination.name").as_deref(), Some("im.msg.alice"));
+        assert_eq!(string_attribute(&consume.attributes, "messaging.operation").as_deref(), Some("receive"));
+        assert_eq!(string_attribute(&connection.attributes, "net.peer.addr").as_deref(), Some("127.0.0.1:40000"));
+
+        // 消费方与发布方在同一条链路上，IM 的 span 挂在消费 span 之下
+        assert_eq!(consume.trace_id, publish.trace_id);
+        assert_eq!(consume.parent_span_id, publish.span_id);
+        assert_eq!(connection.parent_span_id, consume.span_id);
+        assert_eq!(find("im.message").1.parent_span_id, connection.span_id);
+    }
+
+    #[test]
+    fn consumer_lag_comes_from_jetstream_ack_subjects() {
+        assert_eq!(jetstream_pending("$JS.ACK.CHAT.worker.1.42.40.1700000000000000000.7"), Some(("CHAT", "worker", 7)));
+        assert_eq!(jetstream_pending("$JS.ACK.hub.ACCHASH.CHAT.worker.1.42.40.1700000000000000000.0.abc"), Some(("CHAT", "worker", 0)));
+        assert_eq!(jetstream_pending("_INBOX.abc.123"), None);
+        assert_eq!(jetstream_pending("$JS.ACK.CHAT.worker.1.42.40.1700000000000000000.x"), None);
+    }
+}
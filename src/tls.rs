@@ -0,0 +1,787 @@
+// IM 服务器 TLS 证书
+//
+// [imserver] 配置了 cert_path / key_path 时在启动时加载证书，文件无效则拒绝启动。
+// 之后每 cert_reload_secs 秒比较证书、私钥和 CA 链文件的修改时间与大小，变化后重新加载：
+// 服务器证书通过 ResolvesServerCert 在每次握手时取得，替换后只影响新握手，已建立的连接不受影响；
+// 新文件无效时继续使用原证书并告警。
+// 证书距到期的秒数写入 bitcomm_tls_cert_expiry_seconds，少于 cert_expiry_warn_days 天时告警，过期后升级为严重告警。
+//
+// [imserver.client_auth] 启用客户端证书认证 (mTLS) 时，客户端证书须由 ca_path 中的 CA 签发、未被 crl_path 吊销，
+// 并能按 user_id / user_id_prefix 映射出 bitcomm 用户 ID，否则握手失败；optional 模式下不带证书的客户端仍可用 JWT 认证。
+// 吊销列表与证书一样按 cert_reload_secs 检查变化，重新加载后新握手立即生效。
+//
+// 这里加载的证书、热加载和客户端证书认证目前只作用于 bitcomm 自己终结的 TLS，即 TCP+TLS 备用传输 (imtcp)。
+// QUIC 端点在 btcmnetwork 中创建，imserver::start_instant_message_server 不接受外部的 TLS 配置，
+// QUIC 握手使用的证书和客户端认证仍由 btcmnetwork 决定，不受这里的热加载和 mTLS 配置影响 (到期告警按 cert_path 中的文件计算)。
+// quic_server_config 已按同一份可替换证书和 ClientAuth 构造 quinn 的服务器配置，握手完成后用
+// ClientAuth::user_id(连接的 peer_identity) 取得用户 ID；btcmnetwork 提供接受 quinn::ServerConfig 的入口后
+// 由 bin/bitcomm.rs 用 imquic::endpoint 创建端点传入即可，在此之前 QUIC 上的证书热加载和客户端证书认证不算完成。
+
+use crate::alerting::{ self, AlertSeverity };
+use crate::config::{ ClientAuthMode, ClientAuthSection, ClientIdSource, ImServerSection };
+use crate::{ metrics, supervisor };
+use quinn::crypto::rustls::QuicServerConfig;
+use ::metrics::{ counter, gauge };
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{ CertificateDer, CertificateRevocationListDer, PrivateKeyDer, UnixTime };
+use rustls::server::danger::{ ClientCertVerified, ClientCertVerifier };
+use rustls::server::{ ClientHello, ResolvesServerCert, WebPkiClientVerifier };
+use rustls::sign::CertifiedKey;
+use rustls::{ CertificateError, DigitallySignedStruct, DistinguishedName, RootCertStore, ServerConfig, SignatureScheme };
+use std::error::Error;
+use std::fmt;
+use std::path::{ Path, PathBuf };
+use std::sync::atomic::{ AtomicBool, AtomicI64, Ordering };
+use std::sync::{ Arc, OnceLock, RwLock };
+use std::time::{ Duration, SystemTime };
+use tracing::{ debug, error, info, warn };
+use x509_parser::extensions::GeneralName;
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+static IMSERVER: OnceLock<Arc<ReloadingCert>> = OnceLock::new();
+
+static IMSERVER_CLIENT_AUTH: OnceLock<Arc<ClientAuth>> = OnceLock::new();
+
+/// 可在运行中替换的服务器证书
+pub struct ReloadingCert {
+    name: &'static str,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    ca_path: Option<PathBuf>,
+    current: RwLock<Arc<CertifiedKey>>,
+    // 服务器证书的到期时间 (Unix 秒)
+    not_after: AtomicI64,
+    // 是否已发出到期告警
+    expiring: AtomicBool,
+}
+
+impl fmt::Debug for ReloadingCert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReloadingCert")
+            .field("name", &self.name)
+            .field("cert_path", &self.cert_path)
+            .field("not_after", &self.not_after)
+            .finish()
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+impl ReloadingCert {
+    fn load(name: &'static str, cert_path: &Path, key_path: &Path, ca_path: Option<&Path>) -> Result<ReloadingCert, Box<dyn Error>> {
+        let (key, not_after) = load_certified_key(cert_path, key_path, ca_path)?;
+        Ok(ReloadingCert {
+            name,
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            ca_path: ca_path.map(Path::to_path_buf),
+            current: RwLock::new(Arc::new(key)),
+            not_after: AtomicI64::new(not_after),
+            expiring: AtomicBool::new(false),
+        })
+    }
+
+    /// 当前使用的证书
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// 服务器证书的到期时间 (Unix 秒)
+    pub fn not_after(&self) -> i64 {
+        self.not_after.load(Ordering::Relaxed)
+    }
+
+    fn stamp(&self) -> Vec<Option<(SystemTime, u64)>> {
+        let mut paths = vec![self.cert_path.as_path(), self.key_path.as_path()];
+        paths.extend(self.ca_path.as_deref());
+        file_stamp(&paths)
+    }
+
+    fn reload(&self) {
+        let reload_key = format!("tls-cert-reload:{}", self.name);
+        match load_certified_key(&self.cert_path, &self.key_path, self.ca_path.as_deref()) {
+            Ok((key, not_after)) => {
+                *self.current.write().unwrap() = Arc::new(key);
+                self.not_after.store(not_after, Ordering::Relaxed);
+                counter!(metrics::TLS_CERT_RELOADS, "cert" => self.name, "result" => "ok").increment(1);
+                alerting::resolve(reload_key, "certificate reloaded");
+                info!(cert = self.name, path = %self.cert_path.display(), not_after = %format_time(not_after), "certificate reloaded");
+            }
+            Err(e) => {
+                counter!(metrics::TLS_CERT_RELOADS, "cert" => self.name, "result" => "error").increment(1);
+                error!(cert = self.name, path = %self.cert_path.display(), "failed to reload certificate, keeping the current one: {}", e);
+                alerting::fire(
+                    reload_key,
+                    AlertSeverity::Warning,
+                    Some("imserver"),
+                    format!("{} certificate reload failed", self.name),
+                    e.to_string()
+                );
+            }
+        }
+    }
+
+    // 更新剩余有效期指标，临近到期或已过期时告警
+    fn check_expiry(&self, warn_days: u64) {
+        let not_after = self.not_after();
+        let remaining = not_after - chrono::Utc::now().timestamp();
+        gauge!(metrics::TLS_CERT_EXPIRY, "cert" => self.name).set(remaining as f64);
+
+        let expiry_key = format!("tls-cert-expiry:{}", self.name);
+        if remaining >= (warn_days as i64) * SECONDS_PER_DAY {
+            if self.expiring.swap(false, Ordering::Relaxed) {
+                alerting::resolve(expiry_key, format!("certificate valid until {}", format_time(not_after)));
+            }
+            return;
+        }
+        let (severity, summary) = if remaining <= 0 {
+            (AlertSeverity::Critical, format!("{} certificate has expired", self.name))
+        } else {
+            (AlertSeverity::Warning, format!("{} certificate expires in {} days", self.name, remaining / SECONDS_PER_DAY))
+        };
+        if !self.expiring.swap(true, Ordering::Relaxed) {
+            warn!(cert = self.name, not_after = %format_time(not_after), "{}", summary);
+        }
+        alerting::fire(expiry_key, severity, Some("imserver"), summary, format!("{} not after {}", self.cert_path.display(), format_time(not_after)));
+    }
+}
+
+/// 客户端证书认证：校验证书链与吊销列表，并把证书映射为 bitcomm 用户 ID
+pub struct ClientAuth {
+    config: ClientAuthSection,
+    roots: Arc<RootCertStore>,
+    // CertificateRequest 中提示客户端的 CA，取自 ca_path
+    hints: Vec<DistinguishedName>,
+    // 吊销列表重新加载时整体替换
+    verifier: RwLock<Arc<dyn ClientCertVerifier>>,
+}
+
+impl fmt::Debug for ClientAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientAuth")
+            .field("mode", &self.config.mode)
+            .field("ca_path", &self.config.ca_path)
+            .field("crl_path", &self.config.crl_path)
+            .finish()
+    }
+}
+
+impl ClientAuth {
+    fn load(config: &ClientAuthSection) -> Result<ClientAuth, Box<dyn Error>> {
+        let ca_path = config.ca_path.as_ref().ok_or("[imserver.client_auth] ca_path is required when mode is not none")?;
+        let mut roots = RootCertStore::empty();
+        for certificate in read_certificates(ca_path)? {
+            roots.add(certificate).map_err(|e| format!("{}: {}", ca_path.display(), e))?;
+        }
+        let roots = Arc::new(roots);
+        let verifier = client_verifier(config, roots.clone())?;
+        Ok(ClientAuth { config: config.clone(), hints: roots.subjects(), roots, verifier: RwLock::new(verifier) })
+    }
+
+    /// 认证方式
+    pub fn mode(&self) -> ClientAuthMode {
+        self.config.mode
+    }
+
+    /// 由握手得到的客户端证书链 (终端证书在前) 取得用户 ID；客户端没有提供证书时返回 Ok(None)
+    pub fn user_id(&self, peer_certificates: &[CertificateDer<'_>]) -> Result<Option<String>, String> {
+        match peer_certificates.first() {
+            Some(end_entity) => client_user_id(end_entity, self.config.user_id, &self.config.user_id_prefix).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn stamp(&self) -> Vec<Option<(SystemTime, u64)>> {
+        file_stamp(&self.config.crl_path.iter().map(PathBuf::as_path).collect::<Vec<_>>())
+    }
+
+    fn reload_crl(&self) {
+        let reload_key = "tls-crl-reload:imserver";
+        match client_verifier(&self.config, self.roots.clone()) {
+            Ok(verifier) => {
+                *self.verifier.write().unwrap() = verifier;
+                counter!(metrics::TLS_CERT_RELOADS, "cert" => "imserver-crl", "result" => "ok").increment(1);
+                alerting::resolve(reload_key, "revocation list reloaded");
+                info!(path = ?self.config.crl_path, "client certificate revocation list reloaded");
+            }
+            Err(e) => {
+                counter!(metrics::TLS_CERT_RELOADS, "cert" => "imserver-crl", "result" => "error").increment(1);
+                error!(path = ?self.config.crl_path, "failed to reload revocation list, keeping the current one: {}", e);
+                alerting::fire(reload_key, AlertSeverity::Warning, Some("imserver"), "imserver revocation list reload failed", e.to_string());
+            }
+        }
+    }
+
+    fn current(&self) -> Arc<dyn ClientCertVerifier> {
+        self.verifier.read().unwrap().clone()
+    }
+}
+
+impl ClientCertVerifier for ClientAuth {
+    fn offer_client_auth(&self) -> bool {
+        true
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        self.config.mode == ClientAuthMode::Required
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &self.hints
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        let verified = self.current().verify_client_cert(end_entity, intermediates, now);
+        // 证书有效但映射不出用户 ID 时同样拒绝
+        let result = verified.and_then(|verified| {
+            client_user_id(end_entity, self.config.user_id, &self.config.user_id_prefix).map(|_| verified).map_err(rustls::Error::General)
+        });
+        if let Err(e) = &result {
+            counter!(metrics::TLS_CLIENT_AUTH_FAILURES, "reason" => failure_reason(e)).increment(1);
+            debug!("client certificate rejected: {}", e);
+        }
+        result
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.current().verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.current().verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.current().supported_verify_schemes()
+    }
+}
+
+/// 按 [imserver] 配置加载证书和客户端认证并开始监视文件变化；未配置证书时返回 None
+pub fn init(config: &ImServerSection) -> Result<Option<Arc<ReloadingCert>>, Box<dyn Error>> {
+    let (cert_path, key_path) = match (&config.cert_path, &config.key_path) {
+        (None, None) if config.client_auth.mode != ClientAuthMode::None => {
+            return Err("[imserver.client_auth] requires [imserver] cert_path and key_path".into());
+        }
+        (None, None) if config.tcp_fallback.enable => {
+            return Err("[imserver.tcp_fallback] requires [imserver] cert_path and key_path".into());
+        }
+        (None, None) => return Ok(None),
+        (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+        _ => return Err("[imserver] cert_path and key_path must be set together".into()),
+    };
+    let cert = ReloadingCert::load("imserver", cert_path, key_path, config.ca_path.as_deref())
+        .map_err(|e| format!("[imserver] certificate {}: {}", cert_path.display(), e))?;
+    let cert = Arc::new(cert);
+    info!(path = %cert_path.display(), not_after = %format_time(cert.not_after()), "imserver certificate loaded");
+    cert.check_expiry(config.cert_expiry_warn_days);
+    let _ = IMSERVER.set(cert.clone());
+
+    let client_auth = match config.client_auth.mode {
+        ClientAuthMode::None => None,
+        mode => {
+            let client_auth = Arc::new(ClientAuth::load(&config.client_auth)?);
+            info!(?mode, user_id = ?config.client_auth.user_id, crl = ?config.client_auth.crl_path, "imserver client certificate authentication enabled");
+            let _ = IMSERVER_CLIENT_AUTH.set(client_auth.clone());
+            Some(client_auth)
+        }
+    };
+
+    let watched = cert.clone();
+    let interval = Duration::from_secs(config.cert_reload_secs);
+    let warn_days = config.cert_expiry_warn_days;
+    supervisor::spawn_named("imserver/cert-reload", async move {
+        let mut stamp = watched.stamp();
+        let mut crl_stamp = client_auth.as_ref().map(|client_auth| client_auth.stamp());
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let latest = watched.stamp();
+            if latest != stamp {
+                stamp = latest;
+                watched.reload();
+            }
+            watched.check_expiry(warn_days);
+
+            if let Some(client_auth) = &client_auth {
+                let latest = Some(client_auth.stamp());
+                if latest != crl_stamp {
+                    crl_stamp = latest;
+                    client_auth.reload_crl();
+                }
+            }
+        }
+    });
+    Ok(Some(cert))
+}
+
+/// IM 服务器证书，未配置时为 None
+pub fn imserver() -> Option<Arc<ReloadingCert>> {
+    IMSERVER.get().cloned()
+}
+
+/// IM 服务器的客户端证书认证，未启用时为 None
+pub fn imserver_client_auth() -> Option<Arc<ClientAuth>> {
+    IMSERVER_CLIENT_AUTH.get().cloned()
+}
+
+/// 使用可替换证书的 rustls 服务器配置，client_auth 为 None 时不请求客户端证书；
+/// QUIC 端点另需设置 ALPN 并只启用 TLS 1.3
+pub fn server_config(cert: Arc<ReloadingCert>, client_auth: Option<Arc<ClientAuth>>) -> Result<ServerConfig, Box<dyn Error>> {
+    let builder = ServerConfig::builder_with_provider(Arc::new(ring::default_provider())).with_safe_default_protocol_versions()?;
+    let builder = match client_auth {
+        Some(client_auth) => builder.with_client_cert_verifier(client_auth),
+        None => builder.with_no_client_auth(),
+    };
+    Ok(builder.with_cert_resolver(cert))
+}
+
+/// QUIC 端点的服务器配置：与 server_config 共用可替换证书和客户端认证，只启用 TLS 1.3，alpn 须与客户端一致
+pub fn quic_server_config(cert: Arc<ReloadingCert>, client_auth: Option<Arc<ClientAuth>>, alpn: &[String]) -> Result<quinn::ServerConfig, Box<dyn Error>> {
+    let builder = ServerConfig::builder_with_provider(Arc::new(ring::default_provider())).with_protocol_versions(&[&rustls::version::TLS13])?;
+    let builder = match client_auth {
+        Some(client_auth) => builder.with_client_cert_verifier(client_auth),
+        None => builder.with_no_client_auth(),
+    };
+    let mut crypto = builder.with_cert_resolver(cert);
+    crypto.alpn_protocols = alpn.iter().map(|alpn| alpn.as_bytes().to_vec()).collect();
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto)?)))
+}
+
+// 吊销列表只检查终端证书；列表中没有签发者的记录时按 allow_unknown_revocation 决定是否放行
+fn client_verifier(config: &ClientAuthSection, roots: Arc<RootCertStore>) -> Result<Arc<dyn ClientCertVerifier>, Box<dyn Error>> {
+    let mut builder = WebPkiClientVerifier::builder_with_provider(roots, Arc::new(ring::default_provider()));
+    if let Some(crl_path) = &config.crl_path {
+        let crls = CertificateRevocationListDer::pem_file_iter(crl_path)
+            .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("{}: {}", crl_path.display(), e))?;
+        if crls.is_empty() {
+            return Err(format!("{}: no revocation list found", crl_path.display()).into());
+        }
+        builder = builder.with_crls(crls).only_check_end_entity_revocation();
+        if config.allow_unknown_revocation {
+            builder = builder.allow_unknown_revocation_status();
+        }
+    }
+    if config.mode == ClientAuthMode::Optional {
+        builder = builder.allow_unauthenticated();
+    }
+    Ok(builder.build()?)
+}
+
+// 按配置从证书中取用户 ID：第一个以前缀开头且去掉前缀后非空的值
+fn client_user_id(certificate: &CertificateDer<'_>, source: ClientIdSource, prefix: &str) -> Result<String, String> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(certificate.as_ref()).map_err(|e| format!("invalid client certificate: {}", e))?;
+    let values: Vec<String> = match source {
+        ClientIdSource::CommonName => {
+            parsed.subject().iter_common_name().filter_map(|name| name.as_str().ok()).map(str::to_string).collect()
+        }
+        _ => {
+            let names = parsed.subject_alternative_name().ok().flatten().map(|san| san.value.general_names.clone()).unwrap_or_default();
+            names
+                .iter()
+                .filter_map(|name| match (source, name) {
+                    (ClientIdSource::SanUri, GeneralName::URI(value)) => Some(value.to_string()),
+                    (ClientIdSource::SanDns, GeneralName::DNSName(value)) => Some(value.to_string()),
+                    (ClientIdSource::SanEmail, GeneralName::RFC822Name(value)) => Some(value.to_string()),
+                    _ => None,
+                })
+                .collect()
+        }
+    };
+    values
+        .iter()
+        .find_map(|value| value.strip_prefix(prefix).filter(|id| !id.is_empty()))
+        .map(str::to_string)
+        .ok_or_else(|| format!("client certificate has no {:?} value with prefix {:?}", source, prefix))
+}
+
+fn failure_reason(error: &rustls::Error) -> &'static str {
+    match error {
+        rustls::Error::InvalidCertificate(CertificateError::Revoked) => "revoked",
+        rustls::Error::InvalidCertificate(CertificateError::UnknownRevocationStatus) => "unknown-revocation-status",
+        rustls::Error::InvalidCertificate(CertificateError::Expired | CertificateError::ExpiredContext { .. }) => "expired",
+        rustls::Error::InvalidCertificate(CertificateError::UnknownIssuer) => "unknown-issuer",
+        rustls::Error::General(_) => "no-user-id",
+        _ => "invalid",
+    }
+}
+
+// 各文件的修改时间与大小，任一变化即重新加载
+fn file_stamp(paths: &[&Path]) -> Vec<Option<(SystemTime, u64)>> {
+    paths
+        .iter()
+        .map(|path| std::fs::metadata(path).ok().and_then(|meta| meta.modified().ok().map(|modified| (modified, meta.len()))))
+        .collect()
+}
+
+// 读取证书链 (服务器证书在前，CA 链在后) 和私钥，返回证书与服务器证书的到期时间
+fn load_certified_key(cert_path: &Path, key_path: &Path, ca_path: Option<&Path>) -> Result<(CertifiedKey, i64), Box<dyn Error>> {
+    let mut chain = read_certificates(cert_path)?;
+    if let Some(ca_path) = ca_path {
+        chain.extend(read_certificates(ca_path)?);
+    }
+    let not_after = certificate_not_after(&chain[0])?;
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| format!("{}: {}", key_path.display(), e))?;
+    let key = CertifiedKey::from_der(chain, key, &ring::default_provider()).map_err(|e| format!("{}: {}", key_path.display(), e))?;
+    Ok((key, not_after))
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, Box<dyn Error>> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if certificates.is_empty() {
+        return Err(format!("{}: no certificate found", path.display()).into());
+    }
+    Ok(certificates)
+}
+
+fn certificate_not_after(certificate: &CertificateDer<'_>) -> Result<i64, Box<dyn Error>> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(certificate.as_ref()).map_err(|e| format!("invalid certificate: {}", e))?;
+    Ok(parsed.validity().not_after.timestamp())
+}
+
+fn format_time(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0).map(|time| time.to_rfc3339()).unwrap_or_else(|| timestamp.to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use rcgen::{
+        date_time_ymd, BasicConstraints, CertificateParams, CertificateRevocationListParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyIdMethod,
+        KeyPair, KeyUsagePurpose, RevocationReason, RevokedCertParams, SanType, SerialNumber,
+    };
+    use rustls::pki_types::ServerName;
+    use rustls::ClientConfig;
+    use std::fs;
+    use std::net::Ipv4Addr;
+    use tokio_rustls::{ TlsAcceptor, TlsConnector };
+
+    /// 测试用的 CA，签发服务器和客户端证书并写入 dir
+    pub(crate) struct TestPki {
+        pub(crate) dir: PathBuf,
+        name: String,
+        ca: rcgen::Certificate,
+        ca_key: KeyPair,
+        serial: u64,
+    }
+
+    impl TestPki {
+        pub(crate) fn new(dir: &Path, name: &str) -> TestPki {
+            fs::create_dir_all(dir).unwrap();
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params.distinguished_name.push(DnType::CommonName, format!("{} CA", name));
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+            let ca_key = KeyPair::generate().unwrap();
+            let ca = params.self_signed(&ca_key).unwrap();
+            fs::write(dir.join(format!("{}-ca.pem", name)), ca.pem()).unwrap();
+            TestPki { dir: dir.to_path_buf(), name: name.to_string(), ca, ca_key, serial: 100 }
+        }
+
+        pub(crate) fn ca_path(&self) -> PathBuf {
+            self.dir.join(format!("{}-ca.pem", self.name))
+        }
+
+        pub(crate) fn ca_pem(&self) -> String {
+            self.ca.pem()
+        }
+
+        // 签发证书，写入 <file>.pem 和 <file>.key，返回两者路径和序列号
+        fn issue(&mut self, file: &str, params: CertificateParams) -> (PathBuf, PathBuf, u64) {
+            let mut params = params;
+            self.serial += 1;
+            params.serial_number = Some(SerialNumber::from(self.serial));
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+            let (cert_path, key_path) = (self.dir.join(format!("{}.pem", file)), self.dir.join(format!("{}.key", file)));
+            fs::write(&cert_path, cert.pem()).unwrap();
+            fs::write(&key_path, key.serialize_pem()).unwrap();
+            (cert_path, key_path, self.serial)
+        }
+
+        /// localhost 的服务器证书
+        pub(crate) fn server(&mut self, file: &str) -> (PathBuf, PathBuf) {
+            let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            params.distinguished_name.push(DnType::CommonName, "localhost");
+            params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+            let (cert_path, key_path, _) = self.issue(file, params);
+            (cert_path, key_path)
+        }
+
+        /// 主题 CN 为 common_name 的客户端证书，另带 sans 中的 SAN
+        pub(crate) fn client(&mut self, file: &str, common_name: &str, sans: Vec<SanType>) -> (PathBuf, PathBuf, u64) {
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params.distinguished_name.push(DnType::CommonName, common_name);
+            params.subject_alt_names = sans;
+            params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+            self.issue(file, params)
+        }
+
+        /// 把吊销了 serials 的吊销列表写入 path
+        pub(crate) fn write_crl(&self, path: &Path, serials: &[u64]) {
+            let crl = CertificateRevocationListParams {
+                this_update: date_time_ymd(2024, 1, 1),
+                next_update: date_time_ymd(2099, 1, 1),
+                crl_number: SerialNumber::from(1u64),
+                issuing_distribution_point: None,
+                revoked_certs: serials
+                    .iter()
+                    .map(|serial| RevokedCertParams {
+                        serial_number: SerialNumber::from(*serial),
+                        revocation_time: date_time_ymd(2024, 1, 1),
+                        reason_code: Some(RevocationReason::KeyCompromise),
+                        invalidity_date: None,
+                    })
+                    .collect(),
+                key_identifier_method: KeyIdMethod::Sha256,
+            };
+            fs::write(path, crl.signed_by(&self.ca, &self.ca_key).unwrap().pem().unwrap()).unwrap();
+        }
+
+        /// 信任本 CA 的客户端配置，client 为客户端证书和私钥
+        pub(crate) fn client_config(&self, client: Option<(&Path, &Path)>) -> ClientConfig {
+            let mut roots = RootCertStore::empty();
+            roots.add(CertificateDer::from_pem_file(self.ca_path()).unwrap()).unwrap();
+            let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+            match client {
+                Some((cert_path, key_path)) => {
+                    builder.with_client_auth_cert(read_certificates(cert_path).unwrap(), PrivateKeyDer::from_pem_file(key_path).unwrap()).unwrap()
+                }
+                None => builder.with_no_client_auth(),
+            }
+        }
+    }
+
+    // 通过 QUIC 连接 endpoint，返回服务器出示的证书链
+    pub(crate) async fn quic_handshake(endpoint: &quinn::Endpoint, client: ClientConfig) -> Result<Vec<CertificateDer<'static>>, String> {
+        let mut client = client;
+        client.alpn_protocols = vec![b"bitcomm-im".to_vec()];
+        let client = quinn::ClientConfig::new(Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(client).unwrap()));
+        let mut connector = quinn::Endpoint::client((Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+        connector.set_default_client_config(client);
+        let connection = connector.connect(endpoint.local_addr().unwrap(), "localhost").unwrap().await.map_err(|e| e.to_string())?;
+        let chain = connection.peer_identity().and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok());
+        connection.close(0u32.into(), b"");
+        Ok(*chain.unwrap())
+    }
+
+    // 接受所有连接直到 endpoint 关闭
+    fn quic_endpoint(config: quinn::ServerConfig) -> quinn::Endpoint {
+        let endpoint = quinn::Endpoint::server(config, (Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+        let server = endpoint.clone();
+        tokio::spawn(async move {
+            while let Some(incoming) = server.accept().await {
+                tokio::spawn(async move {
+                    if let Ok(connection) = incoming.await {
+                        connection.closed().await;
+                    }
+                });
+            }
+        });
+        endpoint
+    }
+
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bitcomm-{}-{}", name, std::process::id()))
+    }
+
+    pub(crate) fn load_cert(cert_path: &Path, key_path: &Path) -> Arc<ReloadingCert> {
+        Arc::new(ReloadingCert::load("imserver", cert_path, key_path, None).unwrap())
+    }
+
+    pub(crate) fn load_client_auth(config: &ClientAuthSection) -> Arc<ClientAuth> {
+        Arc::new(ClientAuth::load(config).unwrap())
+    }
+
+    // 在内存管道上完成一次握手，返回服务器端看到的客户端证书链
+    async fn handshake(server: &ServerConfig, client: ClientConfig) -> Result<Vec<CertificateDer<'static>>, String> {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let connector = TlsConnector::from(Arc::new(client));
+        let acceptor = TlsAcceptor::from(Arc::new(server.clone()));
+        let (_, accepted) = tokio::join!(connector.connect(ServerName::try_from("localhost").unwrap(), client_io), acceptor.accept(server_io));
+        let stream = accepted.map_err(|e| e.to_string())?;
+        Ok(stream.get_ref().1.peer_certificates().map(<[_]>::to_vec).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn client_certificates_are_verified_and_mapped() {
+        let dir = temp_dir("tls-client-auth");
+        let mut pki = TestPki::new(&dir, "main");
+        let mut other = TestPki::new(&dir, "other");
+        let (cert_path, key_path) = pki.server("server");
+        let (alice_cert, alice_key, alice_serial) = pki.client("alice", "user-alice", Vec::new());
+        let (bob_cert, bob_key, bob_serial) = pki.client("bob", "user-bob", Vec::new());
+        let (guest_cert, guest_key, _) = pki.client("guest", "guest", Vec::new());
+        let (carol_cert, carol_key, _) = other.client("carol", "user-carol", Vec::new());
+
+        // 两个 CA 都受信任，但只有 main 提供吊销列表
+        let ca_bundle = dir.join("client-ca.pem");
+        fs::write(&ca_bundle, pki.ca_pem() + &other.ca_pem()).unwrap();
+        let crl_path = dir.join("crl.pem");
+        pki.write_crl(&crl_path, &[bob_serial]);
+
+        let config = ClientAuthSection {
+            mode: ClientAuthMode::Required,
+            ca_path: Some(ca_bundle.clone()),
+            crl_path: Some(crl_path.clone()),
+            user_id_prefix: "user-".to_string(),
+            ..ClientAuthSection::default()
+        };
+        let cert = Arc::new(ReloadingCert::load("imserver", &cert_path, &key_path, None).unwrap());
+        let client_auth = Arc::new(ClientAuth::load(&config).unwrap());
+        let server = server_config(cert.clone(), Some(client_auth.clone())).unwrap();
+
+        let chain = handshake(&server, pki.client_config(Some((&alice_cert, &alice_key)))).await.unwrap();
+        assert_eq!(client_auth.user_id(&chain).unwrap(), Some("alice".to_string()));
+        assert!(handshake(&server, pki.client_config(Some((&bob_cert, &bob_key)))).await.is_err(), "revoked");
+        assert!(handshake(&server, pki.client_config(Some((&guest_cert, &guest_key)))).await.is_err(), "no user id with the prefix");
+        assert!(handshake(&server, pki.client_config(Some((&carol_cert, &carol_key)))).await.is_err(), "issuer without a revocation list");
+        assert!(handshake(&server, pki.client_config(None)).await.is_err(), "required mode without a certificate");
+
+        // 吊销列表重新加载后立即生效
+        pki.write_crl(&crl_path, &[bob_serial, alice_serial]);
+        client_auth.reload_crl();
+        assert!(handshake(&server, pki.client_config(Some((&alice_cert, &alice_key)))).await.is_err(), "revoked after reload");
+
+        let lenient = ClientAuthSection { allow_unknown_revocation: true, ..config.clone() };
+        let server = server_config(cert.clone(), Some(Arc::new(ClientAuth::load(&lenient).unwrap()))).unwrap();
+        assert!(handshake(&server, pki.client_config(Some((&carol_cert, &carol_key)))).await.is_ok());
+        assert!(handshake(&server, pki.client_config(Some((&bob_cert, &bob_key)))).await.is_err(), "still revoked");
+
+        let optional = ClientAuthSection { mode: ClientAuthMode::Optional, ..config };
+        let client_auth = Arc::new(ClientAuth::load(&optional).unwrap());
+        let server = server_config(cert, Some(client_auth.clone())).unwrap();
+        let chain = handshake(&server, pki.client_config(None)).await.unwrap();
+        assert_eq!(client_auth.user_id(&chain).unwrap(), None);
+        assert!(handshake(&server, pki.client_config(Some((&bob_cert, &bob_key)))).await.is_err(), "optional mode still checks certificates");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn user_ids_come_from_the_configured_field() {
+        let dir = temp_dir("tls-user-id");
+        let mut pki = TestPki::new(&dir, "main");
+        let sans = vec![
+            SanType::URI("https://example.test/".try_into().unwrap()),
+            SanType::URI("bitcomm://user/dave".try_into().unwrap()),
+            SanType::Rfc822Name("dave@example.test".try_into().unwrap()),
+        ];
+        let (cert_path, _, _) = pki.client("dave", "Dave", sans);
+        let cert = CertificateDer::from_pem_file(&cert_path).unwrap();
+
+        assert_eq!(client_user_id(&cert, ClientIdSource::SanUri, "bitcomm://user/").unwrap(), "dave");
+        assert_eq!(client_user_id(&cert, ClientIdSource::SanUri, "").unwrap(), "https://example.test/");
+        assert_eq!(client_user_id(&cert, ClientIdSource::SanEmail, "").unwrap(), "dave@example.test");
+        assert_eq!(client_user_id(&cert, ClientIdSource::CommonName, "").unwrap(), "Dave");
+        assert!(client_user_id(&cert, ClientIdSource::SanDns, "").is_err());
+        assert!(client_user_id(&cert, ClientIdSource::SanUri, "bitcomm://user/dave").is_err(), "empty after the prefix");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn certificates_reload_and_keep_the_last_good_one() {
+        let dir = temp_dir("tls-reload");
+        let mut pki = TestPki::new(&dir, "main");
+        let (cert_path, key_path) = pki.server("server");
+        let cert = ReloadingCert::load("imserver", &cert_path, &key_path, Some(&pki.ca_path())).unwrap();
+        let first = cert.current().cert.clone();
+        assert_eq!(first.len(), 2, "server certificate followed by the CA chain");
+
+        let (next_cert, next_key) = pki.server("next");
+        fs::copy(&next_cert, &cert_path).unwrap();
+        fs::copy(&next_key, &key_path).unwrap();
+        cert.reload();
+        let second = cert.current().cert.clone();
+        assert_ne!(second[0], first[0]);
+        assert_eq!(second[0], CertificateDer::from_pem_file(&next_cert).unwrap());
+
+        fs::write(&cert_path, "not a certificate").unwrap();
+        cert.reload();
+        assert_eq!(cert.current().cert[0], second[0]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn quic_handshakes_use_the_reloaded_certificate() {
+        let dir = temp_dir("tls-quic");
+        let mut pki = TestPki::new(&dir, "main");
+        let (cert_path, key_path) = pki.server("server");
+        let cert = load_cert(&cert_path, &key_path);
+        let endpoint = quic_endpoint(quic_server_config(cert.clone(), None, &["bitcomm-im".to_string()]).unwrap());
+
+        let chain = quic_handshake(&endpoint, pki.client_config(None)).await.unwrap();
+        assert_eq!(chain[0], CertificateDer::from_pem_file(&cert_path).unwrap());
+
+        let (next_cert, next_key) = pki.server("next");
+        fs::copy(&next_cert, &cert_path).unwrap();
+        fs::copy(&next_key, &key_path).unwrap();
+        cert.reload();
+        let chain = quic_handshake(&endpoint, pki.client_config(None)).await.unwrap();
+        assert_eq!(chain[0], CertificateDer::from_pem_file(&next_cert).unwrap());
+
+        endpoint.close(0u32.into(), b"");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn quic_handshakes_verify_client_certificates() {
+        let dir = temp_dir("tls-quic-client-auth");
+        let mut pki = TestPki::new(&dir, "main");
+        let (cert_path, key_path) = pki.server("server");
+        let (alice_cert, alice_key, _) = pki.client("alice", "user-alice", Vec::new());
+        let config = ClientAuthSection {
+            mode: ClientAuthMode::Required,
+            ca_path: Some(pki.ca_path()),
+            user_id_prefix: "user-".to_string(),
+            ..ClientAuthSection::default()
+        };
+        let client_auth = load_client_auth(&config);
+        let server_config = quic_server_config(load_cert(&cert_path, &key_path), Some(client_auth.clone()), &["bitcomm-im".to_string()]).unwrap();
+        let endpoint = quinn::Endpoint::server(server_config, (Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+
+        // 服务器一侧的握手结果和客户端证书映射出的用户 ID
+        let accept = || async {
+            let connection = endpoint.accept().await.unwrap().await.map_err(|e| e.to_string())?;
+            let chain = connection.peer_identity().and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok());
+            client_auth.user_id(&chain.map(|chain| *chain).unwrap_or_default())
+        };
+        let (accepted, _) = tokio::join!(accept(), quic_handshake(&endpoint, pki.client_config(Some((&alice_cert, &alice_key)))));
+        assert_eq!(accepted.unwrap(), Some("alice".to_string()));
+        let (accepted, _) = tokio::join!(accept(), quic_handshake(&endpoint, pki.client_config(None)));
+        assert!(accepted.is_err(), "required mode without a certificate");
+
+        endpoint.close(0u32.into(), b"");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
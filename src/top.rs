@@ -0,0 +1,370 @@
+// bitcomm top：终端实时面板
+//
+// 每秒通过控制 socket 拉取一次 status 快照，显示服务状态、IM 连接、消息速率、
+// NATS 滞后、HTTP 错误率和最近的告警；按键可以排空、切换日志级别、重启选中的服务。
+
+use crate::control::{ self, ControlRequest, ControlResponse, StatusSnapshot };
+use crate::metrics::MetricsSummary;
+use crate::supervisor::ServiceState;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{ self, Event, KeyCode, KeyEventKind, KeyModifiers };
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{ self, EnterAlternateScreen, LeaveAlternateScreen };
+use ratatui::layout::{ Constraint, Layout };
+use ratatui::style::{ Color, Modifier, Style };
+use ratatui::text::{ Line, Span };
+use ratatui::widgets::{ Block, Borders, List, ListItem, Paragraph, Row, Table, TableState };
+use ratatui::{ Frame, Terminal };
+use std::error::Error;
+use std::io::{ self, Stdout };
+use std::path::{ Path, PathBuf };
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+
+/// 刷新间隔
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+// 从面板切换到 debug / trace 后自动回退的时间
+const VERBOSE_LOG_TTL_SECS: u64 = 300;
+
+/// 运行面板直到按下 q
+pub async fn run_top(socket: &Path) -> Result<(), Box<dyn Error>> {
+    let mut terminal = TerminalGuard::enter()?;
+    let mut app = TopApp::new(socket.to_path_buf());
+
+    loop {
+        app.refresh().await;
+        terminal.0.draw(|frame| draw(frame, &mut app))?;
+
+        // 等待按键直到下一次刷新
+        let deadline = Instant::now() + REFRESH_INTERVAL;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() || !tokio::task::block_in_place(|| event::poll(timeout))? {
+                break;
+            }
+            let Event::Key(key) = tokio::task::block_in_place(event::read)? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let ctrl_c = key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c');
+            if ctrl_c || key.code == KeyCode::Char('q') || key.code == KeyCode::Esc {
+                return Ok(());
+            }
+            app.handle_key(key.code).await;
+            terminal.0.draw(|frame| draw(frame, &mut app))?;
+        }
+    }
+}
+
+// 进入原始模式和备用屏幕，析构时恢复终端 (包括出错返回时)
+struct TerminalGuard(Terminal<CrosstermBackend<Stdout>>);
+
+impl TerminalGuard {
+    fn enter() -> io::Result<TerminalGuard> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        terminal.clear()?;
+        Ok(TerminalGuard(terminal))
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        let _ = self.0.show_cursor();
+    }
+}
+
+/// 两次采样之间的速率，单位 /s
+#[derive(Debug, Clone, Copy, Default)]
+struct Rates {
+    im_bytes_in: f64,
+    im_bytes_out: f64,
+    mq_published: f64,
+    mq_consumed: f64,
+    http_requests: f64,
+    http_errors: f64,
+}
+
+impl Rates {
+    fn between(previous: &MetricsSummary, current: &MetricsSummary, elapsed: Duration) -> Rates {
+        let secs = elapsed.as_secs_f64().max(0.001);
+        // 计数器在服务重启后可能变小，此时按 0 计
+        let rate = |before: f64, after: f64| (after - before).max(0.0) / secs;
+        Rates {
+            im_bytes_in: rate(previous.im_bytes_in, current.im_bytes_in),
+            im_bytes_out: rate(previous.im_bytes_out, current.im_bytes_out),
+            mq_published: rate(previous.mq_published, current.mq_published),
+            mq_consumed: rate(previous.mq_consumed, current.mq_consumed),
+            http_requests: rate(previous.http_requests, current.http_requests),
+            http_errors: rate(previous.http_errors, current.http_errors),
+        }
+    }
+}
+
+struct TopApp {
+    socket: PathBuf,
+    snapshot: Option<StatusSnapshot>,
+    sampled_at: Option<Instant>,
+    rates: Rates,
+    services: TableState,
+    // 首次连接时的日志规则，l 键在它与 debug / trace 之间循环
+    base_filter: Option<String>,
+    log_level: usize,
+    // 等待按 y 确认的操作
+    pending: Option<ControlRequest>,
+    message: Option<String>,
+}
+
+impl TopApp {
+    fn new(socket: PathBuf) -> TopApp {
+        TopApp {
+            socket,
+            snapshot: None,
+            sampled_at: None,
+            rates: Rates::default(),
+            services: TableState::default().with_selected(Some(0)),
+            base_filter: None,
+            log_level: 0,
+            pending: None,
+            message: None,
+        }
+    }
+
+    async fn refresh(&mut self) {
+        let snapshot = match self.request(&ControlRequest::Status).await {
+            Ok(data) => serde_json::from_value::<StatusSnapshot>(data).map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        match snapshot {
+            Ok(snapshot) => {
+                let now = Instant::now();
+                if let (Some(previous), Some(sampled_at)) = (&self.snapshot, self.sampled_at) {
+                    self.rates = Rates::between(&previous.metrics, &snapshot.metrics, now - sampled_at);
+                }
+                if self.base_filter.is_none() {
+                    self.base_filter = snapshot.log_filter.clone();
+                }
+                self.snapshot = Some(snapshot);
+                self.sampled_at = Some(now);
+            }
+            Err(e) => self.message = Some(format!("{}: {}", self.socket.display(), e)),
+        }
+    }
+
+    async fn request(&self, request: &ControlRequest) -> Result<serde_json::Value, String> {
+        let response: ControlResponse = control::send_request(&self.socket, request).await.map_err(|e| e.to_string())?;
+        if response.ok {
+            Ok(response.data)
+        } else {
+            Err(response.error.unwrap_or_else(|| "request failed".to_string()))
+        }
+    }
+
+    fn selected_service(&self) -> Option<String> {
+        let snapshot = self.snapshot.as_ref()?;
+        let index = self.services.selected()?;
+        snapshot.services.get(index).map(|status| status.service.clone())
+    }
+
+    async fn handle_key(&mut self, code: KeyCode) {
+        // 有待确认的操作时，y 执行，其他键取消
+        if let Some(request) = self.pending.take() {
+            self.message = Some(if code == KeyCode::Char('y') {
+                match self.request(&request).await {
+                    Ok(_) => format!("{} done", describe(&request)),
+                    Err(e) => format!("{} failed: {}", describe(&request), e),
+                }
+            } else {
+                "cancelled".to_string()
+            });
+            return;
+        }
+
+        let count = self.snapshot.as_ref().map(|snapshot| snapshot.services.len()).unwrap_or(0);
+        match code {
+            KeyCode::Up | KeyCode::Char('k') => {
+                let index = self.services.selected().unwrap_or(0);
+                self.services.select(Some(index.saturating_sub(1)));
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                let index = self.services.selected().unwrap_or(0);
+                self.services.select(Some((index + 1).min(count.saturating_sub(1))));
+            }
+            KeyCode::Char('d') => {
+                self.confirm(ControlRequest::Drain);
+            }
+            KeyCode::Char('r') => {
+                if let Some(service) = self.selected_service() {
+                    self.confirm(ControlRequest::RestartService { service });
+                }
+            }
+            KeyCode::Char('l') => self.cycle_log_level().await,
+            _ => {}
+        }
+    }
+
+    fn confirm(&mut self, request: ControlRequest) {
+        self.message = Some(format!("{}? press y to confirm", describe(&request)));
+        self.pending = Some(request);
+    }
+
+    async fn cycle_log_level(&mut self) {
+        let Some(base) = self.base_filter.clone() else {
+            return;
+        };
+        let levels = [base.as_str(), "debug", "trace"];
+        self.log_level = (self.log_level + 1) % levels.len();
+        let filter = levels[self.log_level].to_string();
+        // 切回初始规则时不设 ttl，其余级别到期自动回退
+        let ttl_secs = if self.log_level == 0 { None } else { Some(VERBOSE_LOG_TTL_SECS) };
+        let request = ControlRequest::SetLogLevel { filter: filter.clone(), ttl_secs };
+        self.message = Some(match self.request(&request).await {
+            Ok(_) => {
+                match ttl_secs {
+                    Some(ttl) => format!("log filter set to {} for {}s", filter, ttl),
+                    None => format!("log filter restored to {}", filter),
+                }
+            }
+            Err(e) => format!("set log filter failed: {}", e),
+        });
+    }
+}
+
+fn describe(request: &ControlRequest) -> String {
+    match request {
+        ControlRequest::Drain => "drain".to_string(),
+        ControlRequest::RestartService { service } => format!("restart {}", service),
+        other => format!("{:?}", other),
+    }
+}
+
+fn draw(frame: &mut Frame, app: &mut TopApp) {
+    let service_rows = app.snapshot.as_ref().map(|snapshot| snapshot.services.len()).unwrap_or(0) as u16;
+    let [header, services, metrics, warnings, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(service_rows + 3),
+        Constraint::Length(5),
+        Constraint::Min(3),
+        Constraint::Length(1),
+    ]).areas(frame.area());
+
+    frame.render_widget(header_line(app), header);
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let rows: Vec<Row> = app.snapshot
+        .iter()
+        .flat_map(|snapshot| snapshot.services.iter())
+        .map(|status| {
+            let color = match status.state {
+                ServiceState::Running => Color::Green,
+                ServiceState::Stopped => Color::Yellow,
+                ServiceState::Failed => Color::Red,
+            };
+            Row::new(vec![
+                Span::raw(status.service.clone()),
+                Span::styled(format!("{:?}", status.state).to_lowercase(), Style::default().fg(color)),
+                Span::raw(human_duration(now.saturating_sub(status.since))),
+                Span::raw(status.heartbeat_age_secs.map(|age| format!("{:.1}s ago", age)).unwrap_or_else(|| "-".to_string())),
+            ])
+        })
+        .collect();
+    let table = Table::new(rows, [Constraint::Length(12), Constraint::Length(9), Constraint::Length(10), Constraint::Min(10)])
+        .header(Row::new(vec!["SERVICE", "STATE", "FOR", "HEARTBEAT"]).style(Style::default().add_modifier(Modifier::BOLD)))
+        .block(Block::default().borders(Borders::ALL).title(" services "))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(table, services, &mut app.services);
+
+    frame.render_widget(metrics_panel(app), metrics);
+
+    let items: Vec<ListItem> = app.snapshot
+        .iter()
+        .flat_map(|snapshot| snapshot.warnings.iter().rev())
+        .map(|log| {
+            let color = if log.level == "ERROR" { Color::Red } else { Color::Yellow };
+            // 只显示 RFC 3339 时间中的时分秒
+            let time = log.timestamp.get(11..19).unwrap_or(&log.timestamp);
+            ListItem::new(Line::from(vec![
+                Span::raw(format!("{} ", time)),
+                Span::styled(format!("{:<5} ", log.level), Style::default().fg(color)),
+                Span::styled(format!("{} ", log.target), Style::default().fg(Color::DarkGray)),
+                Span::raw(log.message.clone()),
+            ]))
+        })
+        .collect();
+    frame.render_widget(List::new(items).block(Block::default().borders(Borders::ALL).title(" recent warnings ")), warnings);
+
+    let help = "q quit  ↑/↓ select  r restart service  d drain  l cycle log level";
+    frame.render_widget(Paragraph::new(app.message.clone().unwrap_or_else(|| help.to_string())), footer);
+}
+
+fn header_line(app: &TopApp) -> Paragraph<'static> {
+    let Some(snapshot) = &app.snapshot else {
+        return Paragraph::new("bitcomm top  connecting...");
+    };
+    let mut spans = vec![
+        Span::styled(format!("bitcomm {}", snapshot.version), Style::default().add_modifier(Modifier::BOLD)),
+        Span::raw(format!("  pid {}  log {}", snapshot.pid, snapshot.log_filter.clone().unwrap_or_default())),
+    ];
+    if snapshot.draining {
+        spans.push(Span::styled("  DRAINING", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)));
+    }
+    if snapshot.shedding {
+        spans.push(Span::styled("  SHEDDING", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)));
+    }
+    Paragraph::new(Line::from(spans))
+}
+
+fn metrics_panel(app: &TopApp) -> Paragraph<'static> {
+    let metrics = app.snapshot.as_ref().map(|snapshot| snapshot.metrics.clone()).unwrap_or_default();
+    let rates = app.rates;
+    let error_ratio = if rates.http_requests > 0.0 { rates.http_errors / rates.http_requests * 100.0 } else { 0.0 };
+    // 依赖库尚未上报的指标不显示为 0
+    let im = if metrics.im_reported {
+        format!(
+            "IM    connections {:<6.0} streams {:<6.0} in {}/s  out {}/s",
+            metrics.im_connections,
+            metrics.im_open_streams,
+            human_bytes(rates.im_bytes_in),
+            human_bytes(rates.im_bytes_out)
+        )
+    } else {
+        "IM    not reported".to_string()
+    };
+    let mq = if metrics.mq_reported {
+        format!("NATS  published {:.1}/s  consumed {:.1}/s  lag {:.0}", rates.mq_published, rates.mq_consumed, metrics.mq_lag)
+    } else {
+        "NATS  not reported".to_string()
+    };
+    let lines = vec![
+        Line::from(im),
+        Line::from(mq),
+        Line::from(
+            format!("HTTP  requests {:.1}/s  5xx {:.1}/s ({:.1}%)", rates.http_requests, rates.http_errors, error_ratio)
+        )
+    ];
+    Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" traffic "))
+}
+
+fn human_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+fn human_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
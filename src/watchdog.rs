@@ -0,0 +1,271 @@
+// 服务看门狗
+//
+// 每隔 [wdserver] time 秒巡检一次 supervisor 中登记为 running 的服务：
+// - 心跳：ServiceGuard 在独立的任务中上报，心跳过期说明服务的登记或心跳任务已失效；
+// - 进度：服务用 supervisor::start_work 登记进行中的工作，完成时计入 bitcomm_service_progress_total。
+//   imserver / mqserver 的主体在 btcmnetwork 中，不经过 start_work，由它直接增减 bitcomm_service_in_flight
+//   并累加进度计数 (bitcomm 转接的连接和 MQ span 已代为上报)。服务有进行中的工作、进度计数在 progress_timeout 内
+//   没有增长即视为卡住 (活着但没有进展)；没有进行中的工作时视为空闲，不做判断。
+// 判定卡住后按配置的动作处理：输出告警、原地重启该服务或重新执行整个进程。
+// 运行时整体卡顿时所有服务的心跳都会过期，这种情况由卡顿检测告警，不归咎于单个服务。
+// 同一周期还执行 [[wdserver.checks]] 资源检查，见 resources.rs。
+
+use crate::alerting::{ self, AlertSeverity };
+use crate::config::{ BitcommConfig, WatchdogAction, WdServerSection };
+use crate::journal::{ self, EventKind };
+use crate::resources::ResourceMonitor;
+use crate::supervisor::{ self, ServiceState, ServiceStatus };
+use crate::{ metrics, stall, telemetry };
+use std::collections::{ BTreeMap, BTreeSet };
+use std::os::unix::process::CommandExt;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+use tracing::{ debug, error, info, warn };
+
+// 看门狗自身所在的服务，不巡检自己
+const SELF_SERVICE: &str = "wdserver";
+
+// 最近一轮巡检判定卡住的服务个数，systemd 看门狗据此决定是否继续喂狗
+static STUCK_SERVICES: AtomicUsize = AtomicUsize::new(0);
+
+struct Watchdog {
+    config: WdServerSection,
+    // 服务 -> (上次看到的进度计数, 计数最近一次变化的时间)
+    progress: BTreeMap<String, (f64, Instant)>,
+    // 已判定卡住、尚未恢复的服务，避免每轮重复执行动作
+    stuck: BTreeSet<String>,
+    resources: ResourceMonitor,
+}
+
+/// 按 [wdserver] 配置周期巡检各服务和资源，不会返回
+pub async fn run_watchdog(config: Arc<BitcommConfig>) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.wdserver.time));
+    let mut watchdog = Watchdog {
+        config: config.wdserver.clone(),
+        progress: BTreeMap::new(),
+        stuck: BTreeSet::new(),
+        resources: ResourceMonitor::new(&config),
+    };
+    loop {
+        ticker.tick().await;
+        watchdog.check_services();
+        watchdog.resources.check().await;
+    }
+}
+
+/// 最近一轮巡检判定卡住、尚未恢复的服务个数
+pub fn stuck_services() -> usize {
+    STUCK_SERVICES.load(Ordering::Relaxed)
+}
+
+impl Watchdog {
+    fn check_services(&mut self) {
+        if stall::status().stalled {
+            debug!("tokio runtime stalled, skipping watchdog round");
+            return;
+        }
+        let progress = metrics::service_progress();
+        let reported = metrics::service_in_flight();
+        for status in supervisor::services() {
+            if status.service == SELF_SERVICE || status.state != ServiceState::Running {
+                alerting::resolve(stuck_key(&status.service), format!("service {:?}", status.state).to_lowercase());
+                self.stuck.remove(&status.service);
+                self.progress.remove(&status.service);
+                continue;
+            }
+            let current = progress.get(&status.service).copied().unwrap_or(0.0);
+            let in_flight = status.in_flight + reported.get(&status.service).copied().unwrap_or(0.0).max(0.0) as usize;
+            match self.stuck_reason(&status, current, in_flight) {
+                Some(reason) => {
+                    if self.stuck.insert(status.service.clone()) {
+                        self.act(&status.service, &reason);
+                    }
+                }
+                None => {
+                    alerting::resolve(stuck_key(&status.service), "service making progress again");
+                    if self.stuck.remove(&status.service) {
+                        info!(service = %status.service, "service recovered");
+                    }
+                }
+            }
+        }
+        STUCK_SERVICES.store(self.stuck.len(), Ordering::Relaxed);
+    }
+
+    // 服务卡住时返回原因；in_flight 为 start_work 登记的与依赖库上报的工作数之和
+    fn stuck_reason(&mut self, status: &ServiceStatus, current: f64, in_flight: usize) -> Option<String> {
+        let rule = self.config.services.get(&status.service);
+        let heartbeat_timeout = rule.and_then(|rule| rule.heartbeat_timeout).unwrap_or(self.config.heartbeat_timeout);
+        let progress_timeout = rule.and_then(|rule| rule.progress_timeout).unwrap_or(self.config.progress_timeout);
+
+        if let Some(age) = status.heartbeat_age_secs {
+            if age > (heartbeat_timeout as f64) {
+                return Some(format!("no heartbeat for {:.0}s", age));
+            }
+        }
+
+        // 空闲时一直重新计时，有进行中的工作后从最近一次进度变化算起
+        let now = Instant::now();
+        let (seen, changed) = self.progress.entry(status.service.clone()).or_insert((current, now));
+        if *seen != current || in_flight == 0 {
+            *seen = current;
+            *changed = now;
+        }
+        let stalled = changed.elapsed();
+        if progress_timeout > 0 && stalled > Duration::from_secs(progress_timeout) {
+            return Some(format!("no progress for {}s with {} units of work in flight (progress counter {})", stalled.as_secs(), in_flight, current));
+        }
+        None
+    }
+
+    fn act(&mut self, service: &str, reason: &str) {
+        let action = self.config.services
+            .get(service)
+            .and_then(|rule| rule.action)
+            .unwrap_or(self.config.action);
+        let action_name = match action {
+            WatchdogAction::Log => "log",
+            WatchdogAction::RestartService => "restart-service",
+            WatchdogAction::RestartProcess => "restart-process",
+        };
+        metrics::record_watchdog_trigger(service, action_name);
+        journal::record(EventKind::Stuck, Some(service), format!("{}, action {}", reason, action_name));
+        warn!(service, action = action_name, "service appears stuck: {}", reason);
+        alerting::fire(
+            stuck_key(service),
+            AlertSeverity::Critical,
+            Some(service),
+            format!("service {} is stuck", service),
+            format!("{}, action {}", reason, action_name)
+        );
+
+        match action {
+            WatchdogAction::Log => {}
+            WatchdogAction::RestartService => {
+                match supervisor::restart_service(service) {
+                    // 重启后重新计时，仍然卡住时下一轮再次处理
+                    Ok(()) => {
+                        self.stuck.remove(service);
+                        self.progress.remove(service);
+                    }
+                    Err(e) => warn!(service, "watchdog could not restart service: {}", e),
+                }
+            }
+            WatchdogAction::RestartProcess => restart_process(),
+        }
+    }
+}
+
+fn stuck_key(service: &str) -> String {
+    format!("service-stuck:{}", service)
+}
+
+/// 以相同的参数重新执行当前程序；监听的 socket 都带 CLOEXEC，exec 后由新进程重新绑定。
+/// 只在 exec 失败时返回
+pub fn restart_process() {
+    let program = match std::env::current_exe() {
+        Ok(program) => program,
+        Err(e) => {
+            error!("watchdog could not locate the current executable: {}", e);
+            return;
+        }
+    };
+    warn!(program = %program.display(), "watchdog restarting the process");
+    journal::record(EventKind::Restarted, None, "process restart requested by watchdog");
+    // 刷新文件日志，exec 不会执行析构
+    telemetry::shutdown_tracing();
+    let e = std::process::Command::new(&program).args(std::env::args_os().skip(1)).exec();
+    error!("watchdog failed to exec {}: {}", program.display(), e);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supervisor::ServiceGuard;
+    use ::metrics::{ counter, gauge };
+
+    fn watchdog(progress_timeout: u64) -> Watchdog {
+        let mut config = BitcommConfig::default();
+        config.wdserver.progress_timeout = progress_timeout;
+        Watchdog {
+            config: config.wdserver.clone(),
+            progress: BTreeMap::new(),
+            stuck: BTreeSet::new(),
+            resources: ResourceMonitor::new(&config),
+        }
+    }
+
+    #[tokio::test]
+    async fn stalled_work_is_stuck_until_it_completes() {
+        let service = "wdtest-stalled";
+        let up = ServiceGuard::new(service);
+        let mut watchdog = watchdog(1);
+
+        // 空闲的服务没有进度也不算卡住
+        watchdog.check_services();
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        watchdog.check_services();
+        assert!(!watchdog.stuck.contains(service));
+        assert!(supervisor::service_status(service).unwrap().heartbeat_age_secs.is_some(), "heartbeat task is running");
+
+        // 工作没有完成，进度超过 progress_timeout 没有变化
+        let work = supervisor::start_work(service);
+        assert_eq!(supervisor::service_status(service).unwrap().in_flight, 1);
+        watchdog.check_services();
+        assert!(!watchdog.stuck.contains(service));
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        watchdog.check_services();
+        assert!(watchdog.stuck.contains(service));
+
+        // 工作完成后恢复
+        drop(work);
+        assert_eq!(supervisor::service_status(service).unwrap().in_flight, 0);
+        watchdog.check_services();
+        assert!(!watchdog.stuck.contains(service));
+
+        // 服务停止后不再巡检
+        drop(up);
+        let _work = supervisor::start_work(service);
+        watchdog.check_services();
+        assert!(!watchdog.progress.contains_key(service));
+    }
+
+    #[tokio::test]
+    async fn stale_heartbeats_are_stuck() {
+        let service = "wdtest-heartbeat";
+        let _up = ServiceGuard::new(service);
+        let mut watchdog = watchdog(0);
+        watchdog.config.heartbeat_timeout = 1;
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        watchdog.check_services();
+        assert!(!watchdog.stuck.contains(service));
+
+        // 心跳任务仍在运行，但 HEARTBEAT_INTERVAL 大于超时
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        watchdog.check_services();
+        assert!(watchdog.stuck.contains(service));
+    }
+
+    #[tokio::test]
+    async fn work_reported_by_libraries_counts_as_in_flight() {
+        metrics::init_metrics().unwrap();
+        let service = "wdtest-library";
+        let _up = ServiceGuard::new(service);
+        let mut watchdog = watchdog(1);
+
+        // 依赖库登记了进行中的工作但没有进度
+        gauge!(metrics::SERVICE_IN_FLIGHT, "service" => service).increment(1.0);
+        watchdog.check_services();
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        watchdog.check_services();
+        assert!(watchdog.stuck.contains(service));
+
+        // 累加进度后恢复
+        counter!(metrics::SERVICE_PROGRESS, "service" => service).increment(1);
+        watchdog.check_services();
+        assert!(!watchdog.stuck.contains(service));
+        gauge!(metrics::SERVICE_IN_FLIGHT, "service" => service).decrement(1.0);
+    }
+}